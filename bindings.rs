@@ -557,6 +557,7 @@ pub const _USE_FORTIFY_LEVEL: u32 = 2;
 pub const VERSION: &[u8; 14] = b"starcode-v1.4\0";
 pub const DATE: &[u8; 11] = b"2021-09-22\0";
 pub const STARCODE_MAX_TAU: u32 = 8;
+pub const STARCODE_MAX_THREADS: u32 = 15446;
 pub const EPERM: u32 = 1;
 pub const ENOENT: u32 = 2;
 pub const ESRCH: u32 = 3;
@@ -2390,19 +2391,299 @@ extern "C" {
 }
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-pub struct useq_t {
+pub struct match_t {
     _unused: [u8; 0],
 }
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-pub struct match_t {
+pub struct starcode_params_t {
     _unused: [u8; 0],
 }
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-pub struct starcode_params_t {
+pub struct starcode_context_t {
     _unused: [u8; 0],
 }
+pub type ssize_t = __darwin_ssize_t;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct useq_t {
+    pub count: ssize_t,
+    pub nids: ::std::os::raw::c_uint,
+    pub sphere_c: ssize_t,
+    pub sphere_d: ssize_t,
+    pub seq: *mut ::std::os::raw::c_char,
+    pub info: *mut ::std::os::raw::c_char,
+    pub matches: *mut *mut gstack_t,
+    pub canonical: *mut useq_t,
+    pub seqid: *mut ::std::os::raw::c_int,
+}
+#[test]
+fn bindgen_test_layout_useq_t() {
+    const UNINIT: ::std::mem::MaybeUninit<useq_t> = ::std::mem::MaybeUninit::uninit();
+    let ptr = UNINIT.as_ptr();
+    assert_eq!(
+        ::std::mem::size_of::<useq_t>(),
+        72usize,
+        concat!("Size of: ", stringify!(useq_t))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<useq_t>(),
+        8usize,
+        concat!("Alignment of ", stringify!(useq_t))
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).count) as usize - ptr as usize },
+        0usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(count)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).nids) as usize - ptr as usize },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(nids)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).sphere_c) as usize - ptr as usize },
+        16usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(sphere_c)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).sphere_d) as usize - ptr as usize },
+        24usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(sphere_d)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).seq) as usize - ptr as usize },
+        32usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(seq)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).info) as usize - ptr as usize },
+        40usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(info)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).matches) as usize - ptr as usize },
+        48usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(matches)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).canonical) as usize - ptr as usize },
+        56usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(canonical)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).seqid) as usize - ptr as usize },
+        64usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(useq_t),
+            "::",
+            stringify!(seqid)
+        )
+    );
+}
+pub const format_t_FASTA: format_t = 0;
+pub const format_t_FASTQ: format_t = 1;
+pub const format_t_RAW: format_t = 2;
+pub const format_t_PE_FASTQ: format_t = 3;
+pub const format_t_UNSET: format_t = 4;
+pub type format_t = ::std::os::raw::c_uint;
+pub const stage_t_STAGE_READING: stage_t = 0;
+pub const stage_t_STAGE_SORTING: stage_t = 1;
+pub const stage_t_STAGE_PADDING: stage_t = 2;
+pub const stage_t_STAGE_QUERYING: stage_t = 3;
+pub const stage_t_STAGE_CLUSTERING: stage_t = 4;
+pub const stage_t_STAGE_OUTPUT: stage_t = 5;
+pub type stage_t = ::std::os::raw::c_uint;
+pub const progress_t_PROGRESS_STARTED: progress_t = 0;
+pub const progress_t_PROGRESS_FINISHED: progress_t = 1;
+pub const progress_t_PROGRESS_UNIQUE: progress_t = 2;
+pub const progress_t_PROGRESS_JOBS: progress_t = 3;
+pub type progress_t = ::std::os::raw::c_uint;
+pub const log_level_t_LOG_ERROR: log_level_t = 0;
+pub const log_level_t_LOG_WARNING: log_level_t = 1;
+pub const log_level_t_LOG_INFO: log_level_t = 2;
+pub const log_level_t_LOG_DEBUG: log_level_t = 3;
+pub type log_level_t = ::std::os::raw::c_uint;
+#[repr(C)]
+#[derive(Debug, Copy, Clone)]
+pub struct starcode_hooks_t {
+    pub data: *mut ::std::os::raw::c_void,
+    pub member: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            canonical: *const useq_t,
+            member: *const useq_t,
+        ),
+    >,
+    pub format: ::std::option::Option<
+        unsafe extern "C" fn(data: *mut ::std::os::raw::c_void, format: format_t),
+    >,
+    pub cancelled: ::std::option::Option<
+        unsafe extern "C" fn(data: *mut ::std::os::raw::c_void) -> ::std::os::raw::c_int,
+    >,
+    pub progress: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            event: progress_t,
+            stage: stage_t,
+            count: ::std::os::raw::c_long,
+            total: ::std::os::raw::c_long,
+        ),
+    >,
+    pub log: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            level: log_level_t,
+            message: *const ::std::os::raw::c_char,
+        ),
+    >,
+    pub edge: ::std::option::Option<
+        unsafe extern "C" fn(
+            data: *mut ::std::os::raw::c_void,
+            query: *const useq_t,
+            match_: *const useq_t,
+            dist: ::std::os::raw::c_int,
+        ),
+    >,
+    pub context: *mut starcode_context_t,
+}
+#[test]
+fn bindgen_test_layout_starcode_hooks_t() {
+    const UNINIT: ::std::mem::MaybeUninit<starcode_hooks_t> = ::std::mem::MaybeUninit::uninit();
+    let ptr = UNINIT.as_ptr();
+    assert_eq!(
+        ::std::mem::size_of::<starcode_hooks_t>(),
+        64usize,
+        concat!("Size of: ", stringify!(starcode_hooks_t))
+    );
+    assert_eq!(
+        ::std::mem::align_of::<starcode_hooks_t>(),
+        8usize,
+        concat!("Alignment of ", stringify!(starcode_hooks_t))
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).data) as usize - ptr as usize },
+        0usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(data)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).member) as usize - ptr as usize },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(member)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).format) as usize - ptr as usize },
+        16usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(format)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).cancelled) as usize - ptr as usize },
+        24usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(cancelled)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).progress) as usize - ptr as usize },
+        32usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(progress)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).log) as usize - ptr as usize },
+        40usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(log)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).edge) as usize - ptr as usize },
+        48usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(edge)
+        )
+    );
+    assert_eq!(
+        unsafe { ::std::ptr::addr_of!((*ptr).context) as usize - ptr as usize },
+        56usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(starcode_hooks_t),
+            "::",
+            stringify!(context)
+        )
+    );
+}
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
 pub struct lookup_t {
@@ -2417,6 +2698,16 @@ pub const cluster_t_MP_CLUSTER: cluster_t = 0;
 pub const cluster_t_SPHERES_CLUSTER: cluster_t = 1;
 pub const cluster_t_COMPONENTS_CLUSTER: cluster_t = 2;
 pub type cluster_t = ::std::os::raw::c_uint;
+pub const starcode_status_t_STARCODE_OK: starcode_status_t = 0;
+pub const starcode_status_t_STARCODE_EMPTY_INPUT: starcode_status_t = 1;
+pub const starcode_status_t_STARCODE_IO_ERROR: starcode_status_t = 2;
+pub const starcode_status_t_STARCODE_ALLOC_ERROR: starcode_status_t = 3;
+pub const starcode_status_t_STARCODE_THREAD_ERROR: starcode_status_t = 4;
+pub const starcode_status_t_STARCODE_INVALID_INPUT: starcode_status_t = 5;
+pub const starcode_status_t_STARCODE_SEQ_TOO_LONG: starcode_status_t = 6;
+pub const starcode_status_t_STARCODE_INTERNAL_ERROR: starcode_status_t = 7;
+pub const starcode_status_t_STARCODE_CANCELLED: starcode_status_t = 8;
+pub type starcode_status_t = ::std::os::raw::c_uint;
 extern "C" {
     pub fn starcode_helper(
         input: *mut ::std::os::raw::c_char,
@@ -2447,6 +2738,89 @@ extern "C" {
         outputt: ::std::os::raw::c_int,
     ) -> ::std::os::raw::c_int;
 }
+extern "C" {
+    pub fn starcode_files(
+        inputf1: *mut FILE,
+        inputf2: *mut FILE,
+        outputf1: *mut FILE,
+        outputf2: *mut FILE,
+        hooks: *mut starcode_hooks_t,
+        tau: ::std::os::raw::c_int,
+        verbose: ::std::os::raw::c_int,
+        thrmax: ::std::os::raw::c_int,
+        clusteralg: ::std::os::raw::c_int,
+        parent_to_child: f64,
+        showclusters: ::std::os::raw::c_int,
+        showids: ::std::os::raw::c_int,
+        outputt: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn starcode_seqs(
+        seqs: *mut *mut ::std::os::raw::c_char,
+        counts: *const ::std::os::raw::c_long,
+        nseq: usize,
+        outputf1: *mut FILE,
+        hooks: *mut starcode_hooks_t,
+        tau: ::std::os::raw::c_int,
+        verbose: ::std::os::raw::c_int,
+        thrmax: ::std::os::raw::c_int,
+        clusteralg: ::std::os::raw::c_int,
+        parent_to_child: f64,
+        showclusters: ::std::os::raw::c_int,
+        showids: ::std::os::raw::c_int,
+        outputt: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn starcode_pe_seqs(
+        seqs1: *mut *mut ::std::os::raw::c_char,
+        seqs2: *mut *mut ::std::os::raw::c_char,
+        counts: *const ::std::os::raw::c_long,
+        nseq: usize,
+        outputf1: *mut FILE,
+        hooks: *mut starcode_hooks_t,
+        tau: ::std::os::raw::c_int,
+        verbose: ::std::os::raw::c_int,
+        thrmax: ::std::os::raw::c_int,
+        clusteralg: ::std::os::raw::c_int,
+        parent_to_child: f64,
+        showclusters: ::std::os::raw::c_int,
+        showids: ::std::os::raw::c_int,
+        outputt: ::std::os::raw::c_int,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn starcode_context_new() -> *mut starcode_context_t;
+}
+extern "C" {
+    pub fn starcode_context_clear(context: *mut starcode_context_t);
+}
+extern "C" {
+    pub fn starcode_context_destroy(context: *mut starcode_context_t);
+}
+extern "C" {
+    pub fn starcode_new_lookup(
+        slen: ::std::os::raw::c_int,
+        maxlen: ::std::os::raw::c_int,
+        tau: ::std::os::raw::c_int,
+    ) -> *mut lookup_t;
+}
+extern "C" {
+    pub fn starcode_lookup_insert(
+        lut: *mut lookup_t,
+        seq: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn starcode_lookup_search(
+        lut: *const lookup_t,
+        seq: *const ::std::os::raw::c_char,
+    ) -> ::std::os::raw::c_int;
+}
+extern "C" {
+    pub fn starcode_destroy_lookup(lut: *mut lookup_t);
+}
 pub type errno_t = ::std::os::raw::c_int;
 extern "C" {
     pub fn __error() -> *mut ::std::os::raw::c_int;
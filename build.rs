@@ -9,11 +9,14 @@ use bindgen::builder;
 
 fn main() {
 
-    Command::new("make")
-        .arg("clean")
-        .arg("libstarcode.a")
-        .status()
-        .expect("failed to make!");
+    // The fault injection of the test suite is only compiled in with the
+    // 'fault-injection' feature
+    let mut make = Command::new("make");
+    make.arg("clean").arg("libstarcode.a");
+    if env::var_os("CARGO_FEATURE_FAULT_INJECTION").is_some() {
+        make.arg("CPPFLAGS=-DSTARCODE_FAULT_INJECTION");
+    }
+    make.status().expect("failed to make!");
 
     println!("cargo:rustc-link-lib=starcode");
     //println!("cargo:rustc-link-search={}", out_path.display());
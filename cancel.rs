@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::StarcodeParams;
+
+/// Stops the runs whose parameters hold a clone of it, see
+/// `StarcodeParamsBuilder::cancellation_token`. Cancelled runs fail with
+/// `StarcodeError::Cancelled`.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancels the runs in progress and those started later with this token
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+// Clones of a token are equal, separate tokens are not
+impl PartialEq for CancellationToken {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.cancelled, &other.cancelled)
+    }
+}
+
+// What stops a run: the token of its parameters or the end of its time limit,
+// which starts when the run does
+pub(crate) struct Cancellation {
+    token: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl Cancellation {
+    pub(crate) fn start(params: &StarcodeParams) -> Self {
+        Cancellation {
+            token: params.cancellation_token.clone(),
+            // A time limit too far in the future is no limit
+            deadline: params.time_limit.and_then(|limit| Instant::now().checked_add(limit)),
+        }
+    }
+
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.token.as_ref().is_some_and(CancellationToken::is_cancelled)
+            || self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+}
@@ -0,0 +1,277 @@
+use rustc_hash::FxHashMap;
+
+use crate::{CsrAdjacency, ExportedCluster, ExportedMember, MatchGraph, StarcodeError};
+
+/// Groups the unique sequences of a match graph into clusters. Implementations return the
+/// canonical of every sequence of `graph`, by position in `MatchGraph::sequences`, and every
+/// canonical is its own canonical. `adjacency` is `graph.to_csr()`.
+pub trait ClusteringAlgorithm {
+    fn cluster(&self, graph: &MatchGraph, adjacency: &CsrAdjacency) -> Result<Vec<usize>, StarcodeError>;
+}
+
+/// The message passing of starcode: the counts of each sequence go to its nearest neighbors
+/// with at least `cluster_ratio` times more counts, up to the sequences without any. With the
+/// edges of one thread, the clusters are those of starcode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MessagePassing {
+    pub cluster_ratio: f64,
+}
+
+impl Default for MessagePassing {
+    fn default() -> Self {
+        MessagePassing { cluster_ratio: 5.0 }
+    }
+}
+
+/// The sphere clustering of starcode: from the most abundant sequence down, every sequence
+/// not yet claimed claims its neighbors, or takes them from a sphere they are further from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Spheres;
+
+/// The connected components of the graph, the canonical of each is its sequence with the
+/// most counts, then the most neighbors
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ConnectedComponents;
+
+impl ClusteringAlgorithm for MessagePassing {
+    fn cluster(&self, graph: &MatchGraph, _adjacency: &CsrAdjacency) -> Result<Vec<usize>, StarcodeError> {
+        // The nearest parents of every sequence, as 'do_query()' links them
+        let mut parents: Vec<(usize, Vec<usize>)> = vec![(usize::MAX, Vec::new()); graph.len()];
+        for edge in &graph.edges {
+            let (mut parent, mut child) = match edge.target_count > edge.query_count {
+                true => (edge.target, edge.query),
+                false => (edge.query, edge.target),
+            };
+            let (mincount, maxcount) = (graph.counts[child], graph.counts[parent]);
+            if (maxcount as f64) < self.cluster_ratio * mincount as f64 {
+                continue;
+            }
+            // For equal counts the parent is the first in sorted order, so that no sequence is
+            // its own ancestor
+            if maxcount == mincount && parent > child {
+                std::mem::swap(&mut parent, &mut child);
+            }
+            let (distance, nearest) = &mut parents[child];
+            if edge.distance < *distance {
+                *distance = edge.distance;
+                nearest.clear();
+            }
+            if edge.distance == *distance {
+                nearest.push(parent);
+            }
+        }
+
+        let mut state = Passing {
+            parents: parents.into_iter().map(|(_, nearest)| nearest).collect(),
+            count: graph.counts.clone(),
+            canonical: vec![None; graph.len()],
+            sphere_c: vec![0; graph.len()],
+            ambiguous: vec![false; graph.len()],
+        };
+        for u in 0..graph.len() {
+            state.transfer_counts_and_update_canonicals(u);
+        }
+        for u in 0..graph.len() {
+            state.resolve_ambiguous(u)?;
+        }
+        state.canonical.into_iter().map(|canonical| canonical.ok_or(StarcodeError::Internal)).collect()
+    }
+}
+
+// The fields of 'useq_t' that message passing updates
+struct Passing {
+    parents: Vec<Vec<usize>>,
+    count: Vec<usize>,
+    canonical: Vec<Option<usize>>,
+    sphere_c: Vec<usize>,
+    ambiguous: Vec<bool>,
+}
+
+impl Passing {
+    fn absorb(&mut self, u: usize, canonical: usize) {
+        self.canonical[u] = Some(canonical);
+        self.count[canonical] += self.count[u];
+        self.count[u] = 0;
+        self.sphere_c[canonical] += 1;
+    }
+
+    fn transfer_counts_and_update_canonicals(&mut self, u: usize) {
+        if self.ambiguous[u] {
+            return;
+        }
+        // No parent: the sequence is its own canonical
+        if self.parents[u].is_empty() {
+            self.canonical[u] = Some(u);
+            return;
+        }
+        if let Some(canonical) = self.canonical[u] {
+            self.absorb(u, canonical);
+            return;
+        }
+
+        let parents = self.parents[u].clone();
+        for &parent in &parents {
+            self.transfer_counts_and_update_canonicals(parent);
+        }
+        // The canonical of the parents if they all have the same
+        let first = self.canonical[parents[0]];
+        match first.filter(|_| parents[1..].iter().all(|&parent| self.canonical[parent] == first)) {
+            Some(canonical) => self.absorb(u, canonical),
+            None => self.ambiguous[u] = true,
+        }
+    }
+
+    fn resolve_ambiguous(&mut self, u: usize) -> Result<(), StarcodeError> {
+        if self.canonical[u].is_some() {
+            return Ok(());
+        }
+        let parents = self.parents[u].clone();
+        for &parent in &parents {
+            if self.canonical[parent].is_none() {
+                self.resolve_ambiguous(parent)?;
+            }
+        }
+
+        // 1. The canonical parent with more counts.
+        // 2. The canonical parent whose sphere has more sequences.
+        // 3. The parent whose canonical has more counts.
+        let mut canonical = None;
+        let (mut cnt_max, mut ssz_max) = (0, 0);
+        for &parent in &parents {
+            if self.canonical[parent] != Some(parent) {
+                continue;
+            }
+            let (count, sphere_c) = (self.count[parent], self.sphere_c[parent]);
+            if count > cnt_max {
+                canonical = Some(parent);
+                cnt_max = count;
+                ssz_max = sphere_c;
+            } else if count == cnt_max && canonical != Some(parent) {
+                if sphere_c > ssz_max {
+                    canonical = Some(parent);
+                    ssz_max = sphere_c;
+                } else if sphere_c == ssz_max {
+                    canonical = None;
+                }
+            }
+        }
+        if canonical.is_none() {
+            cnt_max = 0;
+            for &parent in &parents {
+                let parent_canonical = self.canonical[parent].ok_or(StarcodeError::Internal)?;
+                if self.count[parent_canonical] > cnt_max {
+                    cnt_max = self.count[parent_canonical];
+                    canonical = Some(parent_canonical);
+                }
+            }
+        }
+        self.absorb(u, canonical.ok_or(StarcodeError::Internal)?);
+        Ok(())
+    }
+}
+
+impl ClusteringAlgorithm for Spheres {
+    fn cluster(&self, graph: &MatchGraph, adjacency: &CsrAdjacency) -> Result<Vec<usize>, StarcodeError> {
+        // Most counts first, then most counts in the neighborhood ('count_order_spheres()')
+        let neighborhood: Vec<usize> = (0..graph.len())
+            .map(|u| adjacency.row(u).map(|(neighbor, _)| graph.counts[neighbor]).sum())
+            .collect();
+        let mut order: Vec<usize> = (0..graph.len()).collect();
+        order.sort_by(|&a, &b| (graph.counts[b], neighborhood[b]).cmp(&(graph.counts[a], neighborhood[a])));
+
+        let mut canonical: Vec<Option<usize>> = vec![None; graph.len()];
+        let mut sphere_d = vec![0; graph.len()];
+        for u in order {
+            if canonical[u].is_some() {
+                continue;
+            }
+            canonical[u] = Some(u);
+            for (neighbor, distance) in adjacency.row(u) {
+                // Claimed sequences are only taken from spheres they are further from
+                if canonical[neighbor].is_some() && distance >= sphere_d[neighbor] {
+                    continue;
+                }
+                canonical[neighbor] = Some(u);
+                sphere_d[neighbor] = distance;
+            }
+        }
+        canonical.into_iter().map(|canonical| canonical.ok_or(StarcodeError::Internal)).collect()
+    }
+}
+
+impl ClusteringAlgorithm for ConnectedComponents {
+    fn cluster(&self, graph: &MatchGraph, adjacency: &CsrAdjacency) -> Result<Vec<usize>, StarcodeError> {
+        let degree = |u: usize| adjacency.offsets[u + 1] - adjacency.offsets[u];
+        let mut canonical: Vec<Option<usize>> = vec![None; graph.len()];
+        for root in 0..graph.len() {
+            if canonical[root].is_some() {
+                continue;
+            }
+            // Depth first, in the order of the recursion of 'connected_components()'
+            let mut component = vec![root];
+            canonical[root] = Some(root);
+            let mut stack = vec![(root, adjacency.offsets[root])];
+            while let Some((u, next)) = stack.last_mut() {
+                if *next == adjacency.offsets[*u + 1] {
+                    stack.pop();
+                    continue;
+                }
+                let neighbor = adjacency.neighbors[*next];
+                *next += 1;
+                if canonical[neighbor].is_none() {
+                    canonical[neighbor] = Some(root);
+                    component.push(neighbor);
+                    stack.push((neighbor, adjacency.offsets[neighbor]));
+                }
+            }
+
+            // The centroid is the first with the most counts, then the most neighbors
+            let mut centroid = root;
+            for &u in &component[1..] {
+                if (graph.counts[u], degree(u)) > (graph.counts[centroid], degree(centroid)) {
+                    centroid = u;
+                }
+            }
+            for &u in &component {
+                canonical[u] = Some(centroid);
+            }
+        }
+        canonical.into_iter().map(|canonical| canonical.ok_or(StarcodeError::Internal)).collect()
+    }
+}
+
+// Shapes the clusters of 'canonicals' as starcode exports message passing clusters: by
+// decreasing count then canonical, the members in byte order. 'ids' are the input positions
+// of each sequence of 'graph'.
+pub(crate) fn export(graph: &MatchGraph, canonicals: &[usize], mut ids: Vec<Vec<u32>>) -> Result<Vec<ExportedCluster>, StarcodeError> {
+    if canonicals.len() != graph.len() {
+        return Err(StarcodeError::InvalidParameter(format!(
+            "the clustering has {} canonicals for {} sequences", canonicals.len(), graph.len())));
+    }
+    let mut members: FxHashMap<usize, Vec<usize>> = FxHashMap::default();
+    for (u, &canonical) in canonicals.iter().enumerate() {
+        if canonicals.get(canonical) != Some(&canonical) {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "the clustering assigns sequence {} to {}, which is not a canonical", u, canonical)));
+        }
+        members.entry(canonical).or_default().push(u);
+    }
+
+    let mut clusters: Vec<ExportedCluster> = members.into_iter().map(|(canonical, mut members)| {
+        members.sort_by(|&a, &b| graph.sequences[a].cmp(&graph.sequences[b]));
+        let count = members.iter().map(|&u| graph.counts[u]).sum();
+        ExportedCluster {
+            canonical: graph.sequences[canonical].clone(),
+            count,
+            sphere_size: count,
+            members: members.into_iter().map(|u| ExportedMember {
+                sequence: graph.sequences[u].clone(),
+                count: graph.counts[u],
+                sphere_distance: 0,
+                ids: std::mem::take(&mut ids[u]),
+            }).collect(),
+        }
+    }).collect();
+    clusters.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.canonical.cmp(&b.canonical)));
+    Ok(clusters)
+}
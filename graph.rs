@@ -0,0 +1,165 @@
+use std::sync::{Mutex, PoisonError};
+
+use rustc_hash::FxHashMap;
+
+/// The unique sequences of an input and the pairs of them within tau of each other, as the
+/// all-pairs search of starcode finds them, see `match_graph`
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchGraph {
+    // in the order of starcode: shortest first, then in byte order
+    pub sequences: Vec<Vec<u8>>,
+    // sum of the counts of each unique sequence in the input
+    pub counts: Vec<usize>,
+    // each pair once, in the order the search found them
+    pub edges: Vec<MatchEdge>,
+}
+
+/// Two unique sequences within tau of each other, by position in `MatchGraph::sequences`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchEdge {
+    // the sequence that was searched
+    pub query: usize,
+    // the sequence of the trie it matched
+    pub target: usize,
+    // Levenshtein distance, at least 1
+    pub distance: usize,
+    pub query_count: usize,
+    pub target_count: usize,
+}
+
+/// The match graph as compressed sparse rows: the neighbors of the sequence at `i` are
+/// `neighbors[offsets[i]..offsets[i + 1]]`, every edge appears in both rows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CsrAdjacency {
+    pub offsets: Vec<usize>,
+    pub neighbors: Vec<usize>,
+    // distance of each entry of 'neighbors'
+    pub distances: Vec<usize>,
+}
+
+impl MatchGraph {
+    // The unique sequences of 'sequences' in the order of starcode, with their total counts
+    pub(crate) fn new<'a, I>(sequences: I) -> MatchGraph
+    where
+        I: IntoIterator<Item = (&'a [u8], u64)>,
+    {
+        let mut totals: FxHashMap<&[u8], u64> = FxHashMap::default();
+        for (sequence, count) in sequences {
+            *totals.entry(sequence).or_insert(0) += count;
+        }
+        let mut nodes: Vec<(&[u8], u64)> = totals.into_iter().collect();
+        nodes.sort_unstable_by(|a, b| (a.0.len(), a.0).cmp(&(b.0.len(), b.0)));
+        MatchGraph {
+            sequences: nodes.iter().map(|(sequence, _)| sequence.to_vec()).collect(),
+            counts: nodes.iter().map(|&(_, count)| count as usize).collect(),
+            edges: Vec::new(),
+        }
+    }
+
+    /// Number of unique sequences
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    // The 0-based positions of each sequence in 'input', which holds the sequences of the graph
+    pub(crate) fn input_ids<'a, I>(&self, input: I) -> Vec<Vec<u32>>
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        let positions: FxHashMap<&[u8], usize> = self.sequences.iter().enumerate()
+            .map(|(position, sequence)| (sequence.as_slice(), position))
+            .collect();
+        let mut ids = vec![Vec::new(); self.len()];
+        for (id, sequence) in input.into_iter().enumerate() {
+            if let Some(&position) = positions.get(sequence) {
+                ids[position].push(id as u32);
+            }
+        }
+        ids
+    }
+
+    /// The adjacency of every sequence, nearest neighbors first, then in the order of `edges`
+    pub fn to_csr(&self) -> CsrAdjacency {
+        let mut degrees = vec![0; self.len()];
+        for edge in &self.edges {
+            degrees[edge.query] += 1;
+            degrees[edge.target] += 1;
+        }
+        let mut offsets = Vec::with_capacity(self.len() + 1);
+        offsets.push(0);
+        for degree in degrees {
+            offsets.push(offsets[offsets.len() - 1] + degree);
+        }
+
+        let mut rows: Vec<(usize, usize)> = vec![(0, 0); offsets[self.len()]];
+        let mut next = offsets.clone();
+        for edge in &self.edges {
+            for (from, to) in [(edge.query, edge.target), (edge.target, edge.query)] {
+                rows[next[from]] = (to, edge.distance);
+                next[from] += 1;
+            }
+        }
+        for i in 0..self.len() {
+            rows[offsets[i]..offsets[i + 1]].sort_by_key(|&(_, distance)| distance);
+        }
+        CsrAdjacency {
+            offsets,
+            neighbors: rows.iter().map(|&(neighbor, _)| neighbor).collect(),
+            distances: rows.iter().map(|&(_, distance)| distance).collect(),
+        }
+    }
+}
+
+impl CsrAdjacency {
+    /// The neighbors of the sequence at `index` with their distances
+    pub fn row(&self, index: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let range = self.offsets[index]..self.offsets[index + 1];
+        self.neighbors[range.clone()].iter().copied().zip(self.distances[range].iter().copied())
+    }
+}
+
+// Turns the pairs passed to the 'edge' hook into the edges of a graph. The hook is called
+// from the query threads, hence the lock.
+pub(crate) struct EdgeCollector {
+    graph: MatchGraph,
+    positions: FxHashMap<Vec<u8>, usize>,
+    edges: Mutex<Vec<MatchEdge>>,
+}
+
+impl EdgeCollector {
+    pub(crate) fn new(graph: MatchGraph) -> Self {
+        let positions = graph.sequences.iter().enumerate()
+            .map(|(position, sequence)| (sequence.clone(), position))
+            .collect();
+        EdgeCollector { graph, positions, edges: Mutex::new(Vec::new()) }
+    }
+
+    // The sequences are padded, as starcode holds them during the query
+    pub(crate) fn push(&self, query: &[u8], target: &[u8], distance: usize) {
+        let (query, target) = match (self.position(query), self.position(target)) {
+            (Some(query), Some(target)) => (query, target),
+            _ => return,
+        };
+        let edge = MatchEdge {
+            query,
+            target,
+            distance,
+            query_count: self.graph.counts[query],
+            target_count: self.graph.counts[target],
+        };
+        self.edges.lock().unwrap_or_else(PoisonError::into_inner).push(edge);
+    }
+
+    fn position(&self, padded: &[u8]) -> Option<usize> {
+        let start = padded.iter().position(|&c| c != b' ').unwrap_or(padded.len());
+        self.positions.get(&padded[start..]).copied()
+    }
+
+    pub(crate) fn into_graph(self) -> MatchGraph {
+        MatchGraph { edges: self.edges.into_inner().unwrap_or_else(PoisonError::into_inner), ..self.graph }
+    }
+}
@@ -0,0 +1,157 @@
+use std::ops::Range;
+use std::thread;
+
+use rustc_hash::FxHashMap;
+
+use crate::cancel::Cancellation;
+use crate::clustering;
+use crate::{
+    ClusterAlgorithm, ClusteringAlgorithm, ConnectedComponents, ExportedCluster, MatchEdge,
+    MatchGraph, MessagePassing, Progress, SequenceCounts, Spheres, Stage, StarcodeError,
+    StarcodeParams,
+};
+
+// Queries between two checks of the cancellation, as in starcode.c
+const CANCEL_INTERVAL: usize = 1024;
+
+// Same as 'run_starcode_graph' by Hamming distance: the match graph of 'sequences' and, if
+// 'cluster' is set, the clusters of the algorithm of 'params' in the shape starcode exports
+// them (spheres and components start with their canonical)
+pub(crate) fn run(sequences: &SequenceCounts, params: &StarcodeParams, cluster: bool) -> Result<(Vec<ExportedCluster>, MatchGraph), StarcodeError> {
+    let graph = match_graph(sequences, params)?;
+    if !cluster {
+        return Ok((Vec::new(), graph));
+    }
+
+    report(params, Progress::Started(Stage::Clustering));
+    let adjacency = graph.to_csr();
+    let canonicals = match params.algorithm {
+        ClusterAlgorithm::MessagePassing => MessagePassing { cluster_ratio: params.cluster_ratio }.cluster(&graph, &adjacency)?,
+        ClusterAlgorithm::Spheres => Spheres.cluster(&graph, &adjacency)?,
+        ClusterAlgorithm::ConnectedComponents => ConnectedComponents.cluster(&graph, &adjacency)?,
+    };
+    report(params, Progress::Finished(Stage::Clustering));
+
+    let ids = graph.input_ids(sequences.sequences.iter().map(|sequence| sequence.to_bytes()));
+    let mut clusters = clustering::export(&graph, &canonicals, ids)?;
+    if params.algorithm != ClusterAlgorithm::MessagePassing {
+        for cluster in &mut clusters {
+            for member in &mut cluster.members {
+                member.sphere_distance = hamming(&member.sequence, &cluster.canonical, usize::MAX).unwrap_or(0);
+            }
+            // The members are in byte order, the sort keeps it within a distance
+            cluster.members.sort_by_key(|member| member.sphere_distance);
+        }
+    }
+    Ok((clusters, graph))
+}
+
+// The match graph of 'sequences' by Hamming distance. A sequence of length L split in tau + 1
+// segments shares at least one of them with every sequence within tau, so only the sequences
+// of the same length that share a segment are compared. The queries are split between the
+// threads of 'params', the edges come in the order of the queries all the same.
+fn match_graph(sequences: &SequenceCounts, params: &StarcodeParams) -> Result<MatchGraph, StarcodeError> {
+    if sequences.counts.is_empty() {
+        return Err(StarcodeError::EmptyInput);
+    }
+    let cancellation = Cancellation::start(params);
+
+    report(params, Progress::Started(Stage::Sorting));
+    let mut graph = MatchGraph::new(sequences.sequences.iter().zip(&sequences.counts)
+        .map(|(sequence, &count)| (sequence.to_bytes(), count as u64)));
+    report(params, Progress::UniqueSequences { unique: graph.len(), total: sequences.counts.len() });
+    report(params, Progress::Finished(Stage::Sorting));
+
+    report(params, Progress::Started(Stage::Querying));
+    let tau = params.tau(graph.sequences[(graph.len() - 1) / 2].len());
+    // The sequences are sorted by length first, each length is a block
+    let mut start = 0;
+    while start < graph.len() {
+        let length = graph.sequences[start].len();
+        let end = start + graph.sequences[start..].partition_point(|sequence| sequence.len() == length);
+        let edges = search(&graph, start..end, tau, params.threads, &cancellation)?;
+        graph.edges.extend(edges);
+        start = end;
+    }
+    report(params, Progress::Finished(Stage::Querying));
+    Ok(graph)
+}
+
+// The pairs within 'tau' of the sequences of 'block', which all have the same length
+fn search(graph: &MatchGraph, block: Range<usize>, tau: usize, threads: usize, cancellation: &Cancellation) -> Result<Vec<MatchEdge>, StarcodeError> {
+    let length = graph.sequences[block.start].len();
+    // Segments are empty when tau is not below the length, every sequence shares them
+    let bounds: Vec<usize> = (0..=tau + 1).map(|i| i * length / (tau + 1)).collect();
+    let mut segments: Vec<FxHashMap<&[u8], Vec<usize>>> = vec![FxHashMap::default(); tau + 1];
+    for u in block.clone() {
+        for (i, segment) in segments.iter_mut().enumerate() {
+            segment.entry(&graph.sequences[u].as_slice()[bounds[i]..bounds[i + 1]]).or_default().push(u);
+        }
+    }
+
+    let queries: Vec<usize> = block.collect();
+    let chunk = queries.len().div_ceil(threads);
+    let (bounds, segments) = (&bounds, &segments);
+    thread::scope(|scope| {
+        let jobs: Vec<_> = queries.chunks(chunk)
+            .map(|queries| scope.spawn(move || query(graph, queries, tau, bounds, segments, cancellation)))
+            .collect();
+        let mut edges = Vec::new();
+        for job in jobs {
+            edges.extend(job.join().map_err(|_| StarcodeError::Internal)??);
+        }
+        Ok(edges)
+    })
+}
+
+// Matches each query against the sequences before it, so that every pair is found once
+fn query(graph: &MatchGraph, queries: &[usize], tau: usize, bounds: &[usize], segments: &[FxHashMap<&[u8], Vec<usize>>], cancellation: &Cancellation) -> Result<Vec<MatchEdge>, StarcodeError> {
+    let mut edges = Vec::new();
+    let mut candidates = Vec::new();
+    for (n, &query) in queries.iter().enumerate() {
+        if n % CANCEL_INTERVAL == CANCEL_INTERVAL - 1 && cancellation.is_cancelled() {
+            return Err(StarcodeError::Cancelled);
+        }
+        let sequence = &graph.sequences[query];
+        candidates.clear();
+        for (i, segment) in segments.iter().enumerate() {
+            if let Some(targets) = segment.get(&sequence[bounds[i]..bounds[i + 1]]) {
+                candidates.extend(targets.iter().copied().take_while(|&target| target < query));
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        for &target in &candidates {
+            if let Some(distance) = hamming(sequence, &graph.sequences[target], tau) {
+                edges.push(MatchEdge {
+                    query,
+                    target,
+                    distance,
+                    query_count: graph.counts[query],
+                    target_count: graph.counts[target],
+                });
+            }
+        }
+    }
+    Ok(edges)
+}
+
+// Hamming distance of two sequences of the same length, None beyond 'tau'
+fn hamming(a: &[u8], b: &[u8], tau: usize) -> Option<usize> {
+    let mut distance = 0;
+    for (x, y) in a.iter().zip(b) {
+        if x != y {
+            distance += 1;
+            if distance > tau {
+                return None;
+            }
+        }
+    }
+    Some(distance)
+}
+
+fn report(params: &StarcodeParams, progress: Progress) {
+    if let Some(callback) = &params.progress {
+        callback.call(progress);
+    }
+}
@@ -0,0 +1,216 @@
+use std::ffi::CString;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+
+use libc::c_int;
+use rustc_hash::FxHashMap;
+
+use crate::trie::Trie;
+use crate::validation::check_sequence;
+use crate::{
+    lookup_t, starcode_destroy_lookup, starcode_lookup_insert, starcode_lookup_search,
+    starcode_new_lookup, StarcodeError, MAXBRCDLEN, TAU,
+};
+
+/// An index of a set of reference sequences (barcodes), built once and queried for the
+/// nearest references of any number of sequences, from any number of threads.
+///
+/// Queries go through the k-mer filter of starcode before the trie search. The distances
+/// are Levenshtein distances, references that only differ by case are the same barcode and
+/// N never matches.
+pub struct BarcodeIndex {
+    sequences: Vec<Vec<u8>>,
+    // positions in 'sequences' of each different reference, the payloads of the tries
+    groups: Vec<Vec<usize>>,
+    max_tau: usize,
+    height: usize,
+    lookup: Lookup,
+    // A search writes to the trie, so each concurrent query takes its own trie from the
+    // pool (or builds one) and puts it back when done
+    tries: Mutex<Vec<Trie<usize>>>,
+}
+
+/// A reference of the index nearest to a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarcodeMatch {
+    // position of the reference in the sequences the index was built from
+    pub index: usize,
+    pub distance: usize,
+}
+
+impl BarcodeIndex {
+    /// Indexes `sequences` for queries up to `max_tau` away from them
+    pub fn new<I>(sequences: I, max_tau: usize) -> Result<Self, StarcodeError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        if max_tau > TAU as usize {
+            return Err(StarcodeError::InvalidParameter(format!("tau must be at most {}", TAU)));
+        }
+        let mut groups: Vec<Vec<usize>> = Vec::new();
+        let mut group_of: FxHashMap<Vec<u8>, usize> = FxHashMap::default();
+        let sequences = sequences.into_iter().enumerate().map(|(index, sequence)| {
+            let sequence = sequence.as_ref();
+            check_sequence(sequence).map_err(|error| StarcodeError::InvalidEntry { index, error })?;
+            let group = *group_of.entry(sequence.to_ascii_uppercase()).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(index);
+            Ok(sequence.to_vec())
+        }).collect::<Result<Vec<_>, StarcodeError>>()?;
+        if sequences.is_empty() {
+            return Err(StarcodeError::EmptyInput);
+        }
+
+        // Queries up to 'max_tau' longer than the longest reference can still match it
+        let longest = sequences.iter().map(Vec::len).max().unwrap_or(0);
+        let height = (longest + max_tau).min(MAXBRCDLEN as usize);
+        let mut lengths: Vec<usize> = groups.iter().map(|group| sequences[group[0]].len()).collect();
+        lengths.sort_unstable();
+        let mut lookup = Lookup::new(lengths[lengths.len() / 2], height, max_tau)?;
+        for group in &groups {
+            lookup.insert(&pad(&sequences[group[0]], height))?;
+        }
+
+        let index = BarcodeIndex { sequences, groups, max_tau, height, lookup, tries: Mutex::new(Vec::new()) };
+        let trie = index.build_trie()?;
+        index.put_back(trie);
+        Ok(index)
+    }
+
+    pub fn max_tau(&self) -> usize {
+        self.max_tau
+    }
+
+    /// Number of sequences the index was built from, repeats included
+    pub fn len(&self) -> usize {
+        self.sequences.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sequences.is_empty()
+    }
+
+    /// The reference at `index`, as given to `new`
+    pub fn sequence(&self, index: usize) -> &[u8] {
+        &self.sequences[index]
+    }
+
+    /// Returns the references nearest to `sequence` if they are within `tau` of it, by
+    /// position. Repeated references all match.
+    pub fn query(&self, sequence: &[u8], tau: usize) -> Result<Vec<BarcodeMatch>, StarcodeError> {
+        if tau > self.max_tau {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "tau {} is larger than the tau of the index {}", tau, self.max_tau)));
+        }
+        check_sequence(sequence).map_err(StarcodeError::InvalidSequence)?;
+        // Too long to be within 'max_tau' of any reference
+        if sequence.len() > self.height {
+            return Ok(Vec::new());
+        }
+        if !self.lookup.search(&pad(sequence, self.height))? {
+            return Ok(Vec::new());
+        }
+
+        let mut trie = self.take_trie()?;
+        let result = trie.search(sequence, tau).map(|hits| {
+            let nearest = hits.first().map_or(0, |hit| hit.distance);
+            let mut matches: Vec<BarcodeMatch> = hits.iter()
+                .take_while(|hit| hit.distance == nearest)
+                .flat_map(|hit| self.groups[*hit.payload].iter()
+                    .map(move |&index| BarcodeMatch { index, distance: hit.distance }))
+                .collect();
+            matches.sort_unstable_by_key(|m| m.index);
+            matches
+        });
+        self.put_back(trie);
+        result
+    }
+
+    /// Same as `query` for each of `sequences`, spread over the available cores
+    pub fn query_batch<S>(&self, sequences: &[S], tau: usize) -> Result<Vec<Vec<BarcodeMatch>>, StarcodeError>
+    where
+        S: AsRef<[u8]> + Sync,
+    {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = sequences.len().div_ceil(threads).max(1);
+        thread::scope(|scope| {
+            let workers: Vec<_> = sequences.chunks(chunk_size).map(|chunk| scope.spawn(move || {
+                chunk.iter().map(|sequence| self.query(sequence.as_ref(), tau)).collect::<Result<Vec<_>, _>>()
+            })).collect();
+            let mut results = Vec::with_capacity(sequences.len());
+            for worker in workers {
+                results.extend(worker.join().map_err(|_| StarcodeError::Internal)??);
+            }
+            Ok(results)
+        })
+    }
+
+    fn build_trie(&self) -> Result<Trie<usize>, StarcodeError> {
+        let mut trie = Trie::new(self.height)?;
+        for (group, positions) in self.groups.iter().enumerate() {
+            *trie.insert(&self.sequences[positions[0]])? = group;
+        }
+        Ok(trie)
+    }
+
+    fn take_trie(&self) -> Result<Trie<usize>, StarcodeError> {
+        let pooled = self.tries.lock().unwrap_or_else(PoisonError::into_inner).pop();
+        match pooled {
+            Some(trie) => Ok(trie),
+            None => self.build_trie(),
+        }
+    }
+
+    fn put_back(&self, trie: Trie<usize>) {
+        self.tries.lock().unwrap_or_else(PoisonError::into_inner).push(trie);
+    }
+}
+
+// The sequence padded with leading spaces to 'height', as the lookup and the tries take it
+fn pad(sequence: &[u8], height: usize) -> CString {
+    let mut padded = vec![b' '; height - sequence.len()];
+    padded.extend_from_slice(sequence);
+    // checked by the callers: DNA only
+    CString::new(padded).unwrap()
+}
+
+// The k-mer filter of starcode, only read once built
+struct Lookup(*mut lookup_t);
+
+unsafe impl Send for Lookup {}
+unsafe impl Sync for Lookup {}
+
+impl Lookup {
+    fn new(median: usize, height: usize, tau: usize) -> Result<Lookup, StarcodeError> {
+        let lookup = unsafe { starcode_new_lookup(median as c_int, height as c_int, tau as c_int) };
+        if lookup.is_null() {
+            return Err(StarcodeError::OutOfMemory);
+        }
+        Ok(Lookup(lookup))
+    }
+
+    fn insert(&mut self, padded: &CString) -> Result<(), StarcodeError> {
+        match unsafe { starcode_lookup_insert(self.0, padded.as_ptr()) } {
+            0 => Ok(()),
+            _ => Err(StarcodeError::Internal),
+        }
+    }
+
+    // Whether the padded query may be within the tau of the lookup of a reference
+    fn search(&self, padded: &CString) -> Result<bool, StarcodeError> {
+        match unsafe { starcode_lookup_search(self.0, padded.as_ptr()) } {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(StarcodeError::Internal),
+        }
+    }
+}
+
+impl Drop for Lookup {
+    fn drop(&mut self) {
+        unsafe { starcode_destroy_lookup(self.0) }
+    }
+}
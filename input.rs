@@ -0,0 +1,125 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::io::FromRawFd;
+use std::path::Path;
+use std::thread;
+
+use flate2::bufread::MultiGzDecoder;
+use libc::c_char;
+
+use crate::{
+    format_t, format_t_FASTA, format_t_FASTQ, format_t_PE_FASTQ, format_t_RAW, CFile, StarcodeError, FILE,
+};
+
+/// Format of the input as detected by starcode (the `format_t` enum of starcode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputFormat {
+    Raw,
+    Fasta,
+    Fastq,
+    PairedFastq,
+}
+
+impl InputFormat {
+    pub(crate) fn from_format_t(format: format_t) -> Option<InputFormat> {
+        match format {
+            format_t_RAW => Some(InputFormat::Raw),
+            format_t_FASTA => Some(InputFormat::Fasta),
+            format_t_FASTQ => Some(InputFormat::Fastq),
+            format_t_PE_FASTQ => Some(InputFormat::PairedFastq),
+            _ => None,
+        }
+    }
+}
+
+/// Count of a sequence in an input collection, implemented for the unsigned integers
+/// and references to them so that maps can be passed by reference
+pub trait SequenceCount {
+    fn sequence_count(&self) -> u64;
+}
+
+macro_rules! impl_sequence_count {
+    ($($t:ty),*) => {
+        $(impl SequenceCount for $t {
+            fn sequence_count(&self) -> u64 {
+                *self as u64
+            }
+        })*
+    };
+}
+
+impl_sequence_count!(u8, u16, u32, u64, usize);
+
+impl<T: SequenceCount + ?Sized> SequenceCount for &T {
+    fn sequence_count(&self) -> u64 {
+        (**self).sequence_count()
+    }
+}
+
+pub(crate) type Input<'a> = Box<dyn Read + Send + 'a>;
+
+// Opens 'path' for starcode, "-" is the standard input
+pub(crate) fn open_path(path: &Path) -> Result<Input<'static>, StarcodeError> {
+    if path == Path::new("-") {
+        return decompressed(io::stdin());
+    }
+    let file = File::open(path).map_err(|e| io::Error::new(e.kind(), format!("could not open {:?}: {}", path, e)))?;
+    decompressed(file)
+}
+
+// Decompresses gzip streams on the fly, other streams are passed as they are
+pub(crate) fn decompressed<'a, R: Read + Send + 'a>(reader: R) -> Result<Input<'a>, StarcodeError> {
+    let mut reader = BufReader::new(reader);
+    // A short first read is not a gzip header either
+    let is_gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    if is_gzip {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+// Passes the inputs to 'run' as C streams. Each stream is the read end of a pipe that a
+// thread fills from the input, so starcode reads the input only once and from anywhere.
+pub(crate) fn with_c_streams<T, F>(inputs: Vec<Input<'_>>, run: F) -> Result<T, StarcodeError>
+where
+    F: FnOnce(&[*mut FILE]) -> Result<T, StarcodeError>,
+{
+    let mut streams = Vec::with_capacity(inputs.len());
+    let mut writers = Vec::with_capacity(inputs.len());
+    for _ in 0..inputs.len() {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(StarcodeError::Io(io::Error::last_os_error()));
+        }
+        // The File closes the write end once the input is copied
+        writers.push(unsafe { File::from_raw_fd(fds[1]) });
+        let stream = unsafe { libc::fdopen(fds[0], b"r\0".as_ptr() as *const c_char) };
+        if stream.is_null() {
+            unsafe { libc::close(fds[0]) };
+            return Err(StarcodeError::Io(io::Error::last_os_error()));
+        }
+        streams.push(CFile(stream as *mut FILE));
+    }
+
+    thread::scope(|scope| {
+        let copies: Vec<_> = inputs.into_iter().zip(writers).map(|(mut input, mut writer)| {
+            scope.spawn(move || io::copy(&mut input, &mut writer))
+        }).collect();
+
+        let result = run(&streams.iter().map(|stream| stream.0).collect::<Vec<_>>());
+        // Closing the read ends stops the copies if starcode did not read everything
+        drop(streams);
+
+        for copy in copies {
+            match copy.join() {
+                Ok(Ok(_)) => {}
+                Ok(Err(e)) if e.kind() == io::ErrorKind::BrokenPipe => {}
+                // starcode did not see the whole input, report it before its result
+                Ok(Err(e)) => return Err(StarcodeError::Io(e)),
+                Err(panic) => std::panic::resume_unwind(panic),
+            }
+        }
+        result
+    })
+}
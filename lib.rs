@@ -10,248 +10,1031 @@ extern crate tempfile;
 extern crate bindgen;
 extern crate rand;
 extern crate rustc_hash;
+extern crate flate2;
+extern crate tracing;
 
-use std::ffi::CString;
-use libc::{c_char};
-use std::fs::{File, OpenOptions};
-use std::io::{self, BufRead, Write};
+use std::borrow::{Borrow, Cow};
+use std::ffi::{CStr, CString};
+use std::fmt;
+use libc::{c_char, c_int, c_long, c_void};
+use std::fs::File;
+use std::io::{self, BufRead};
 use std::path::Path;
+use std::sync::{Mutex, PoisonError};
 use tempfile::NamedTempFile;
-use rustc_hash::{FxHashMap};
+use tracing::Span;
 
 
 include!("bindings.rs");
 
+mod params;
+pub use params::{ClusterAlgorithm, DistanceMetric, OutputMode, StarcodeParams, StarcodeParamsBuilder};
+mod records;
+pub use records::{FastaRecord, FastqRecord, Representatives};
+mod input;
+pub use input::{InputFormat, SequenceCount};
+mod validation;
+pub use validation::EntryError;
+mod cancel;
+pub use cancel::CancellationToken;
+use cancel::Cancellation;
+mod progress;
+pub use progress::{Progress, Stage};
+use progress::ProgressCallback;
+pub mod trie;
+mod index;
+pub use index::{BarcodeIndex, BarcodeMatch};
+mod whitelist;
+pub use whitelist::{BarcodeAssignment, Whitelist, WhitelistCorrection};
+mod graph;
+pub use graph::{CsrAdjacency, MatchEdge, MatchGraph};
+use graph::EdgeCollector;
+mod clustering;
+pub use clustering::{ClusteringAlgorithm, ConnectedComponents, MessagePassing, Spheres};
+mod hamming;
+#[cfg(feature = "pure-rust")]
+pub mod native;
+
 #[allow(dead_code)]
 pub struct StarcodeAlignment {
     pub cluster_centers: Vec<Vec<u8>>,
     pub cluster_count: Vec<usize>,
     pub cluster_members: Vec<Vec<Vec<u8>>>,
+    // sorted 0-based positions of the members in the input (in iteration order),
+    // filled when the ids are shown
+    pub cluster_member_ids: Vec<Vec<u32>>,
+}
+
+/// Clusters of a file or a stream, see `cluster_file`
+pub struct ClusteredFile {
+    pub format: InputFormat,
+    pub alignment: StarcodeAlignment,
+}
+
+/// A sphere: the sequences within tau of a center that no larger sphere claimed first
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sphere {
+    pub center: Vec<u8>,
+    // sum of the counts of all the members, center included (sphere_c)
+    pub size: usize,
+    // the center comes first, at distance 0
+    pub members: Vec<SphereMember>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SphereMember {
+    pub sequence: Vec<u8>,
+    pub count: usize,
+    // Levenshtein distance to the center
+    pub distance: usize,
+}
+
+/// A connected component of the graph linking the sequences within tau of each other
+#[derive(Debug, Clone, PartialEq)]
+pub struct Component {
+    // the member with the highest count, ties broken by number of neighbors
+    pub centroid: Vec<u8>,
+    // sum of the counts of all the members
+    pub count: usize,
+    // the centroid comes first
+    pub members: Vec<ComponentMember>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ComponentMember {
+    pub sequence: Vec<u8>,
+    pub count: usize,
+}
+
+/// The two mates of a paired-end read, clustered as one sequence
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MatePair {
+    pub first: Vec<u8>,
+    pub second: Vec<u8>,
+}
+
+/// A cluster of read pairs, see `cluster_pairs`
+#[derive(Debug, Clone, PartialEq)]
+pub struct PairedCluster {
+    pub canonical: MatePair,
+    // same as 'cluster_count' of StarcodeAlignment
+    pub count: usize,
+    // empty unless the members are shown
+    pub members: Vec<MatePair>,
+}
+
+#[derive(Debug)]
+pub enum StarcodeError {
+    Io(io::Error),
+    // starcode returned a status code we do not know about
+    Starcode(i32),
+    EmptyInput,
+    MalformedOutput(String),
+    InvalidParameter(String),
+    OutOfMemory,
+    // a worker thread could not be started
+    Thread,
+    // the input has non DNA characters or a broken format
+    InvalidInput,
+    SequenceTooLong,
+    // the C core reached an inconsistent state (this is a bug)
+    Internal,
+    // an entry of an in-memory input, 'index' is its position in the input
+    InvalidEntry { index: usize, error: EntryError },
+    // stopped by the cancellation token or the time limit of the parameters
+    Cancelled,
+    // a sequence passed on its own, such as a trie key
+    InvalidSequence(EntryError),
+}
+
+impl fmt::Display for StarcodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StarcodeError::Io(e) => write!(f, "I/O error: {}", e),
+            StarcodeError::Starcode(code) => write!(f, "starcode failed with code {}", code),
+            StarcodeError::EmptyInput => write!(f, "no sequences to cluster"),
+            StarcodeError::MalformedOutput(line) => write!(f, "malformed starcode output: {}", line),
+            StarcodeError::InvalidParameter(message) => write!(f, "invalid parameter: {}", message),
+            StarcodeError::OutOfMemory => write!(f, "starcode ran out of memory"),
+            StarcodeError::Thread => write!(f, "starcode could not start a thread"),
+            StarcodeError::InvalidInput => write!(f, "input contains non DNA characters"),
+            StarcodeError::SequenceTooLong => write!(f, "sequence longer than {} characters", MAXBRCDLEN),
+            StarcodeError::Internal => write!(f, "internal starcode error"),
+            StarcodeError::InvalidEntry { index, error } => write!(f, "input entry {}: {}", index, error),
+            StarcodeError::Cancelled => write!(f, "starcode run cancelled"),
+            StarcodeError::InvalidSequence(error) => write!(f, "invalid sequence: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for StarcodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            StarcodeError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for StarcodeError {
+    fn from(e: io::Error) -> Self {
+        StarcodeError::Io(e)
+    }
 }
 
+// Maps the status returned by the C entry points to an error
+fn status_to_result(code: i32) -> Result<(), StarcodeError> {
+    match code as starcode_status_t {
+        starcode_status_t_STARCODE_OK => Ok(()),
+        starcode_status_t_STARCODE_EMPTY_INPUT => Err(StarcodeError::EmptyInput),
+        starcode_status_t_STARCODE_IO_ERROR => Err(StarcodeError::Io(io::Error::other(
+            "starcode could not open or read a file"))),
+        starcode_status_t_STARCODE_ALLOC_ERROR => Err(StarcodeError::OutOfMemory),
+        starcode_status_t_STARCODE_THREAD_ERROR => Err(StarcodeError::Thread),
+        starcode_status_t_STARCODE_INVALID_INPUT => Err(StarcodeError::InvalidInput),
+        starcode_status_t_STARCODE_SEQ_TOO_LONG => Err(StarcodeError::SequenceTooLong),
+        starcode_status_t_STARCODE_INTERNAL_ERROR => Err(StarcodeError::Internal),
+        starcode_status_t_STARCODE_CANCELLED => Err(StarcodeError::Cancelled),
+        _ => Err(StarcodeError::Starcode(code)),
+    }
+}
+
+/// Owner of the C allocations of the runs made through it: the parameters, the sequences
+/// (`useq_t`, with the towers of their matches) and the tries and lookups of the last run stay
+/// with the context until its next run, `clear` or drop, which free them. Runs made without a
+/// context free them before they return.
+#[derive(Debug)]
 pub struct StarcodeContext {
-    tower_top: *mut gstack_t,
-    #[cfg(debug_assertions)]
-    allocation_count: std::sync::atomic::AtomicUsize,
+    // Created by the first run
+    context: *mut starcode_context_t,
 }
 
+// Runs take the context by '&mut', it is never shared between threads
+unsafe impl Send for StarcodeContext {}
+
 impl StarcodeContext {
     pub fn new() -> Self {
-        // Initialize the context
-        unsafe {
-            // Call init_tower to ensure we start with a clean state
-            //init_new_tower();
+        StarcodeContext { context: std::ptr::null_mut() }
+    }
+
+    /// Same as `cluster`, the allocations of the run stay with the context
+    pub fn cluster<I, S, C>(&mut self, params: &StarcodeParams, input: I) -> Result<StarcodeAlignment, StarcodeError>
+    where
+        I: IntoIterator<Item = (S, C)>,
+        S: AsRef<[u8]>,
+        C: SequenceCount,
+    {
+        if params.output == OutputMode::Tidy {
+            return Err(StarcodeError::InvalidParameter(
+                "tidy output assigns reads, not clusters, use assign_reads instead".to_string()));
         }
-        
-        Self {
-            tower_top: std::ptr::null_mut(),
-            #[cfg(debug_assertions)]
-            allocation_count: std::sync::atomic::AtomicUsize::new(0),
+        if params.metric != DistanceMetric::Levenshtein {
+            return Err(StarcodeError::InvalidParameter(
+                "a context owns the allocations of starcode, which only searches by Levenshtein distance".to_string()));
         }
+        let sequences = SequenceCounts::new(input, params)?;
+        if self.context.is_null() {
+            self.context = unsafe { starcode_context_new() };
+            if self.context.is_null() {
+                return Err(StarcodeError::OutOfMemory);
+            }
+        }
+        let collector = run_seqs_with_hooks(&sequences, params, true, None, self.context)?;
+        Ok(StarcodeAlignment::from_exported_clusters(collector.clusters, params))
     }
 
-    #[cfg(debug_assertions)]
-    fn track_allocation(&self) {
-        self.allocation_count.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    /// Frees the allocations of the last run
+    pub fn clear(&mut self) {
+        unsafe { starcode_context_clear(self.context) }
     }
+}
 
-    #[cfg(debug_assertions)]
-    fn track_deallocation(&self) {
-        self.allocation_count.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+impl Default for StarcodeContext {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl Drop for StarcodeContext {
     fn drop(&mut self) {
-        unsafe {
-            // Ensure tower is cleaned up
-            //cleanup_new_tower();
+        unsafe { starcode_context_destroy(self.context) }
+    }
+}
+
+/// Clusters `input` (sequence and count pairs, such as a map passed by reference) with the
+/// given parameters. The counts of repeated sequences are added up.
+pub fn cluster<I, S, C>(params: &StarcodeParams, input: I) -> Result<StarcodeAlignment, StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+{
+    if params.output == OutputMode::Tidy {
+        return Err(StarcodeError::InvalidParameter(
+            "tidy output assigns reads, not clusters, use assign_reads instead".to_string()));
+    }
+    StarcodeAlignment::in_memory_align_sequences(input, params)
+}
+
+/// Clusters `input` with the spheres algorithm, whatever the algorithm set in `params`
+pub fn cluster_spheres<I, S, C>(params: &StarcodeParams, input: I) -> Result<Vec<Sphere>, StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+{
+    let params = StarcodeParams { algorithm: ClusterAlgorithm::Spheres, ..params.clone() };
+    let clusters = run_starcode_seqs(&SequenceCounts::new(input, &params)?, &params)?;
+    Ok(clusters.into_iter().map(|cluster| Sphere {
+        center: cluster.canonical,
+        size: cluster.sphere_size,
+        members: cluster.members.into_iter().map(|member| SphereMember {
+            sequence: member.sequence,
+            count: member.count,
+            distance: member.sphere_distance,
+        }).collect(),
+    }).collect())
+}
+
+/// Clusters `input` in connected components, whatever the algorithm set in `params`
+pub fn cluster_components<I, S, C>(params: &StarcodeParams, input: I) -> Result<Vec<Component>, StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+{
+    let params = StarcodeParams { algorithm: ClusterAlgorithm::ConnectedComponents, ..params.clone() };
+    let clusters = run_starcode_seqs(&SequenceCounts::new(input, &params)?, &params)?;
+    Ok(clusters.into_iter().map(|cluster| {
+        let mut members: Vec<ComponentMember> = cluster.members.into_iter().map(|member| ComponentMember {
+            sequence: member.sequence,
+            count: member.count,
+        }).collect();
+        // starcode stores the component count in the centroid, recover its own count
+        let others: usize = members.iter().skip(1).map(|member| member.count).sum();
+        if let Some(centroid) = members.first_mut() {
+            centroid.count = cluster.count - others;
         }
-        
-        #[cfg(debug_assertions)]
-        {
-            let final_count = self.allocation_count.load(std::sync::atomic::Ordering::SeqCst);
-            if final_count != 0 {
-                eprintln!("Warning: {} allocations were not freed", final_count);
-            }
+        Component {
+            centroid: cluster.canonical,
+            count: cluster.count,
+            members,
         }
+    }).collect())
+}
+
+/// The match graph of `input`: its unique sequences and the pairs of them within the
+/// distance of `params`, found as starcode finds them, without clustering. By Hamming
+/// distance, the pairs are all those within the distance.
+pub fn match_graph<I, S, C>(params: &StarcodeParams, input: I) -> Result<MatchGraph, StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+{
+    let (_, graph) = run_starcode_graph(&SequenceCounts::new(input, params)?, params, false)?;
+    Ok(graph)
+}
+
+/// Same as `cluster`, with the match graph the clusters were built from
+pub fn cluster_with_graph<I, S, C>(params: &StarcodeParams, input: I) -> Result<(StarcodeAlignment, MatchGraph), StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+{
+    if params.output == OutputMode::Tidy {
+        return Err(StarcodeError::InvalidParameter(
+            "tidy output assigns reads, not clusters, use assign_reads instead".to_string()));
     }
+    let (clusters, graph) = run_starcode_graph(&SequenceCounts::new(input, params)?, params, true)?;
+    Ok((StarcodeAlignment::from_exported_clusters(clusters, params), graph))
 }
 
-fn write_vectors_to_file(filename: &Path, vectors: &FxHashMap<Vec<u8>,usize>) -> io::Result<()> {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .write(true)
-        .append(true)  // Use this if you want to append to an existing file
-        .open(filename)?;
+/// Clusters `input` with `algorithm` over the match graph of starcode. The algorithm and
+/// cluster ratio of `params` are ignored: the clusters come by decreasing count, then
+/// canonical, and the count of each is the sum of the counts of its members.
+pub fn cluster_graph<I, S, C, A>(params: &StarcodeParams, input: I, algorithm: &A) -> Result<StarcodeAlignment, StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+    A: ClusteringAlgorithm + ?Sized,
+{
+    if params.output == OutputMode::Tidy {
+        return Err(StarcodeError::InvalidParameter(
+            "tidy output assigns reads, not clusters, use assign_reads instead".to_string()));
+    }
+    let sequences = SequenceCounts::new(input, params)?;
+    let (_, graph) = run_starcode_graph(&sequences, params, false)?;
+    let canonicals = algorithm.cluster(&graph, &graph.to_csr())?;
+    let ids = graph.input_ids(sequences.sequences.iter().map(|sequence| sequence.to_bytes()));
+    let clusters = clustering::export(&graph, &canonicals, ids)?;
+    Ok(StarcodeAlignment::from_exported_clusters(clusters, params))
+}
 
-    for data in vectors {
-        file.write_all(&data.0)?;
-        file.write_all(b"\t")?;
-        file.write_all(data.1.to_string().as_bytes())?;
-        file.write_all(b"\n")?;
+/// Returns the canonical sequence of every read, in the order of `reads`. The output mode
+/// of `params` is ignored.
+pub fn assign_reads<S: AsRef<[u8]>>(params: &StarcodeParams, reads: &[S]) -> Result<Vec<Vec<u8>>, StarcodeError> {
+    if reads.is_empty() {
+        return Err(StarcodeError::EmptyInput);
     }
+    let c_reads = reads.iter().enumerate()
+        .map(|(index, read)| sequence_to_cstring(index, read.as_ref(), params))
+        .collect::<Result<Vec<_>, _>>()?;
+    let lines = run_starcode_tidy(&c_reads, None, params)?;
+    Ok(lines.into_iter().map(|(_, canonical)| canonical).collect())
+}
 
-    Ok(())
+/// Returns the canonical pair of every read pair, in the order of `pairs`. The output mode
+/// of `params` is ignored.
+pub fn assign_read_pairs<A: AsRef<[u8]>, B: AsRef<[u8]>>(params: &StarcodeParams, pairs: &[(A, B)]) -> Result<Vec<ReadPair>, StarcodeError> {
+    if pairs.is_empty() {
+        return Err(StarcodeError::EmptyInput);
+    }
+    let mut c_reads1 = Vec::with_capacity(pairs.len());
+    let mut c_reads2 = Vec::with_capacity(pairs.len());
+    for (index, pair) in pairs.iter().enumerate() {
+        let (first, second) = pair_to_cstrings(index, pair.0.as_ref(), pair.1.as_ref(), params)?;
+        c_reads1.push(first);
+        c_reads2.push(second);
+    }
+    let lines = run_starcode_tidy(&c_reads1, Some(&c_reads2), params)?;
+    lines.into_iter().map(|(_, canonical)| {
+        // Pairs are printed as "first/second"
+        match canonical.iter().position(|b| *b == b'/') {
+            Some(i) => Ok((canonical[..i].to_vec(), canonical[i + 1..].to_vec())),
+            None => Err(StarcodeError::MalformedOutput(String::from_utf8_lossy(&canonical).into_owned())),
+        }
+    }).collect()
 }
 
+// Two mates, or a read and its canonical
+type ReadPair = (Vec<u8>, Vec<u8>);
 
-impl StarcodeAlignment {
-    fn debug_align_sequences(
-        sequences: &FxHashMap<Vec<u8>,usize>,
-        max_distance: &i32,
-        parent_to_child_ratio: &f64,
-    ) -> Result<StarcodeAlignment, String> {
-        // Create a context to ensure cleanup
-        let _context = StarcodeContext::new();
-        
-        println!("Debug: Starting alignment with {} sequences", sequences.len());
-        
-        // Create temporary files with error checking
-        let temp_input_file = match NamedTempFile::new() {
-            Ok(f) => f,
-            Err(e) => return Err(format!("Failed to create input temp file: {}", e)),
-        };
-        let temp_output_file = match NamedTempFile::new() {
-            Ok(f) => f,
-            Err(e) => return Err(format!("Failed to create output temp file: {}", e)),
-        };
+// Checks the sequence at 'index' of an input, normalized if asked, before it is passed to starcode
+fn sequence_to_cstring(index: usize, sequence: &[u8], params: &StarcodeParams) -> Result<CString, StarcodeError> {
+    let sequence = if params.normalize {
+        Cow::Owned(validation::normalize(sequence))
+    } else {
+        Cow::Borrowed(sequence)
+    };
+    validation::check_sequence(&sequence).map_err(|error| StarcodeError::InvalidEntry { index, error })?;
+    // The check rules out NUL bytes
+    CString::new(sequence.into_owned()).map_err(|_| StarcodeError::Internal)
+}
+
+// Same as 'sequence_to_cstring' for the mates of the pair at 'index', which must also fit once joined
+fn pair_to_cstrings(index: usize, first: &[u8], second: &[u8], params: &StarcodeParams) -> Result<(CString, CString), StarcodeError> {
+    let first = sequence_to_cstring(index, first, params)?;
+    let second = sequence_to_cstring(index, second, params)?;
+    validation::check_pair(first.as_bytes(), second.as_bytes()).map_err(|error| StarcodeError::InvalidEntry { index, error })?;
+    Ok((first, second))
+}
 
-        let temp_input_path = temp_input_file.path();
-        let temp_output_path = temp_output_file.path();
-        
-        println!("Debug: Created temp files - Input: {:?}, Output: {:?}", 
-                temp_input_path, temp_output_path);
+fn count_to_c_long(index: usize, count: u64) -> Result<c_long, StarcodeError> {
+    validation::check_count(count).map_err(|error| StarcodeError::InvalidEntry { index, error })?;
+    Ok(count as c_long)
+}
+
+// Sequences and counts as starcode takes them, in input order. Repeated sequences are
+// passed as they are, starcode adds up their counts and keeps all their positions.
+struct SequenceCounts {
+    sequences: Vec<CString>,
+    counts: Vec<c_long>,
+}
 
-        // Write sequences to file with error checking
-        if let Err(e) = write_vectors_to_file(temp_input_path, sequences) {
-            return Err(format!("Failed to write sequences to temp file: {}", e));
+impl SequenceCounts {
+    fn new<I, S, C>(input: I, params: &StarcodeParams) -> Result<SequenceCounts, StarcodeError>
+    where
+        I: IntoIterator<Item = (S, C)>,
+        S: AsRef<[u8]>,
+        C: SequenceCount,
+    {
+        let mut sequence_counts = SequenceCounts { sequences: Vec::new(), counts: Vec::new() };
+        for (index, (sequence, count)) in input.into_iter().enumerate() {
+            sequence_counts.sequences.push(sequence_to_cstring(index, sequence.as_ref(), params)?);
+            sequence_counts.counts.push(count_to_c_long(index, count.sequence_count())?);
         }
-        
-        println!("Debug: Wrote sequences to input file");
+        Ok(sequence_counts)
+    }
+}
 
-        unsafe {
-            println!("Debug: Converting paths to C strings");
-            
-            // Use CString properly to avoid memory leaks
-            let input_path_str = match temp_input_path.to_str() {
-                Some(s) => s,
-                None => return Err("Failed to convert input path to string".to_string()),
-            };
-            
-            let output_path_str = match temp_output_path.to_str() {
-                Some(s) => s,
-                None => return Err("Failed to convert output path to string".to_string()),
-            };
-            
-            let input_file_path = match CString::new(input_path_str) {
-                Ok(s) => s,
-                Err(e) => return Err(format!("Failed to create input path CString: {}", e)),
-            };
-            
-            let output_file_path = match CString::new(output_path_str) {
-                Ok(s) => s,
-                Err(e) => return Err(format!("Failed to create output path CString: {}", e)),
-            };
+// Runs starcode with the tidy output on reads held in memory, each with a count of one, and
+// returns the (read, canonical) lines in input order. Mates are passed in 'reads2' if any.
+fn run_starcode_tidy(reads1: &[CString], reads2: Option<&[CString]>, params: &StarcodeParams) -> Result<Vec<ReadPair>, StarcodeError> {
+    let mut read_ptrs1: Vec<*mut c_char> = reads1.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    let mut read_ptrs2: Vec<*mut c_char> = reads2.unwrap_or(&[]).iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    let counts: Vec<c_long> = vec![1; reads1.len()];
 
-            println!("Debug: About to call starcode_helper");
-            
-            // Get raw pointers but don't transfer ownership
-            let input_ptr = input_file_path.as_ptr();
-            let output_ptr = output_file_path.as_ptr();
-            
-            let result = starcode_helper(
-                input_ptr as *mut c_char,
-                output_ptr as *mut c_char,
-                *max_distance,
+    let temp_output_file = NamedTempFile::new()?;
+    let outputf1 = CFile::open(temp_output_file.path(), b"w\0")?;
+
+    let result = run_printing(params, |hooks| unsafe {
+        match reads2 {
+            None => starcode_seqs(
+                read_ptrs1.as_mut_ptr(),
+                counts.as_ptr(),
+                read_ptrs1.len(),
+                outputf1.0,
+                hooks,
+                params.max_distance,
+                params.verbose as i32,
+                params.threads as i32,
+                params.algorithm.as_cluster_t() as i32,
+                params.cluster_ratio,
                 0,
-                1,
                 0,
-                *parent_to_child_ratio,
-                1,
+                output_t_TIDY_OUTPUT as i32
+            ),
+            Some(_) => starcode_pe_seqs(
+                read_ptrs1.as_mut_ptr(),
+                read_ptrs2.as_mut_ptr(),
+                counts.as_ptr(),
+                read_ptrs1.len(),
+                outputf1.0,
+                hooks,
+                params.max_distance,
+                params.verbose as i32,
+                params.threads as i32,
+                params.algorithm.as_cluster_t() as i32,
+                params.cluster_ratio,
                 0,
-                0
-            );
-            
-            println!("Debug: starcode_helper returned {}", result);
-            
-            if result != 0 {
-                return Err(format!("starcode_helper failed with code {}", result));
+                0,
+                output_t_TIDY_OUTPUT as i32
+            ),
+        }
+    });
+    // Flush the output before reading it back
+    drop(outputf1);
+    result?;
+
+    let file = File::open(temp_output_file.path())?;
+    let mut lines = Vec::with_capacity(reads1.len());
+    for line in io::BufReader::new(file).lines() {
+        let line = line?;
+        match line.split_once('\t') {
+            Some((read, canonical)) => lines.push((read.as_bytes().to_vec(), canonical.as_bytes().to_vec())),
+            None => return Err(StarcodeError::MalformedOutput(line)),
+        }
+    }
+    if lines.len() != reads1.len() {
+        return Err(StarcodeError::MalformedOutput(format!("expected {} tidy lines, got {}", reads1.len(), lines.len())));
+    }
+    Ok(lines)
+}
+
+/// Returns the representative record of every cluster of the FASTA, FASTQ or raw file
+/// `input`, with its header and quality. The output mode of `params` is ignored.
+pub fn representatives(params: &StarcodeParams, input: &Path) -> Result<Representatives, StarcodeError> {
+    let temp_output_file = NamedTempFile::new()?;
+    run_starcode_nred(params, vec![input::open_path(input)?], (temp_output_file.path(), None))?;
+    records::read_representatives(io::BufReader::new(File::open(temp_output_file.path())?))
+}
+
+/// Same as `representatives` for a pair of paired-end FASTQ files
+pub fn representative_pairs(params: &StarcodeParams, input1: &Path, input2: &Path) -> Result<Vec<(FastqRecord, FastqRecord)>, StarcodeError> {
+    let temp_output_file1 = NamedTempFile::new()?;
+    let temp_output_file2 = NamedTempFile::new()?;
+    let inputs = vec![input::open_path(input1)?, input::open_path(input2)?];
+    run_starcode_nred(params, inputs, (temp_output_file1.path(), Some(temp_output_file2.path())))?;
+    let records1 = records::read_fastq_records(io::BufReader::new(File::open(temp_output_file1.path())?))?;
+    let records2 = records::read_fastq_records(io::BufReader::new(File::open(temp_output_file2.path())?))?;
+    if records1.len() != records2.len() {
+        return Err(StarcodeError::MalformedOutput(format!(
+            "{} first reads but {} second reads", records1.len(), records2.len())));
+    }
+    Ok(records1.into_iter().zip(records2).collect())
+}
+
+// Runs starcode with the non-redundant output, the second input and output are for the
+// second reads of paired-end input
+fn run_starcode_nred(params: &StarcodeParams, inputs: Vec<input::Input<'_>>, outputs: (&Path, Option<&Path>)) -> Result<(), StarcodeError> {
+    let outputf1 = CFile::open(outputs.0, b"w\0")?;
+    let outputf2 = outputs.1.map(|path| CFile::open(path, b"w\0")).transpose()?;
+
+    input::with_c_streams(inputs, |streams| run_printing(params, |hooks| unsafe {
+        starcode_files(
+            streams[0],
+            streams.get(1).copied().unwrap_or(std::ptr::null_mut()),
+            outputf1.0,
+            outputf2.as_ref().map_or(std::ptr::null_mut(), |f| f.0),
+            hooks,
+            params.max_distance,
+            params.verbose as i32,
+            params.threads as i32,
+            params.algorithm.as_cluster_t() as i32,
+            params.cluster_ratio,
+            0,
+            0,
+            output_t_NRED_OUTPUT as i32
+        )
+    }))
+}
+
+// A C stream, closed (and flushed) when dropped
+struct CFile(*mut FILE);
+
+impl CFile {
+    // 'mode' is NUL-terminated, as fopen expects it
+    fn open(path: &Path, mode: &[u8]) -> Result<CFile, StarcodeError> {
+        let c_path = path_to_cstring(path)?;
+        let file = unsafe { libc::fopen(c_path.as_ptr(), mode.as_ptr() as *const c_char) };
+        if file.is_null() {
+            return Err(StarcodeError::Io(io::Error::new(io::ErrorKind::NotFound,
+                format!("could not open {:?}: {}", path, io::Error::last_os_error()))));
+        }
+        Ok(CFile(file as *mut FILE))
+    }
+}
+
+impl Drop for CFile {
+    fn drop(&mut self) {
+        unsafe { libc::fclose(self.0 as *mut libc::FILE) };
+    }
+}
+
+/// Clusters read pairs held in memory (pair and count) with the given parameters
+pub fn cluster_pairs<I, P, C>(params: &StarcodeParams, input: I) -> Result<Vec<PairedCluster>, StarcodeError>
+where
+    I: IntoIterator<Item = (P, C)>,
+    P: Borrow<MatePair>,
+    C: SequenceCount,
+{
+    check_paired_output(params)?;
+    // The pairs have no headers and no qualities to report
+    if params.output == OutputMode::NonRedundant {
+        return Err(StarcodeError::InvalidParameter("non-redundant output requires fastq input".to_string()));
+    }
+    let mut c_firsts: Vec<CString> = Vec::new();
+    let mut c_seconds: Vec<CString> = Vec::new();
+    let mut counts: Vec<c_long> = Vec::new();
+    for (index, (pair, count)) in input.into_iter().enumerate() {
+        let (first, second) = pair_to_cstrings(index, &pair.borrow().first, &pair.borrow().second, params)?;
+        c_firsts.push(first);
+        c_seconds.push(second);
+        counts.push(count_to_c_long(index, count.sequence_count())?);
+    }
+    if counts.is_empty() {
+        return Err(StarcodeError::EmptyInput);
+    }
+    let mut first_ptrs: Vec<*mut c_char> = c_firsts.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+    let mut second_ptrs: Vec<*mut c_char> = c_seconds.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+
+    let collector = collect_clusters(params, |hooks| unsafe {
+        starcode_pe_seqs(
+            first_ptrs.as_mut_ptr(),
+            second_ptrs.as_mut_ptr(),
+            counts.as_ptr(),
+            first_ptrs.len(),
+            std::ptr::null_mut(),
+            hooks,
+            params.max_distance,
+            params.verbose as i32,
+            params.threads as i32,
+            params.algorithm.as_cluster_t() as i32,
+            params.cluster_ratio,
+            params.show_members as i32,
+            params.show_ids as i32,
+            output_t_DEFAULT_OUTPUT as i32
+        )
+    })?;
+    paired_clusters(params, collector.clusters)
+}
+
+/// Clusters the read pairs of two paired-end FASTQ files with the given parameters
+pub fn cluster_pair_files(params: &StarcodeParams, input1: &Path, input2: &Path) -> Result<Vec<PairedCluster>, StarcodeError> {
+    check_paired_output(params)?;
+    let collector = run_starcode_files(params, vec![input::open_path(input1)?, input::open_path(input2)?])?;
+    paired_clusters(params, collector.clusters)
+}
+
+/// Clusters the sequences of a raw, FASTA or FASTQ file as the starcode command line
+/// does, "-" is the standard input. Gzip files are decompressed.
+pub fn cluster_file(params: &StarcodeParams, path: &Path) -> Result<ClusteredFile, StarcodeError> {
+    cluster_input(params, input::open_path(path)?)
+}
+
+/// Same as `cluster_file` for a stream
+pub fn cluster_reader<R: io::Read + Send>(params: &StarcodeParams, reader: R) -> Result<ClusteredFile, StarcodeError> {
+    cluster_input(params, input::decompressed(reader)?)
+}
+
+fn cluster_input(params: &StarcodeParams, input: input::Input<'_>) -> Result<ClusteredFile, StarcodeError> {
+    if params.output == OutputMode::Tidy {
+        return Err(StarcodeError::InvalidParameter(
+            "tidy output assigns reads, not clusters, use assign_reads instead".to_string()));
+    }
+    let collector = run_starcode_files(params, vec![input])?;
+    // starcode reports the format of any input that it clusters
+    let format = collector.format.ok_or(StarcodeError::Internal)?;
+    Ok(ClusteredFile {
+        format,
+        alignment: StarcodeAlignment::from_exported_clusters(collector.clusters, params),
+    })
+}
+
+// Runs starcode on one input, or two for paired-end reads, and collects the clusters
+fn run_starcode_files(params: &StarcodeParams, inputs: Vec<input::Input<'_>>) -> Result<ClusterCollector, StarcodeError> {
+    input::with_c_streams(inputs, |streams| collect_clusters(params, |hooks| unsafe {
+        starcode_files(
+            streams[0],
+            streams.get(1).copied().unwrap_or(std::ptr::null_mut()),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            hooks,
+            params.max_distance,
+            params.verbose as i32,
+            params.threads as i32,
+            params.algorithm.as_cluster_t() as i32,
+            params.cluster_ratio,
+            params.show_members as i32,
+            params.show_ids as i32,
+            output_t_DEFAULT_OUTPUT as i32
+        )
+    }))
+}
+
+fn check_paired_output(params: &StarcodeParams) -> Result<(), StarcodeError> {
+    match params.output {
+        OutputMode::Tidy => Err(StarcodeError::InvalidParameter(
+            "tidy output assigns reads, not clusters, use assign_read_pairs instead".to_string())),
+        _ => Ok(()),
+    }
+}
+
+// Shapes the exported clusters of read pairs as 'in_memory_align_sequences' does
+fn paired_clusters(params: &StarcodeParams, clusters: Vec<ExportedCluster>) -> Result<Vec<PairedCluster>, StarcodeError> {
+    let keep_members = params.show_members && params.output == OutputMode::Clusters;
+    clusters.into_iter().map(|cluster| {
+        let count = match params.algorithm {
+            ClusterAlgorithm::Spheres => cluster.sphere_size,
+            _ => cluster.count,
+        };
+        let members = if keep_members {
+            cluster.members.into_iter().map(|member| split_pair(&member.sequence)).collect::<Result<_, _>>()?
+        } else {
+            Vec::new()
+        };
+        Ok(PairedCluster {
+            canonical: split_pair(&cluster.canonical)?,
+            count,
+            members,
+        })
+    }).collect()
+}
+
+// starcode joins the mates with a run of '-', which is not a valid DNA character
+fn split_pair(joined: &[u8]) -> Result<MatePair, StarcodeError> {
+    match (joined.iter().position(|b| *b == b'-'), joined.iter().rposition(|b| *b == b'-')) {
+        (Some(start), Some(end)) => Ok(MatePair {
+            first: joined[..start].to_vec(),
+            second: joined[end + 1..].to_vec(),
+        }),
+        _ => Err(StarcodeError::MalformedOutput(String::from_utf8_lossy(joined).into_owned())),
+    }
+}
+
+// Runs starcode on sequences held in memory and returns the clusters in output order
+fn run_starcode_seqs(sequences: &SequenceCounts, params: &StarcodeParams) -> Result<Vec<ExportedCluster>, StarcodeError> {
+    if params.metric == DistanceMetric::Hamming {
+        return Ok(hamming::run(sequences, params, true)?.0);
+    }
+    Ok(run_seqs_with_hooks(sequences, params, true, None, std::ptr::null_mut())?.clusters)
+}
+
+// Same as 'run_starcode_seqs' with the match graph of the sequences, the clusters are empty
+// unless 'cluster' is set
+fn run_starcode_graph(sequences: &SequenceCounts, params: &StarcodeParams, cluster: bool) -> Result<(Vec<ExportedCluster>, MatchGraph), StarcodeError> {
+    if params.metric == DistanceMetric::Hamming {
+        return hamming::run(sequences, params, cluster);
+    }
+    let graph = MatchGraph::new(sequences.sequences.iter().zip(&sequences.counts)
+        .map(|(sequence, &count)| (sequence.to_bytes(), count as u64)));
+    let collector = run_seqs_with_hooks(sequences, params, cluster, Some(EdgeCollector::new(graph)), std::ptr::null_mut())?;
+    let graph = collector.edges.map(EdgeCollector::into_graph).ok_or(StarcodeError::Internal)?;
+    Ok((collector.clusters, graph))
+}
+
+// The allocations of the run go to 'context' unless it is null
+fn run_seqs_with_hooks(sequences: &SequenceCounts, params: &StarcodeParams, collect: bool, edges: Option<EdgeCollector>, context: *mut starcode_context_t) -> Result<ClusterCollector, StarcodeError> {
+    if sequences.counts.is_empty() {
+        return Err(StarcodeError::EmptyInput);
+    }
+    // starcode only borrows the sequences, they are owned by 'sequences'
+    let mut sequence_ptrs: Vec<*mut c_char> = sequences.sequences.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+
+    run_with_hooks(params, collect, edges, |hooks| unsafe {
+        (*hooks).context = context;
+        starcode_seqs(
+            sequence_ptrs.as_mut_ptr(),
+            sequences.counts.as_ptr(),
+            sequence_ptrs.len(),
+            std::ptr::null_mut(),
+            hooks,
+            params.max_distance,
+            params.verbose as i32,
+            params.threads as i32,
+            params.algorithm.as_cluster_t() as i32,
+            params.cluster_ratio,
+            params.show_members as i32,
+            params.show_ids as i32,
+            output_t_DEFAULT_OUTPUT as i32
+        )
+    })
+}
+
+// Calls 'run' with hooks that collect the clusters, 'run' returns the starcode status.
+// The clusters are passed to the hook, which only sees the default output.
+fn collect_clusters<F: FnOnce(*mut starcode_hooks_t) -> i32>(params: &StarcodeParams, run: F) -> Result<ClusterCollector, StarcodeError> {
+    run_with_hooks(params, true, None, run)
+}
+
+// Same as 'collect_clusters' for the runs whose output starcode prints
+fn run_printing<F: FnOnce(*mut starcode_hooks_t) -> i32>(params: &StarcodeParams, run: F) -> Result<(), StarcodeError> {
+    run_with_hooks(params, false, None, run).map(|_| ())
+}
+
+// The hooks also stop the run when the cancellation token or the time limit of 'params' say so,
+// report its progress and pass the messages of starcode to 'tracing'. With 'edges', the pairs
+// found by the query go there, and the run stops after the query unless 'collect' is set.
+fn run_with_hooks<F: FnOnce(*mut starcode_hooks_t) -> i32>(params: &StarcodeParams, collect: bool, edges: Option<EdgeCollector>, run: F) -> Result<ClusterCollector, StarcodeError> {
+    // starcode itself only searches by Levenshtein distance
+    if params.metric != DistanceMetric::Levenshtein {
+        return Err(StarcodeError::InvalidParameter(
+            "the Hamming distance only applies to sequences held in memory, clustered or matched".to_string()));
+    }
+    let run_span = tracing::info_span!("starcode", max_distance = params.max_distance, threads = params.threads);
+    let _entered = run_span.enter();
+    let mut collector = ClusterCollector::new(Cancellation::start(params), params.progress.clone());
+    let collect_edges = edges.is_some();
+    collector.edges = edges;
+    let mut hooks = starcode_hooks_t {
+        data: &mut collector as *mut ClusterCollector as *mut c_void,
+        member: if collect { Some(collect_member) } else { None },
+        format: Some(collect_format),
+        cancelled: Some(run_cancelled),
+        progress: Some(report_progress),
+        log: Some(forward_log),
+        edge: if collect_edges { Some(collect_edge) } else { None },
+        context: std::ptr::null_mut(),
+    };
+    status_to_result(run(&mut hooks))?;
+    Ok(collector)
+}
+
+impl StarcodeAlignment {
+    fn in_memory_align_sequences<I, S, C>(
+        sequences: I,
+        params: &StarcodeParams,
+    ) -> Result<StarcodeAlignment, StarcodeError>
+    where
+        I: IntoIterator<Item = (S, C)>,
+        S: AsRef<[u8]>,
+        C: SequenceCount,
+    {
+        let clusters = run_starcode_seqs(&SequenceCounts::new(sequences, params)?, params)?;
+        Ok(Self::from_exported_clusters(clusters, params))
+    }
+
+    fn from_exported_clusters(clusters: Vec<ExportedCluster>, params: &StarcodeParams) -> StarcodeAlignment {
+        // The non-redundant output is the canonicals of the same clusters.
+        let keep_members = params.show_members && params.output == OutputMode::Clusters;
+        let keep_ids = params.show_ids && params.output == OutputMode::Clusters;
+
+        let mut alignment = StarcodeAlignment {
+            cluster_centers: Vec::with_capacity(clusters.len()),
+            cluster_count: Vec::with_capacity(clusters.len()),
+            cluster_members: Vec::with_capacity(clusters.len()),
+            cluster_member_ids: Vec::with_capacity(clusters.len()),
+        };
+        for cluster in clusters {
+            // Spheres keep the counts of the sequences and report the size apart
+            let count = match params.algorithm {
+                ClusterAlgorithm::Spheres => cluster.sphere_size,
+                _ => cluster.count,
+            };
+            let mut ids: Vec<u32> = Vec::new();
+            if keep_ids {
+                ids.extend(cluster.members.iter().flat_map(|member| member.ids.iter().copied()));
+                ids.sort_unstable();
             }
+            let members = if keep_members {
+                cluster.members.into_iter().map(|member| member.sequence).collect()
+            } else {
+                Vec::new()
+            };
+            alignment.cluster_centers.push(cluster.canonical);
+            alignment.cluster_count.push(count);
+            alignment.cluster_members.push(members);
+            alignment.cluster_member_ids.push(ids);
         }
+        alignment
+    }
 
-        //println!("Debug: Reading results from output file {}", temp_output_path.to_str().unwrap());
-        Ok(recover_cluster_entries_from_file(temp_output_path.to_str().unwrap()))
+    pub fn try_align_sequences<I, S, C>(
+        sequences: I,
+        max_distance: &i32,
+        parent_to_child_ratio: &f64,
+    ) -> Result<StarcodeAlignment, StarcodeError>
+    where
+        I: IntoIterator<Item = (S, C)>,
+        S: AsRef<[u8]>,
+        C: SequenceCount,
+    {
+        let mut sequences = sequences.into_iter().peekable();
+        if sequences.peek().is_none() {
+            return Err(StarcodeError::EmptyInput);
+        }
+        let params = StarcodeParams::builder()
+            .max_distance(*max_distance)
+            .cluster_ratio(*parent_to_child_ratio)
+            .build()?;
+        cluster(&params, sequences)
     }
 
-    pub fn align_sequences(
-        sequences: &FxHashMap<Vec<u8>,usize>,
+    pub fn align_sequences<I, S, C>(
+        sequences: I,
         max_distance: &i32,
         parent_to_child_ratio: &f64,
-    ) -> StarcodeAlignment {
-        match Self::debug_align_sequences(sequences, max_distance, parent_to_child_ratio) {
+    ) -> StarcodeAlignment
+    where
+        I: IntoIterator<Item = (S, C)>,
+        S: AsRef<[u8]>,
+        C: SequenceCount,
+    {
+        match Self::try_align_sequences(sequences, max_distance, parent_to_child_ratio) {
             Ok(alignment) => alignment,
             Err(e) => panic!("Alignment failed: {}", e),
         }
     }
 }
 
-fn split_line(line: &str) -> (Vec<u8>, Vec<u8>, Option<Vec<Vec<u8>>>) {
-    let tokens: Vec<&str> = line.split_whitespace().collect();
-
-    let first = tokens.get(0).unwrap_or(&"").as_bytes().to_vec();
-    let second = tokens.get(1).unwrap_or(&"").as_bytes().to_vec();
+fn path_to_cstring(path: &Path) -> Result<CString, StarcodeError> {
+    let path_str = path.to_str().ok_or_else(|| io::Error::new(
+        io::ErrorKind::InvalidInput, format!("path is not valid UTF-8: {:?}", path)))?;
+    CString::new(path_str).map_err(|e| StarcodeError::Io(io::Error::new(io::ErrorKind::InvalidInput, e)))
+}
 
-    if tokens.len() > 2 {
-        let third_token = tokens[2];
-        let third_list: Vec<Vec<u8>> = third_token.split(',').map(|s| s.as_bytes().to_vec()).collect();
-        (first, second, Some(third_list))
-    } else {
-        (first, second, None)
-    }
+// Accumulates the clusters that starcode passes to the 'member' hook. Members of
+// a cluster arrive consecutively, so a new canonical pointer starts a new cluster.
+struct ClusterCollector {
+    clusters: Vec<ExportedCluster>,
+    last_canonical: *const useq_t,
+    format: Option<InputFormat>,
+    // read by the query threads as well
+    cancellation: Cancellation,
+    progress: Option<ProgressCallback>,
+    // span of the current stage, the messages of the query threads go there too
+    stage_span: Mutex<Span>,
+    // the pairs found by the query, when the match graph is asked for
+    edges: Option<EdgeCollector>,
 }
 
+// A cluster as starcode exports it, before it is shaped for the caller
+struct ExportedCluster {
+    canonical: Vec<u8>,
+    // 'count' of the canonical, which is the cluster count except for spheres
+    count: usize,
+    // 'sphere_c' of the canonical (spheres only)
+    sphere_size: usize,
+    members: Vec<ExportedMember>,
+}
 
+struct ExportedMember {
+    sequence: Vec<u8>,
+    count: usize,
+    // 'sphere_d' of the member (spheres only)
+    sphere_distance: usize,
+    // 0-based positions of the sequence in the input ('seqid' is 1-based)
+    ids: Vec<u32>,
+}
 
-fn recover_cluster_entries_from_file(file_path: &str) -> StarcodeAlignment {
+impl ClusterCollector {
+    fn new(cancellation: Cancellation, progress: Option<ProgressCallback>) -> Self {
+        ClusterCollector {
+            clusters: Vec::new(),
+            last_canonical: std::ptr::null(),
+            format: None,
+            cancellation,
+            progress,
+            stage_span: Mutex::new(Span::none()),
+            edges: None,
+        }
+    }
+}
 
-    let mut cluster_centers: Vec<Vec<u8>> = Vec::new();
-    let mut cluster_count: Vec<usize> = Vec::new();
-    let mut cluster_members: Vec<Vec<Vec<u8>>> = Vec::new();
+unsafe extern "C" fn collect_format(data: *mut c_void, format: format_t) {
+    let collector = &mut *(data as *mut ClusterCollector);
+    collector.format = InputFormat::from_format_t(format);
+}
 
-    // Open the file
-    let file = File::open(file_path).expect("Unable to open starcode clustering output");
+// Called from the query threads while the collector is not otherwise in use
+unsafe extern "C" fn run_cancelled(data: *mut c_void) -> c_int {
+    let collector = &*(data as *const ClusterCollector);
+    collector.cancellation.is_cancelled() as c_int
+}
 
-    // Create a buffered reader
-    let reader = io::BufReader::new(file);
+unsafe extern "C" fn report_progress(data: *mut c_void, event: progress_t, stage: stage_t, count: c_long, total: c_long) {
+    let collector = &*(data as *const ClusterCollector);
+    let progress = match Progress::from_c(event, stage, count, total) {
+        Some(progress) => progress,
+        None => return,
+    };
+    match progress {
+        Progress::Started(stage) => *collector.stage_span.lock().unwrap_or_else(PoisonError::into_inner) = stage.span(),
+        Progress::Finished(_) => *collector.stage_span.lock().unwrap_or_else(PoisonError::into_inner) = Span::none(),
+        _ => {}
+    }
+    if let Some(callback) = &collector.progress {
+        callback.call(progress);
+    }
+}
 
-    // Iterate over each line in the file
-    for line in reader.lines() {
-        // Print each line
-        let line = line.unwrap();
-        //println!("{}", &line.clone());
-        let spt = split_line(line.as_str());
-        assert_ne!(spt.0.len(),0);
-        assert_ne!(spt.0.len(),0);
-        cluster_centers.push(spt.0.clone());
-        cluster_count.push(String::from_utf8(spt.1).unwrap().parse().unwrap());
-        match spt.2 {
-            None => {
-                cluster_members.push(Vec::new());
-            }
-            Some(x) => {
-                cluster_members.push(x);
-            }
-        }
+// Called from the query threads as well
+unsafe extern "C" fn forward_log(data: *mut c_void, level: log_level_t, message: *const c_char) {
+    let collector = &*(data as *const ClusterCollector);
+    let message = CStr::from_ptr(message).to_string_lossy();
+    let span = collector.stage_span.lock().unwrap_or_else(PoisonError::into_inner).clone();
+    let _entered = span.enter();
+    match level {
+        log_level_t_LOG_ERROR => tracing::error!("{}", message),
+        log_level_t_LOG_WARNING => tracing::warn!("{}", message),
+        log_level_t_LOG_INFO => tracing::info!("{}", message),
+        _ => tracing::debug!("{}", message),
+    }
+}
 
+// Called from the query threads, the collector locks the edges
+unsafe extern "C" fn collect_edge(data: *mut c_void, query: *const useq_t, target: *const useq_t, dist: c_int) {
+    let collector = &*(data as *const ClusterCollector);
+    if let Some(edges) = &collector.edges {
+        edges.push(CStr::from_ptr((*query).seq).to_bytes(), CStr::from_ptr((*target).seq).to_bytes(), dist as usize);
     }
+}
 
-    StarcodeAlignment{
-        cluster_centers,
-        cluster_count,
-        cluster_members,
+unsafe extern "C" fn collect_member(data: *mut c_void, canonical: *const useq_t, member: *const useq_t) {
+    let collector = &mut *(data as *mut ClusterCollector);
+
+    if canonical != collector.last_canonical {
+        collector.last_canonical = canonical;
+        collector.clusters.push(ExportedCluster {
+            canonical: CStr::from_ptr((*canonical).seq).to_bytes().to_vec(),
+            count: (*canonical).count as usize,
+            sphere_size: (*canonical).sphere_c as usize,
+            members: Vec::new(),
+        });
+    }
+    if let Some(cluster) = collector.clusters.last_mut() {
+        cluster.members.push(ExportedMember {
+            sequence: CStr::from_ptr((*member).seq).to_bytes().to_vec(),
+            count: (*member).count as usize,
+            sphere_distance: (*member).sphere_d as usize,
+            ids: (0..(*member).nids as usize).map(|i| (*(*member).seqid.add(i) - 1) as u32).collect(),
+        });
     }
 }
+
 #[allow(dead_code)]
 fn print_lines_from_file(file_path: &str) -> io::Result<()> {
     // Open the file
@@ -272,11 +1055,140 @@ fn print_lines_from_file(file_path: &str) -> io::Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use rustc_hash::FxHashMap;
+    use std::fs::OpenOptions;
+    use std::io::Write;
     use rand::Rng;
     use rand::prelude::IndexedRandom;
-    use std::time::Duration;
+    use std::time::{Duration, Instant};
     use std::thread;
     use std::panic;
+    use std::process::Command;
+    use std::sync::{Arc, Mutex};
+
+    // Fault injection of starcode.c, left out of starcode.h and the bindings: the n-th next
+    // operation of the calling thread that can fail with 'status' fails with it
+    #[cfg(feature = "fault-injection")]
+    extern "C" {
+        fn starcode_inject_fault(status: c_int, n: c_int);
+    }
+
+    // Canonical, count, members and ids of a line of the default output
+    type OutputLine<'a> = (Vec<u8>, Vec<u8>, Option<Vec<Vec<u8>>>, Option<&'a str>);
+
+    // The members and the ids are both optional, the ids are the only column made of digits.
+    fn split_line(line: &str) -> OutputLine<'_> {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+
+        let first = tokens.get(0).unwrap_or(&"").as_bytes().to_vec();
+        let second = tokens.get(1).unwrap_or(&"").as_bytes().to_vec();
+
+        let (members, ids) = match &tokens[tokens.len().min(2)..] {
+            [] => (None, None),
+            [third] if is_id_list(third) => (None, Some(*third)),
+            [third] => (Some(*third), None),
+            [third, fourth, ..] => (Some(*third), Some(*fourth)),
+        };
+        let members = members.map(|token| token.split(',').map(|s| s.as_bytes().to_vec()).collect());
+        (first, second, members, ids)
+    }
+
+    fn is_id_list(token: &str) -> bool {
+        token.bytes().all(|b| b.is_ascii_digit() || b == b',')
+    }
+
+
+
+    fn recover_cluster_entries<R: BufRead>(reader: R) -> Result<StarcodeAlignment, StarcodeError> {
+
+        let mut cluster_centers: Vec<Vec<u8>> = Vec::new();
+        let mut cluster_count: Vec<usize> = Vec::new();
+        let mut cluster_members: Vec<Vec<Vec<u8>>> = Vec::new();
+        let mut cluster_member_ids: Vec<Vec<u32>> = Vec::new();
+
+        // Iterate over each line in the file
+        for line in reader.lines() {
+            let line = line?;
+            let spt = split_line(line.as_str());
+            if spt.0.is_empty() {
+                return Err(StarcodeError::MalformedOutput(line));
+            }
+            let count = match std::str::from_utf8(&spt.1).ok().and_then(|c| c.parse().ok()) {
+                Some(count) => count,
+                None => return Err(StarcodeError::MalformedOutput(line)),
+            };
+            cluster_centers.push(spt.0);
+            cluster_count.push(count);
+            match spt.2 {
+                None => {
+                    cluster_members.push(Vec::new());
+                }
+                Some(x) => {
+                    cluster_members.push(x);
+                }
+            }
+            // starcode numbers the input lines from 1
+            let ids = match spt.3 {
+                None => Vec::new(),
+                Some(ids) => match ids.split(',').map(|id| id.parse::<u32>().ok().and_then(|id| id.checked_sub(1))).collect() {
+                    Some(ids) => ids,
+                    None => return Err(StarcodeError::MalformedOutput(line)),
+                },
+            };
+            cluster_member_ids.push(ids);
+
+        }
+
+        Ok(StarcodeAlignment{
+            cluster_centers,
+            cluster_count,
+            cluster_members,
+            cluster_member_ids,
+        })
+    }
+
+    fn write_vectors_to_file(filename: &Path, vectors: &FxHashMap<Vec<u8>,usize>) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(filename)?;
+        for data in vectors {
+            file.write_all(&data.0)?;
+            file.write_all(b"\t")?;
+            file.write_all(data.1.to_string().as_bytes())?;
+            file.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    // The clusters of 'sequences' through starcode's file path: the sequences are written to
+    // a file, clustered from it and the output parsed back. The in-memory entry points must
+    // give the same clusters.
+    fn file_round_trip(sequences: &FxHashMap<Vec<u8>,usize>, params: &StarcodeParams) -> Result<StarcodeAlignment, StarcodeError> {
+        let input_file = NamedTempFile::new()?;
+        let output_file = NamedTempFile::new()?;
+        write_vectors_to_file(input_file.path(), sequences)?;
+
+        let outputf1 = CFile::open(output_file.path(), b"w\0")?;
+        let result = input::with_c_streams(vec![input::open_path(input_file.path())?], |streams| run_printing(params, |hooks| unsafe {
+            starcode_files(
+                streams[0],
+                std::ptr::null_mut(),
+                outputf1.0,
+                std::ptr::null_mut(),
+                hooks,
+                params.max_distance,
+                params.verbose as i32,
+                params.threads as i32,
+                params.algorithm.as_cluster_t() as i32,
+                params.cluster_ratio,
+                params.show_members as i32,
+                params.show_ids as i32,
+                output_t_DEFAULT_OUTPUT as i32
+            )
+        }));
+        // Flush the output before reading it back
+        drop(outputf1);
+        result?;
+        recover_cluster_entries(io::BufReader::new(File::open(output_file.path())?))
+    }
 
     fn generate_random_nucleotide_sequence(length: usize) -> (Vec<u8>,usize) {
         let nucleotides = b"ATCG";
@@ -328,7 +1240,1776 @@ mod tests {
 
     }
 
-    // Add this helper function for controlled test execution
+    #[test]
+    fn test_in_memory_matches_file_round_trip() {
+        let sequences = random_10mers();
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).build().unwrap();
+        let in_memory = StarcodeAlignment::in_memory_align_sequences(&sequences, &params).unwrap();
+        let from_file = file_round_trip(&sequences, &params).unwrap();
+
+        assert_eq!(in_memory.cluster_centers, from_file.cluster_centers);
+        assert_eq!(in_memory.cluster_count, from_file.cluster_count);
+        assert_eq!(in_memory.cluster_members, from_file.cluster_members);
+    }
+
+    #[test]
+    fn test_cluster_member_ids() {
+        let sequences = random_10mers();
+        let positions: FxHashMap<&Vec<u8>, u32> = sequences.keys().enumerate().map(|(i, s)| (s, i as u32)).collect();
+        for algorithm in [ClusterAlgorithm::MessagePassing, ClusterAlgorithm::Spheres, ClusterAlgorithm::ConnectedComponents] {
+            let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).algorithm(algorithm).show_ids(true).build().unwrap();
+            let in_memory = StarcodeAlignment::in_memory_align_sequences(&sequences, &params).unwrap();
+            let from_file = file_round_trip(&sequences, &params).unwrap();
+            assert_eq!(in_memory.cluster_member_ids, from_file.cluster_member_ids, "{:?}", algorithm);
+
+            // The ids are the positions of the members in the input
+            for (members, ids) in in_memory.cluster_members.iter().zip(&in_memory.cluster_member_ids) {
+                let mut expected: Vec<u32> = members.iter().map(|member| positions[member]).collect();
+                expected.sort_unstable();
+                assert_eq!(ids, &expected, "{:?}", algorithm);
+            }
+
+            // Without the members, the ids are the third column of the output
+            let params = StarcodeParams { show_members: false, ..params };
+            let from_file = file_round_trip(&sequences, &params).unwrap();
+            assert!(from_file.cluster_members.iter().all(|members| members.is_empty()));
+            assert_eq!(in_memory.cluster_member_ids, from_file.cluster_member_ids, "{:?}", algorithm);
+        }
+
+        let params = StarcodeParams::builder().max_distance(2).build().unwrap();
+        let alignment = cluster(&params, &sequences).unwrap();
+        assert!(alignment.cluster_member_ids.iter().all(|ids| ids.is_empty()));
+    }
+
+    #[test]
+    fn test_in_memory_counts() {
+        let mut knowns: FxHashMap<Vec<u8>,usize> = FxHashMap::default();
+        knowns.insert("AAAAAAAAAA".as_bytes().to_vec(),10);
+        knowns.insert("AAAAAAAAAC".as_bytes().to_vec(),2);
+        knowns.insert("GGGGGGGGGG".as_bytes().to_vec(),5);
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).build().unwrap();
+        let alignment = StarcodeAlignment::in_memory_align_sequences(&knowns, &params).unwrap();
+
+        assert_eq!(alignment.cluster_centers, vec![b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec()]);
+        assert_eq!(alignment.cluster_count, vec![12, 5]);
+        assert_eq!(alignment.cluster_members[0], vec![b"AAAAAAAAAA".to_vec(), b"AAAAAAAAAC".to_vec()]);
+    }
+
+    #[test]
+    fn test_exported_clusters_partition_input() {
+        let sequences = random_10mers();
+        let alignment = StarcodeAlignment::align_sequences(&sequences, &3, &1.0);
+
+        let mut seen: Vec<Vec<u8>> = alignment.cluster_members.iter().flatten().cloned().collect();
+        seen.sort();
+        let mut expected: Vec<Vec<u8>> = sequences.keys().cloned().collect();
+        expected.sort();
+        assert_eq!(seen, expected);
+        assert_eq!(alignment.cluster_count.iter().sum::<usize>(), sequences.values().sum::<usize>());
+    }
+
+    #[test]
+    fn test_generic_inputs() {
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).show_ids(true).build().unwrap();
+        let expected_centers = vec![b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec()];
+
+        // Repeated sequences are merged and keep all their positions
+        let reads: Vec<(String, u64)> = vec![
+            ("AAAAAAAAAA".to_string(), 3),
+            ("GGGGGGGGGG".to_string(), 1),
+            ("AAAAAAAAAT".to_string(), 1),
+            ("AAAAAAAAAA".to_string(), 2),
+        ];
+        let alignment = cluster(&params, reads.iter().map(|(s, c)| (s, c))).unwrap();
+        assert_eq!(alignment.cluster_centers, expected_centers);
+        assert_eq!(alignment.cluster_count, vec![6, 1]);
+        assert_eq!(alignment.cluster_member_ids, vec![vec![0, 2, 3], vec![1]]);
+        assert_eq!(cluster(&params, reads.clone()).unwrap().cluster_count, vec![6, 1]);
+
+        let mut counts: std::collections::BTreeMap<&str, u32> = std::collections::BTreeMap::new();
+        counts.insert("AAAAAAAAAA", 5);
+        counts.insert("AAAAAAAAAT", 1);
+        counts.insert("GGGGGGGGGG", 1);
+        let alignment = cluster(&params, &counts).unwrap();
+        assert_eq!(alignment.cluster_centers, expected_centers);
+        assert_eq!(alignment.cluster_count, vec![6, 1]);
+
+        let spheres = cluster_spheres(&params, [(&b"AAAAAAAAAA"[..], 2u8), (&b"AAAAAAAAAA"[..], 2u8), (&b"AAAAAAAAAT"[..], 1u8)]).unwrap();
+        assert_eq!(spheres.len(), 1);
+        assert_eq!(spheres[0].size, 5);
+
+        let empty: Vec<(String, u64)> = Vec::new();
+        assert!(matches!(cluster(&params, empty.clone()), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(cluster_components(&params, empty), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(cluster(&params, vec![("AAAAAAAAAA", 1), ("AAAAAAAAAA", u64::MAX)]),
+            Err(StarcodeError::InvalidEntry { index: 1, error: EntryError::CountTooLarge { .. } })));
+    }
+
+    #[test]
+    fn test_entry_validation() {
+        let params = StarcodeParams::builder().max_distance(1).build().unwrap();
+        let entries: Vec<(&str, u32)> = vec![("ACGTN", 1), ("acgtn", 1)];
+        assert!(cluster(&params, entries).is_ok());
+
+        for (entry, expected) in [
+            (("", 1), EntryError::Empty),
+            (("ACGT ACGT", 1), EntryError::InvalidCharacter { position: 4, character: b' ' }),
+            (("ACGT\n", 1), EntryError::InvalidCharacter { position: 4, character: b'\n' }),
+            (("ACGU", 1), EntryError::InvalidCharacter { position: 3, character: b'U' }),
+            (("ACGT", 0), EntryError::ZeroCount),
+        ] {
+            let entries: Vec<(&str, u32)> = vec![("ACGTACGT", 2), entry];
+            match cluster(&params, entries) {
+                Err(StarcodeError::InvalidEntry { index, error }) => {
+                    assert_eq!(index, 1);
+                    assert_eq!(error, expected);
+                }
+                _ => panic!("{:?} was not rejected", entry),
+            }
+        }
+        let too_long = "A".repeat(MAXBRCDLEN as usize + 1);
+        assert!(matches!(cluster(&params, vec![(too_long.as_str(), 1u32)]),
+            Err(StarcodeError::InvalidEntry { index: 0, error: EntryError::TooLong { length } }) if length == MAXBRCDLEN as usize + 1));
+        assert_eq!(format!("{}", StarcodeError::InvalidEntry { index: 3, error: EntryError::ZeroCount }), "input entry 3: count is zero");
+
+        // Normalization is opt-in
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).normalize(true).build().unwrap();
+        let alignment = cluster(&params, vec![("ACGUACGU", 3u32), ("acgtacgt", 2), ("ACGTACGA", 1)]).unwrap();
+        assert_eq!(alignment.cluster_centers, vec![b"ACGTACGT".to_vec()]);
+        assert_eq!(alignment.cluster_count, vec![6]);
+        assert_eq!(assign_reads(&params, &["ugca", "UGCA"]).unwrap(), vec![b"TGCA".to_vec(), b"TGCA".to_vec()]);
+        assert!(matches!(cluster(&params, vec![("ACGX", 1u32)]),
+            Err(StarcodeError::InvalidEntry { index: 0, error: EntryError::InvalidCharacter { position: 3, character: b'X' } })));
+    }
+
+    #[test]
+    fn test_try_align_sequences_errors() {
+        let empty: FxHashMap<Vec<u8>,usize> = FxHashMap::default();
+        assert!(matches!(StarcodeAlignment::try_align_sequences(&empty, &2, &2.0), Err(StarcodeError::EmptyInput)));
+
+        let sequences = random_10mers();
+        assert!(matches!(StarcodeAlignment::try_align_sequences(&sequences, &9, &2.0), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(StarcodeAlignment::try_align_sequences(&sequences, &2, &0.5), Err(StarcodeError::InvalidParameter(_))));
+
+        let mut with_nul: FxHashMap<Vec<u8>,usize> = FxHashMap::default();
+        with_nul.insert(b"AAAA\0AAAA".to_vec(), 1);
+        assert!(matches!(StarcodeAlignment::try_align_sequences(&with_nul, &2, &2.0),
+            Err(StarcodeError::InvalidEntry { index: 0, error: EntryError::InvalidCharacter { position: 4, character: 0 } })));
+    }
+
+    #[test]
+    fn test_recover_cluster_entries_malformed() {
+        let alignment = recover_cluster_entries("AAAA\t3\tAAAA,AAAC\n".as_bytes()).unwrap();
+        assert_eq!(alignment.cluster_count, vec![3]);
+        assert_eq!(alignment.cluster_member_ids, vec![Vec::<u32>::new()]);
+
+        let alignment = recover_cluster_entries("AAAA\t3\tAAAA,AAAC\t1,2,4\nCCCC\t1\t3\n".as_bytes()).unwrap();
+        assert_eq!(alignment.cluster_members, vec![vec![b"AAAA".to_vec(), b"AAAC".to_vec()], vec![]]);
+        assert_eq!(alignment.cluster_member_ids, vec![vec![0, 1, 3], vec![2]]);
+        assert!(matches!(recover_cluster_entries("AAAA\t3\tAAAA\t0\n".as_bytes()), Err(StarcodeError::MalformedOutput(_))));
+        assert!(matches!(recover_cluster_entries("AAAA\t3\tAAAA\t1,x\n".as_bytes()), Err(StarcodeError::MalformedOutput(_))));
+
+        assert!(matches!(recover_cluster_entries("AAAA\tmany\n".as_bytes()), Err(StarcodeError::MalformedOutput(_))));
+        assert!(matches!(recover_cluster_entries("AAAA\n".as_bytes()), Err(StarcodeError::MalformedOutput(_))));
+        assert!(matches!(recover_cluster_entries("\n".as_bytes()), Err(StarcodeError::MalformedOutput(_))));
+    }
+
+    #[test]
+    fn test_params_builder_validation() {
+        let params = StarcodeParams::builder().build().unwrap();
+        assert_eq!(params, StarcodeParams::default());
+        assert_eq!(params.max_distance(), -1);
+        assert_eq!(params.algorithm(), ClusterAlgorithm::MessagePassing);
+
+        for tau in [-1, 0, STARCODE_MAX_TAU as i32] {
+            assert!(StarcodeParams::builder().max_distance(tau).build().is_ok());
+        }
+        for tau in [-2, STARCODE_MAX_TAU as i32 + 1] {
+            assert!(matches!(StarcodeParams::builder().max_distance(tau).build(), Err(StarcodeError::InvalidParameter(_))));
+        }
+        assert!(matches!(StarcodeParams::builder().threads(0).build(), Err(StarcodeError::InvalidParameter(_))));
+        // 'run_plan()' counts ntries * (ntries + 1) / 2 jobs in an int, starting from that product
+        let ntries = |threads: i64| 3 * threads + (threads % 2 == 0) as i64;
+        let max_threads = STARCODE_MAX_THREADS as usize;
+        assert_eq!(StarcodeParams::builder().threads(max_threads).build().unwrap().threads(), max_threads);
+        assert!(ntries(max_threads as i64) * (ntries(max_threads as i64) + 1) <= i32::MAX as i64);
+        assert!(matches!(StarcodeParams::builder().threads(max_threads + 1).build(),
+            Err(StarcodeError::InvalidParameter(message)) if message == format!("threads must be between 1 and {}, got {}", max_threads, max_threads + 1)));
+        assert!(ntries(max_threads as i64 + 1) * (ntries(max_threads as i64 + 1) + 1) > i32::MAX as i64);
+        assert!(matches!(StarcodeParams::builder().cluster_ratio(f64::NAN).build(), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(StarcodeParams::builder().cluster_ratio(0.99).build(), Err(StarcodeError::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn test_cluster_with_params() {
+        let sequences = small_input();
+
+        // Auto mode picks tau = 2 for 10-mers.
+        let params = StarcodeParams::builder().cluster_ratio(2.0).build().unwrap();
+        let alignment = cluster(&params, &sequences).unwrap();
+        assert_eq!(alignment.cluster_centers, vec![b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec()]);
+        assert_eq!(alignment.cluster_count, vec![11, 3]);
+        assert_eq!(alignment.cluster_members[0], vec![b"AAAAAAAAAA".to_vec(), b"AAAAAAAAAC".to_vec()]);
+
+        // A ratio above 10 keeps the 1-count sequence apart.
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(11.0).build().unwrap();
+        assert_eq!(cluster(&params, &sequences).unwrap().cluster_centers.len(), 3);
+
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).show_members(false).build().unwrap();
+        let alignment = cluster(&params, &sequences).unwrap();
+        assert_eq!(alignment.cluster_count, vec![11, 3]);
+        assert!(alignment.cluster_members.iter().all(|members| members.is_empty()));
+
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).output(OutputMode::NonRedundant).build().unwrap();
+        let alignment = cluster(&params, &sequences).unwrap();
+        assert_eq!(alignment.cluster_centers, vec![b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec()]);
+        assert!(alignment.cluster_members.iter().all(|members| members.is_empty()));
+
+        let params = StarcodeParams::builder().output(OutputMode::Tidy).build().unwrap();
+        assert!(matches!(cluster(&params, &sequences), Err(StarcodeError::InvalidParameter(_))));
+
+        let sequences = random_10mers();
+        let params = StarcodeParams::builder().max_distance(2).threads(4).build().unwrap();
+        assert_eq!(cluster(&params, &sequences).unwrap().cluster_count.iter().sum::<usize>(), sequences.values().sum::<usize>());
+    }
+
+    #[test]
+    fn test_context_runs() {
+        let sequences = random_10mers();
+        let mut context = StarcodeContext::new();
+        for algorithm in [ClusterAlgorithm::MessagePassing, ClusterAlgorithm::Spheres, ClusterAlgorithm::ConnectedComponents] {
+            let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).algorithm(algorithm).show_ids(true).build().unwrap();
+            let expected = cluster(&params, &sequences).unwrap();
+            // The next run frees the allocations of the previous one
+            for _ in 0..2 {
+                let alignment = context.cluster(&params, &sequences).unwrap();
+                assert_eq!(alignment.cluster_centers, expected.cluster_centers, "{:?}", algorithm);
+                assert_eq!(alignment.cluster_count, expected.cluster_count, "{:?}", algorithm);
+                assert_eq!(alignment.cluster_members, expected.cluster_members, "{:?}", algorithm);
+                assert_eq!(alignment.cluster_member_ids, expected.cluster_member_ids, "{:?}", algorithm);
+            }
+        }
+        context.clear();
+        context.clear();
+
+        // The context outlives the runs that fail
+        let params = StarcodeParams::builder().max_distance(2).build().unwrap();
+        let mut invalid = small_input();
+        invalid.insert(b"ACGTX".to_vec(), 1);
+        assert!(matches!(context.cluster(&params, &invalid), Err(StarcodeError::InvalidEntry { .. })));
+        assert!(matches!(context.cluster(&params, &FxHashMap::<Vec<u8>,usize>::default()), Err(StarcodeError::EmptyInput)));
+        let token = CancellationToken::new();
+        token.cancel();
+        let cancelled = StarcodeParams::builder().max_distance(2).cancellation_token(token).build().unwrap();
+        assert!(matches!(context.cluster(&cancelled, &sequences), Err(StarcodeError::Cancelled)));
+        assert_eq!(context.cluster(&params, &small_input()).unwrap().cluster_count, vec![11, 3]);
+
+        let params = StarcodeParams::builder().output(OutputMode::Tidy).build().unwrap();
+        assert!(matches!(context.cluster(&params, &sequences), Err(StarcodeError::InvalidParameter(_))));
+        let params = StarcodeParams::builder().metric(DistanceMetric::Hamming).build().unwrap();
+        assert!(matches!(context.cluster(&params, &sequences), Err(StarcodeError::InvalidParameter(_))));
+
+        // A context that never ran has nothing to free
+        drop(StarcodeContext::new());
+    }
+
+    #[test]
+    fn test_tidy_output_goes_to_output_file() {
+        let temp_input_file = NamedTempFile::new().unwrap();
+        let temp_output_file = NamedTempFile::new().unwrap();
+        std::fs::write(temp_input_file.path(), "AAAAAAAAAA\nGGGGGGGGGG\nAAAAAAAAAT\nAAAAAAAAAA\n").unwrap();
+
+        let input_file_path = path_to_cstring(temp_input_file.path()).unwrap();
+        let output_file_path = path_to_cstring(temp_output_file.path()).unwrap();
+        let result = unsafe {
+            starcode_helper(input_file_path.as_ptr() as *mut c_char, output_file_path.as_ptr() as *mut c_char,
+                1, 0, 1, 0, 2.0, 0, 0, output_t_TIDY_OUTPUT as i32)
+        };
+        assert!(status_to_result(result).is_ok());
+        assert_eq!(std::fs::read_to_string(temp_output_file.path()).unwrap(),
+            "AAAAAAAAAA\tAAAAAAAAAA\nGGGGGGGGGG\tGGGGGGGGGG\nAAAAAAAAAT\tAAAAAAAAAA\nAAAAAAAAAA\tAAAAAAAAAA\n");
+    }
+
+    #[test]
+    fn test_assign_reads() {
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).build().unwrap();
+        let reads = ["AAAAAAAAAA", "GGGGGGGGGG", "AAAAAAAAAT", "AAAAAAAAAA", "GGGGGGGGGG", "AAAAAAAAAA"];
+        assert_eq!(assign_reads(&params, &reads).unwrap(), vec![
+            b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec(), b"AAAAAAAAAA".to_vec(),
+            b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec(), b"AAAAAAAAAA".to_vec()]);
+
+        let pairs = [
+            ("AAAAAAAAAA", "CCCCCCCCCC"),
+            ("AAAAAAAAAT", "CCCCCCCCCC"),
+            ("AAAAAAAAAA", "CCCCCCCCCC"),
+            ("AAAAAAAAAA", "CCCCCCCCCC"),
+            ("GGGGGGGGGG", "TTTTTTTTTT"),
+        ];
+        // Pairs are matched with the separator between the mates, which takes one more edit
+        let pair_params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).build().unwrap();
+        assert_eq!(assign_read_pairs(&pair_params, &pairs).unwrap(), vec![
+            (b"AAAAAAAAAA".to_vec(), b"CCCCCCCCCC".to_vec()),
+            (b"AAAAAAAAAA".to_vec(), b"CCCCCCCCCC".to_vec()),
+            (b"AAAAAAAAAA".to_vec(), b"CCCCCCCCCC".to_vec()),
+            (b"AAAAAAAAAA".to_vec(), b"CCCCCCCCCC".to_vec()),
+            (b"GGGGGGGGGG".to_vec(), b"TTTTTTTTTT".to_vec())]);
+
+        let multithreaded = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).threads(4).build().unwrap();
+        assert_eq!(assign_reads(&multithreaded, &reads).unwrap(), assign_reads(&params, &reads).unwrap());
+
+        let empty: [&str; 0] = [];
+        assert!(matches!(assign_reads(&params, &empty), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(assign_reads(&params, &["AAAAAAAAAA", "AAAAXAAAAA"]),
+            Err(StarcodeError::InvalidEntry { index: 1, error: EntryError::InvalidCharacter { position: 4, character: b'X' } })));
+        assert!(matches!(assign_read_pairs(&params, &[("AAAAAAAAAA", "CC-CCCCCCC")]),
+            Err(StarcodeError::InvalidEntry { index: 0, error: EntryError::InvalidCharacter { position: 2, character: b'-' } })));
+
+
+        // The mates are joined by STARCODE_MAX_TAU + 1 dashes, the joined pair must fit in
+        // MAXBRCDLEN as any sequence
+        for (length, fits) in [(400, true), (800, false)] {
+            let first = generate_random_nucleotide_sequence(length).0;
+            let second = generate_random_nucleotide_sequence(length).0;
+            let mut substituted = second.clone();
+            substituted[length / 2] = if substituted[length / 2] == b'A' { b'C' } else { b'A' };
+            let pairs = [(&first, &second), (&first, &second), (&first, &substituted)];
+            let assigned = assign_read_pairs(&pair_params, &pairs);
+            match fits {
+                true => assert_eq!(assigned.unwrap(), vec![(first.clone(), second.clone()); 3]),
+                false => assert!(matches!(assigned,
+                    Err(StarcodeError::InvalidEntry { index: 0, error: EntryError::TooLong { length: 1609 } }))),
+            }
+        }
+    }
+
+    fn fastq_record(header: &str, sequence: &str) -> FastqRecord {
+        FastqRecord {
+            header: header.as_bytes().to_vec(),
+            sequence: sequence.as_bytes().to_vec(),
+            quality: vec![b'I'; sequence.len()],
+        }
+    }
+
+    fn write_fastq(records: &[FastqRecord]) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut text = Vec::new();
+        for record in records {
+            text.extend_from_slice(&[b"@", &record.header[..], b"\n", &record.sequence[..], b"\n+\n", &record.quality[..], b"\n"].concat());
+        }
+        std::fs::write(file.path(), text).unwrap();
+        file
+    }
+
+    #[test]
+    fn test_representatives() {
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).build().unwrap();
+
+        let reads = [
+            fastq_record("r0 lane:1", "AAAAAAAAAA"),
+            fastq_record("r1", "AAAAAAAAAT"),
+            fastq_record("r2", "AAAAAAAAAA"),
+            fastq_record("r3", "GGGGGGGGGG"),
+            fastq_record("r4", "AAAAAAAAAA"),
+        ];
+        let fastq = write_fastq(&reads);
+        assert_eq!(representatives(&params, fastq.path()).unwrap(),
+            Representatives::Fastq(vec![reads[0].clone(), reads[3].clone()]));
+
+        let fasta = NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), ">s0\nAAAAAAAAAA\n>s1 x\nAAAAAAAAAT\n>s2\nAAAAAAAAAA\n>s3\nCCCCCCCCCC\n").unwrap();
+        let representatives_fasta = representatives(&params, fasta.path()).unwrap();
+        assert_eq!(representatives_fasta, Representatives::Fasta(vec![
+            FastaRecord { header: b"s0".to_vec(), sequence: b"AAAAAAAAAA".to_vec() },
+            FastaRecord { header: b"s3".to_vec(), sequence: b"CCCCCCCCCC".to_vec() },
+        ]));
+        assert_eq!(representatives_fasta.len(), 2);
+
+        let raw = NamedTempFile::new().unwrap();
+        std::fs::write(raw.path(), "AAAAAAAAAA\t5\nAAAAAAAAAT\t1\nCCCCCCCCCC\t1\n").unwrap();
+        assert_eq!(representatives(&params, raw.path()).unwrap(),
+            Representatives::Raw(vec![b"AAAAAAAAAA".to_vec(), b"CCCCCCCCCC".to_vec()]));
+
+        let components = StarcodeParams::builder().max_distance(1).algorithm(ClusterAlgorithm::ConnectedComponents).build().unwrap();
+        assert_eq!(representatives(&components, fastq.path()).unwrap(),
+            Representatives::Fastq(vec![reads[0].clone(), reads[3].clone()]));
+
+        let empty = NamedTempFile::new().unwrap();
+        assert!(matches!(representatives(&params, empty.path()), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(representatives(&params, Path::new("/nonexistent/starcode/input.fastq")), Err(StarcodeError::Io(_))));
+    }
+
+    #[test]
+    fn test_representative_pairs() {
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).build().unwrap();
+        let reads1 = [
+            fastq_record("p0/1", "AAAAAAAAAA"),
+            fastq_record("p1/1", "AAAAAAAAAT"),
+            fastq_record("p2/1", "AAAAAAAAAA"),
+            fastq_record("p3/1", "GGGGGGGGGG"),
+        ];
+        let reads2 = [
+            fastq_record("p0/2", "CCCCCCCCCC"),
+            fastq_record("p1/2", "CCCCCCCCCC"),
+            fastq_record("p2/2", "CCCCCCCCCC"),
+            fastq_record("p3/2", "TTTTTTTTTT"),
+        ];
+        let (fastq1, fastq2) = (write_fastq(&reads1), write_fastq(&reads2));
+        assert_eq!(representative_pairs(&params, fastq1.path(), fastq2.path()).unwrap(), vec![
+            (reads1[0].clone(), reads2[0].clone()),
+            (reads1[3].clone(), reads2[3].clone()),
+        ]);
+
+        let short = write_fastq(&reads2[..2]);
+        assert!(matches!(representative_pairs(&params, fastq1.path(), short.path()), Err(StarcodeError::InvalidInput)));
+    }
+
+    fn gzipped(path: &Path) -> NamedTempFile {
+        let file = NamedTempFile::new().unwrap();
+        let mut encoder = flate2::write::GzEncoder::new(File::create(file.path()).unwrap(), flate2::Compression::default());
+        encoder.write_all(&std::fs::read(path).unwrap()).unwrap();
+        encoder.finish().unwrap();
+        file
+    }
+
+    #[test]
+    fn test_cluster_file() {
+        let sequences = random_10mers();
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).build().unwrap();
+        let in_memory = cluster(&params, &sequences).unwrap();
+
+        let raw = NamedTempFile::new().unwrap();
+        write_vectors_to_file(raw.path(), &sequences).unwrap();
+        let clustered = cluster_file(&params, raw.path()).unwrap();
+        assert_eq!(clustered.format, InputFormat::Raw);
+        assert_eq!(clustered.alignment.cluster_centers, in_memory.cluster_centers);
+        assert_eq!(clustered.alignment.cluster_count, in_memory.cluster_count);
+        assert_eq!(clustered.alignment.cluster_members, in_memory.cluster_members);
+
+        let gzip = gzipped(raw.path());
+        let from_gzip = cluster_file(&params, gzip.path()).unwrap();
+        assert_eq!(from_gzip.format, InputFormat::Raw);
+        assert_eq!(from_gzip.alignment.cluster_centers, in_memory.cluster_centers);
+        assert_eq!(from_gzip.alignment.cluster_count, in_memory.cluster_count);
+
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(2.0).show_ids(true).build().unwrap();
+        let reads = [
+            fastq_record("r0", "AAAAAAAAAA"),
+            fastq_record("r1", "AAAAAAAAAT"),
+            fastq_record("r2", "GGGGGGGGGG"),
+            fastq_record("r3", "AAAAAAAAAA"),
+        ];
+        let fastq = write_fastq(&reads);
+        for clustered in [
+            cluster_file(&params, fastq.path()).unwrap(),
+            cluster_file(&params, gzipped(fastq.path()).path()).unwrap(),
+            cluster_reader(&params, File::open(fastq.path()).unwrap()).unwrap(),
+            cluster_reader(&params, &std::fs::read(gzipped(fastq.path()).path()).unwrap()[..]).unwrap(),
+        ] {
+            assert_eq!(clustered.format, InputFormat::Fastq);
+            assert_eq!(clustered.alignment.cluster_centers, vec![b"AAAAAAAAAA".to_vec(), b"GGGGGGGGGG".to_vec()]);
+            assert_eq!(clustered.alignment.cluster_count, vec![3, 1]);
+            // The ids are the positions of the records in the file
+            assert_eq!(clustered.alignment.cluster_member_ids, vec![vec![0, 1, 3], vec![2]]);
+        }
+
+        let fasta = ">s0\nAAAAAAAAAA\n>s1\nAAAAAAAAAT\n>s2\nAAAAAAAAAA\n";
+        let clustered = cluster_reader(&params, fasta.as_bytes()).unwrap();
+        assert_eq!(clustered.format, InputFormat::Fasta);
+        assert_eq!(clustered.alignment.cluster_count, vec![3]);
+
+        assert!(matches!(cluster_reader(&params, "".as_bytes()), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(cluster_reader(&params, "AAAAXAAAAA\n".as_bytes()), Err(StarcodeError::InvalidInput)));
+        assert!(matches!(cluster_file(&params, Path::new("/nonexistent/starcode/input.txt")), Err(StarcodeError::Io(_))));
+        let tidy = StarcodeParams::builder().output(OutputMode::Tidy).build().unwrap();
+        assert!(matches!(cluster_reader(&tidy, fasta.as_bytes()), Err(StarcodeError::InvalidParameter(_))));
+
+        // A truncated gzip stream is an error, not a shorter input
+        let compressed = std::fs::read(gzipped(raw.path()).path()).unwrap();
+        assert!(matches!(cluster_reader(&params, &compressed[..compressed.len() / 2]), Err(StarcodeError::Io(_))));
+    }
+
+    #[test]
+    fn test_cancellation() {
+        let sequences: FxHashMap<Vec<u8>,usize> = (0..20000)
+            .map(|_| generate_random_nucleotide_sequence(25))
+            .collect();
+        let token = CancellationToken::new();
+        let params = StarcodeParams::builder().max_distance(3).cancellation_token(token.clone()).build().unwrap();
+        assert_eq!(params.cancellation_token(), Some(&token));
+        assert_ne!(params.cancellation_token(), Some(&CancellationToken::new()));
+
+        // Stop a long run from another thread
+        let start = Instant::now();
+        let canceller = thread::spawn({
+            let token = token.clone();
+            move || {
+                thread::sleep(Duration::from_millis(100));
+                token.cancel();
+            }
+        });
+        assert!(matches!(cluster(&params, &sequences), Err(StarcodeError::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(5), "cancelled after {:?}", start.elapsed());
+        canceller.join().unwrap();
+
+        // Runs started with a cancelled token stop right away
+        let small = small_input();
+        let reads = ["AAAAAAAAAA", "AAAAAAAAAT"];
+        assert!(matches!(cluster_spheres(&params, &small), Err(StarcodeError::Cancelled)));
+        assert!(matches!(cluster_components(&params, &small), Err(StarcodeError::Cancelled)));
+        assert!(matches!(assign_reads(&params, &reads), Err(StarcodeError::Cancelled)));
+        let mut pairs: FxHashMap<MatePair,usize> = FxHashMap::default();
+        pairs.insert(mate_pair("AAAAAAAAAA", "CCCCCCCCCC"), 1);
+        assert!(matches!(cluster_pairs(&params, &pairs), Err(StarcodeError::Cancelled)));
+        let fastq = write_fastq(&[fastq_record("read", "AAAAAAAAAA")]);
+        assert!(matches!(cluster_file(&params, fastq.path()), Err(StarcodeError::Cancelled)));
+        assert!(matches!(representatives(&params, fastq.path()), Err(StarcodeError::Cancelled)));
+
+        // The time limit starts again with every run
+        let params = StarcodeParams::builder().max_distance(3).time_limit(Duration::from_millis(100)).build().unwrap();
+        assert!(matches!(cluster(&params, &sequences), Err(StarcodeError::Cancelled)));
+        assert!(cluster(&params, &small).is_ok());
+        let params = StarcodeParams::builder().time_limit(Duration::ZERO).build().unwrap();
+        assert!(matches!(cluster(&params, &small), Err(StarcodeError::Cancelled)));
+        let params = StarcodeParams::builder().time_limit(Duration::MAX).build().unwrap();
+        assert!(cluster(&params, &small).is_ok());
+    }
+
+    fn run_with_progress<F: FnOnce(&StarcodeParams)>(threads: usize, run: F) -> Vec<Progress> {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let params = StarcodeParams::builder()
+            .threads(threads)
+            .progress({
+                let events = events.clone();
+                move |progress| events.lock().unwrap().push(progress)
+            })
+            .build()
+            .unwrap();
+        run(&params);
+        let events = events.lock().unwrap().clone();
+        events
+    }
+
+    fn check_progress(events: &[Progress], sequences: usize) {
+        let stages = [Stage::Reading, Stage::Sorting, Stage::Padding, Stage::Querying, Stage::Clustering, Stage::Output];
+        let boundaries: Vec<Progress> = events
+            .iter()
+            .filter(|event| matches!(event, Progress::Started(_) | Progress::Finished(_)))
+            .cloned()
+            .collect();
+        let expected: Vec<Progress> = stages
+            .iter()
+            .flat_map(|stage| vec![Progress::Started(*stage), Progress::Finished(*stage)])
+            .collect();
+        assert_eq!(boundaries, expected);
+
+        let unique: Vec<(usize, usize)> = events
+            .iter()
+            .filter_map(|event| match event {
+                Progress::UniqueSequences { unique, total } => Some((*unique, *total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(unique.len(), 1);
+        assert_eq!(unique[0].1, sequences);
+        assert!(unique[0].0 > 0 && unique[0].0 <= sequences);
+
+        let jobs: Vec<(usize, usize)> = events
+            .iter()
+            .filter_map(|event| match event {
+                Progress::QueryJobs { done, total } => Some((*done, *total)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(jobs.first().map(|&(done, _)| done), Some(0));
+        assert!(jobs.windows(2).all(|pair| pair[0].0 < pair[1].0 && pair[0].1 == pair[1].1));
+        let &(done, total) = jobs.last().unwrap();
+        assert!(total > 0);
+        assert_eq!(done, total);
+
+        // The counts come while their stage runs
+        let position = |target: Progress| events.iter().position(|event| *event == target).unwrap();
+        let unique_at = events.iter().position(|event| matches!(event, Progress::UniqueSequences { .. })).unwrap();
+        assert!(position(Progress::Started(Stage::Sorting)) < unique_at);
+        assert!(unique_at < position(Progress::Finished(Stage::Sorting)));
+        let jobs_at = events.iter().position(|event| matches!(event, Progress::QueryJobs { .. })).unwrap();
+        assert!(position(Progress::Started(Stage::Querying)) < jobs_at);
+    }
+
+    #[test]
+    fn test_progress() {
+        let sequences: FxHashMap<Vec<u8>,usize> = (0..2000)
+            .map(|_| generate_random_nucleotide_sequence(20))
+            .collect();
+        for threads in [1, 4] {
+            let events = run_with_progress(threads, |params| {
+                cluster(params, &sequences).unwrap();
+            });
+            check_progress(&events, sequences.len());
+        }
+
+        // Files count every record before they are merged
+        let records: Vec<FastqRecord> = ["AAAAAAAAAA", "AAAAAAAAAT", "AAAAAAAAAA", "GGGGGGGGGG"]
+            .iter()
+            .enumerate()
+            .map(|(i, sequence)| fastq_record(&format!("read{}", i), sequence))
+            .collect();
+        let fastq = write_fastq(&records);
+        let events = run_with_progress(1, |params| {
+            cluster_file(params, fastq.path()).unwrap();
+        });
+        check_progress(&events, 4);
+    }
+
+    // A message logged on the test thread with its level and the names of its spans, outermost first
+    #[derive(Debug)]
+    struct LogEvent {
+        level: tracing::Level,
+        message: String,
+        spans: Vec<&'static str>,
+    }
+
+    // Name and parent of a span
+    type RecordedSpan = (&'static str, Option<tracing::span::Id>);
+
+    // Records the events of the thread it is the default subscriber of
+    #[derive(Clone, Default)]
+    struct LogRecorder {
+        // the id of a span is its index plus one
+        spans: Arc<Mutex<Vec<RecordedSpan>>>,
+        entered: Arc<Mutex<Vec<tracing::span::Id>>>,
+        events: Arc<Mutex<Vec<LogEvent>>>,
+    }
+
+    struct MessageVisitor(String);
+
+    impl tracing::field::Visit for MessageVisitor {
+        fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn fmt::Debug) {
+            if field.name() == "message" {
+                self.0 = format!("{:?}", value);
+            }
+        }
+    }
+
+    impl tracing::Subscriber for LogRecorder {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, attributes: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            let parent = if attributes.is_root() {
+                None
+            } else {
+                attributes.parent().cloned().or_else(|| self.entered.lock().unwrap().last().cloned())
+            };
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attributes.metadata().name(), parent));
+            tracing::span::Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            let mut visitor = MessageVisitor(String::new());
+            event.record(&mut visitor);
+            let mut spans = Vec::new();
+            let mut current = event.parent().cloned().or_else(|| self.entered.lock().unwrap().last().cloned());
+            while let Some(id) = current {
+                let (name, parent) = self.spans.lock().unwrap()[id.into_u64() as usize - 1].clone();
+                spans.insert(0, name);
+                current = parent;
+            }
+            self.events.lock().unwrap().push(LogEvent { level: *event.metadata().level(), message: visitor.0, spans });
+        }
+
+        fn enter(&self, span: &tracing::span::Id) {
+            self.entered.lock().unwrap().push(span.clone());
+        }
+
+        fn exit(&self, _: &tracing::span::Id) {
+            self.entered.lock().unwrap().pop();
+        }
+    }
+
+    fn logged<F: FnOnce()>(run: F) -> Vec<LogEvent> {
+        let recorder = LogRecorder::default();
+        tracing::subscriber::with_default(recorder.clone(), run);
+        let events = std::mem::take(&mut *recorder.events.lock().unwrap());
+        events
+    }
+
+    #[test]
+    fn test_logging() {
+        let fasta = NamedTempFile::new().unwrap();
+        std::fs::write(fasta.path(), ">s0\nAAAAAAAAAA\n>s1\nAAAAAAAAAT\n>s2\nCCCCCCCCCC\n").unwrap();
+        let verbose = StarcodeParams::builder().threads(1).verbose(true).build().unwrap();
+        let events = logged(|| {
+            cluster_file(&verbose, fasta.path()).unwrap();
+        });
+        let find = |message: &str| events.iter().find(|event| event.message == message)
+            .unwrap_or_else(|| panic!("{:?} not logged in {:?}", message, events));
+        for (message, span) in [
+            ("reading input files", "read"),
+            ("FASTA format detected", "read"),
+            ("sorting", "sort"),
+            ("setting dist to 2", "pad"),
+            ("message passing clustering", "cluster"),
+        ] {
+            let event = find(message);
+            assert_eq!(event.level, tracing::Level::INFO);
+            assert_eq!(event.spans, vec!["starcode", span]);
+        }
+        let progress = events.iter().find(|event| event.message.starts_with("progress: ")).unwrap();
+        assert_eq!(progress.level, tracing::Level::DEBUG);
+        assert_eq!(progress.spans, vec!["starcode", "query"]);
+        assert!(events.iter().all(|event| !event.message.ends_with(char::is_whitespace)));
+
+        // Only errors and warnings unless verbose
+        let quiet = StarcodeParams::builder().threads(1).build().unwrap();
+        assert!(logged(|| {
+            cluster_file(&quiet, fasta.path()).unwrap();
+        }).is_empty());
+
+        let raw = NamedTempFile::new().unwrap();
+        std::fs::write(raw.path(), "AAAAAAAAAA\nACGU\n").unwrap();
+        let events = logged(|| {
+            assert!(matches!(cluster_file(&quiet, raw.path()), Err(StarcodeError::InvalidInput)));
+        });
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].level, tracing::Level::ERROR);
+        assert_eq!(events[0].message, "invalid input\noffending sequence:\nACGU");
+        assert_eq!(events[0].spans, vec!["starcode", "read"]);
+    }
+
+    const STDERR_CHECK_VAR: &str = "RUST_STARCODE_STDERR_CHECK";
+
+    #[test]
+    fn test_no_stderr_without_log_hook() {
+        // stderr is shared with the tests running in parallel, read it from a process that
+        // runs nothing else
+        for verbose in ["0", "1"] {
+            let output = Command::new(std::env::current_exe().unwrap())
+                .args(["tests::stderr_check", "--exact", "--ignored", "--test-threads=1"])
+                .env(STDERR_CHECK_VAR, verbose)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            match verbose {
+                "0" => assert!(stderr.is_empty(), "{}", stderr),
+                _ => assert!(stderr.contains("invalid input\noffending sequence:\nACGU\n"), "{}", stderr),
+            }
+        }
+    }
+
+    #[test]
+    #[ignore = "run alone by test_no_stderr_without_log_hook"]
+    fn stderr_check() {
+        let verbose = match std::env::var(STDERR_CHECK_VAR) {
+            Ok(verbose) => verbose == "1",
+            Err(_) => return,
+        };
+        let c_sequence = CString::new("ACGU").unwrap();
+        let mut sequence_ptrs = [c_sequence.as_ptr() as *mut c_char];
+        let counts: [c_long; 1] = [1];
+        let result = unsafe {
+            let outputf1 = libc::tmpfile();
+            let result = starcode_seqs(sequence_ptrs.as_mut_ptr(), counts.as_ptr(), 1,
+                outputf1 as *mut FILE, std::ptr::null_mut(), 2, verbose as i32, 1, 0, 2.0, 0, 0, 0);
+            libc::fclose(outputf1);
+            result
+        };
+        assert_eq!(result, starcode_status_t_STARCODE_INVALID_INPUT as i32);
+    }
+
+    fn levenshtein(a: &[u8], b: &[u8]) -> usize {
+        let mut row: Vec<usize> = (0..=b.len()).collect();
+        for (i, x) in a.iter().enumerate() {
+            let mut diagonal = row[0];
+            row[0] = i + 1;
+            for (j, y) in b.iter().enumerate() {
+                let substitution = diagonal + (x != y) as usize;
+                diagonal = row[j + 1];
+                row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+            }
+        }
+        row[b.len()]
+    }
+
+    #[test]
+    fn test_trie() {
+        assert!(matches!(trie::Trie::<()>::new(0), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(trie::Trie::<()>::new(MAXBRCDLEN as usize + 1), Err(StarcodeError::InvalidParameter(_))));
+
+        let mut counts: trie::Trie<usize> = trie::Trie::new(12).unwrap();
+        assert!(counts.is_empty());
+        *counts.insert(b"ACGTACGTAC").unwrap() += 1;
+        *counts.insert(b"ACGTACGTAC").unwrap() += 1;
+        *counts.insert(b"acgtacgtac").unwrap() += 1;
+        assert_eq!(counts.len(), 1);
+        let hits = counts.search(b"ACGTACGTAC", 0).unwrap();
+        assert_eq!(hits, vec![trie::Hit { distance: 0, key: &b"ACGTACGTAC"[..], payload: &3 }]);
+        assert!(counts.search(b"ACGTACGTAA", 0).unwrap().is_empty());
+
+        assert!(matches!(counts.insert(b"ACGU"), Err(StarcodeError::InvalidSequence(EntryError::InvalidCharacter { position: 3, .. }))));
+        assert!(matches!(counts.insert(b""), Err(StarcodeError::InvalidSequence(EntryError::Empty))));
+        assert!(matches!(counts.insert(b"ACGTACGTACGTA"), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(counts.search(b"ACGTACGTACGTA", 1), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(counts.search(b"ACGT", TAU as usize + 1), Err(StarcodeError::InvalidParameter(_))));
+        assert_eq!(counts.len(), 1);
+
+        // Same hits as a brute force search, for sequences of different lengths
+        let mut rng = rand::thread_rng();
+        let lengths: Vec<usize> = (6..=12).collect();
+        let keys: Vec<Vec<u8>> = (0..500)
+            .map(|_| generate_random_nucleotide_sequence(*lengths.choose(&mut rng).unwrap()).0)
+            .collect();
+        let mut trie: trie::Trie<Vec<usize>> = trie::Trie::new(12).unwrap();
+        for (i, key) in keys.iter().enumerate() {
+            trie.insert(key).unwrap().push(i);
+        }
+        let unique: FxHashMap<&[u8], ()> = keys.iter().map(|key| (key.as_slice(), ())).collect();
+        assert_eq!(trie.len(), unique.len());
+        for query in keys.iter().take(50) {
+            for tau in [0, 1, 3] {
+                let hits = trie.search(query, tau).unwrap();
+                assert!(hits.windows(2).all(|pair| pair[0].distance <= pair[1].distance));
+                let mut found: Vec<(usize, &[u8])> = hits.iter().map(|hit| (hit.distance, hit.key)).collect();
+                found.sort();
+                let mut expected: Vec<(usize, &[u8])> = unique
+                    .keys()
+                    .map(|key| (levenshtein(query, key), *key))
+                    .filter(|(distance, _)| *distance <= tau)
+                    .collect();
+                expected.sort();
+                assert_eq!(found, expected, "query {:?} tau {}", std::str::from_utf8(query), tau);
+                for hit in &hits {
+                    assert!(hit.payload.iter().all(|&i| keys[i] == hit.key));
+                }
+            }
+        }
+
+        // The payloads are dropped with the trie
+        let shared = std::rc::Rc::new(());
+        let mut owners: trie::Trie<Vec<std::rc::Rc<()>>> = trie::Trie::new(4).unwrap();
+        for key in [&b"ACGT"[..], b"ACG", b"TTTT", b"ACGT"] {
+            owners.insert(key).unwrap().push(shared.clone());
+        }
+        assert_eq!(std::rc::Rc::strong_count(&shared), 5);
+        drop(owners);
+        assert_eq!(std::rc::Rc::strong_count(&shared), 1);
+    }
+
+    #[test]
+    fn test_barcode_index() {
+        assert!(matches!(BarcodeIndex::new(Vec::<&[u8]>::new(), 2), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(BarcodeIndex::new([b"ACGT"], TAU as usize + 1), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(BarcodeIndex::new([&b"ACGT"[..], b"ACGU"], 2),
+            Err(StarcodeError::InvalidEntry { index: 1, error: EntryError::InvalidCharacter { position: 3, .. } })));
+
+        // Repeated references (in either case) all match, too long queries match nothing
+        let index = BarcodeIndex::new([&b"ACGTACGTAC"[..], b"TTTTGGGGCC", b"acgtacgtac"], 2).unwrap();
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.sequence(2), b"acgtacgtac");
+        assert_eq!(index.query(b"ACGTACGTAA", 1).unwrap(), vec![
+            BarcodeMatch { index: 0, distance: 1 }, BarcodeMatch { index: 2, distance: 1 }]);
+        assert!(index.query(b"ACGTACGTAA", 0).unwrap().is_empty());
+        assert!(index.query(b"ACGTACGTACGTA", 2).unwrap().is_empty());
+        assert!(matches!(index.query(b"ACGT", 3), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(index.query(b"", 1), Err(StarcodeError::InvalidSequence(EntryError::Empty))));
+
+        // Same nearest references as a brute force search, for sequences of different
+        // lengths and queries a few edits away from them
+        let mut rng = rand::thread_rng();
+        let references: Vec<Vec<u8>> = (0..300)
+            .map(|_| generate_random_nucleotide_sequence(rng.gen_range(10..=14)).0)
+            .collect();
+        let index = BarcodeIndex::new(&references, 3).unwrap();
+        let queries: Vec<Vec<u8>> = (0..300).map(|_| {
+            let mut query = references.choose(&mut rng).unwrap().clone();
+            for _ in 0..rng.gen_range(0..=4) {
+                let position = rng.gen_range(0..query.len());
+                let base = *b"ACGT".choose(&mut rng).unwrap();
+                match rng.gen_range(0..3) {
+                    0 => query[position] = base,
+                    1 => query.insert(position, base),
+                    _ if query.len() > 1 => { query.remove(position); }
+                    _ => {}
+                }
+            }
+            query
+        }).collect();
+        for tau in 0..=3 {
+            let batch = index.query_batch(&queries, tau).unwrap();
+            assert_eq!(batch.len(), queries.len());
+            for (query, matches) in queries.iter().zip(&batch) {
+                let distances: Vec<usize> = references.iter().map(|reference| levenshtein(query, reference)).collect();
+                let nearest = *distances.iter().min().unwrap();
+                let expected: Vec<BarcodeMatch> = distances.iter().enumerate()
+                    .filter(|&(_, &distance)| distance == nearest && distance <= tau)
+                    .map(|(index, &distance)| BarcodeMatch { index, distance })
+                    .collect();
+                assert_eq!(matches, &expected, "query {:?} tau {}", std::str::from_utf8(query), tau);
+                assert_eq!(&index.query(query, tau).unwrap(), matches);
+            }
+        }
+
+        // Queries from several threads at once
+        let expected = index.query_batch(&queries, 2).unwrap();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for (query, matches) in queries.iter().zip(&expected) {
+                        assert_eq!(&index.query(query, 2).unwrap(), matches);
+                    }
+                });
+            }
+        });
+    }
+
+    // The edges of a match graph as (first, second, distance) in sequence order, sorted
+    fn graph_pairs(graph: &MatchGraph) -> Vec<(&[u8], &[u8], usize)> {
+        let mut pairs: Vec<(&[u8], &[u8], usize)> = graph.edges.iter().map(|edge| {
+            let (a, b) = (&graph.sequences[edge.query][..], &graph.sequences[edge.target][..]);
+            (a.min(b), a.max(b), edge.distance)
+        }).collect();
+        pairs.sort();
+        pairs
+    }
+
+    // Copies of random centers with up to 'max_edits' substitutions, with counts from 1 to 5
+    fn mutated_barcodes(rng: &mut impl Rng, centers: &[Vec<u8>], copies: usize, max_edits: usize) -> Vec<(Vec<u8>, usize)> {
+        (0..copies).map(|_| {
+            let mut sequence = centers.choose(rng).unwrap().clone();
+            for _ in 0..rng.gen_range(0..=max_edits) {
+                let position = rng.gen_range(0..sequence.len());
+                sequence[position] = *b"ACGT".choose(rng).unwrap();
+            }
+            (sequence, rng.gen_range(1..=5))
+        }).collect()
+    }
+
+    #[test]
+    fn test_match_graph() {
+        let params = StarcodeParams::builder().max_distance(1).build().unwrap();
+        let graph = match_graph(&params, &chain_input()).unwrap();
+        assert_eq!(graph.sequences, vec![
+            b"AAAAAAAAAA".to_vec(), b"AAAAAAAAAC".to_vec(), b"AAAAAAAACC".to_vec(),
+            b"AAAAAAACCC".to_vec(), b"GGGGGGGGGG".to_vec()]);
+        assert_eq!(graph.counts, vec![10, 5, 3, 2, 1]);
+        assert_eq!(graph_pairs(&graph), vec![
+            (&b"AAAAAAAAAA"[..], &b"AAAAAAAAAC"[..], 1),
+            (&b"AAAAAAAAAC"[..], &b"AAAAAAAACC"[..], 1),
+            (&b"AAAAAAAACC"[..], &b"AAAAAAACCC"[..], 1),
+        ]);
+        for edge in &graph.edges {
+            assert_eq!(edge.query_count, graph.counts[edge.query]);
+            assert_eq!(edge.target_count, graph.counts[edge.target]);
+        }
+
+        // Nearest neighbors first, isolated sequences have empty rows
+        let params = StarcodeParams::builder().max_distance(2).build().unwrap();
+        let csr = match_graph(&params, &chain_input()).unwrap().to_csr();
+        assert_eq!(csr.offsets, vec![0, 2, 5, 8, 10, 10]);
+        assert_eq!(csr.row(0).collect::<Vec<_>>(), vec![(1, 1), (2, 2)]);
+        assert_eq!(csr.row(1).map(|(_, distance)| distance).collect::<Vec<_>>(), vec![1, 1, 2]);
+        assert_eq!(csr.row(3).collect::<Vec<_>>(), vec![(2, 1), (1, 2)]);
+        assert_eq!(csr.row(4).count(), 0);
+
+        // Repeats are added up, the cluster ratio does not drop any pair
+        let params = StarcodeParams::builder().max_distance(1).cluster_ratio(100.0).build().unwrap();
+        let graph = match_graph(&params, [("ACGTA", 1usize), ("ACGTT", 2), ("ACGTA", 3), ("TTTTT", 1)]).unwrap();
+        assert_eq!(graph.sequences, vec![b"ACGTA".to_vec(), b"ACGTT".to_vec(), b"TTTTT".to_vec()]);
+        assert_eq!(graph.counts, vec![4, 2, 1]);
+        assert_eq!(graph_pairs(&graph), vec![(&b"ACGTA"[..], &b"ACGTT"[..], 1)]);
+        assert!(matches!(match_graph(&params, Vec::<(&[u8], usize)>::new()), Err(StarcodeError::EmptyInput)));
+
+        // All the pairs within tau of random barcodes of the same length, with any algorithm
+        // and number of threads, along with the same clusters as 'cluster'
+        let mut rng = rand::thread_rng();
+        let centers: Vec<Vec<u8>> = (0..20).map(|_| generate_random_nucleotide_sequence(12).0).collect();
+        let sequences = mutated_barcodes(&mut rng, &centers, 300, 2);
+        let unique: FxHashMap<&[u8], ()> = sequences.iter().map(|(sequence, _)| (sequence.as_slice(), ())).collect();
+        let mut unique: Vec<&[u8]> = unique.into_keys().collect();
+        unique.sort();
+        for tau in [1, 2, 3] {
+            let mut expected = Vec::new();
+            for (i, a) in unique.iter().enumerate() {
+                for b in &unique[i + 1..] {
+                    let distance = levenshtein(a, b);
+                    if distance <= tau {
+                        expected.push((*a, *b, distance));
+                    }
+                }
+            }
+            expected.sort();
+            for (algorithm, threads) in [(ClusterAlgorithm::MessagePassing, 1), (ClusterAlgorithm::Spheres, 3),
+                                         (ClusterAlgorithm::ConnectedComponents, 2)] {
+                let params = StarcodeParams::builder().max_distance(tau as i32).algorithm(algorithm)
+                    .threads(threads).show_members(true).build().unwrap();
+                let graph = match_graph(&params, sequences.iter().cloned()).unwrap();
+                assert_eq!(graph.len(), unique.len());
+                assert_eq!(graph_pairs(&graph), expected, "tau {} {:?}", tau, algorithm);
+
+                let (alignment, clustered) = cluster_with_graph(&params, sequences.iter().cloned()).unwrap();
+                assert_eq!(graph_pairs(&clustered), expected);
+                if threads == 1 {
+                    let expected = cluster(&params, sequences.iter().cloned()).unwrap();
+                    assert_eq!(alignment.cluster_centers, expected.cluster_centers);
+                    assert_eq!(alignment.cluster_count, expected.cluster_count);
+                    assert_eq!(alignment.cluster_members, expected.cluster_members);
+                }
+
+                let csr = graph.to_csr();
+                assert_eq!(csr.neighbors.len(), 2 * graph.edges.len());
+                for i in 0..graph.len() {
+                    let distances: Vec<usize> = csr.row(i).map(|(_, distance)| distance).collect();
+                    assert!(distances.windows(2).all(|pair| pair[0] <= pair[1]));
+                    assert!(csr.row(i).all(|(j, distance)| levenshtein(&graph.sequences[i], &graph.sequences[j]) == distance));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_clustering_algorithm() {
+        use rand::seq::SliceRandom;
+
+        // The clusters as sorted (canonical, members) pairs
+        fn partition(clusters: impl IntoIterator<Item = (Vec<u8>, Vec<Vec<u8>>)>) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+            let mut clusters: Vec<_> = clusters.into_iter().map(|(canonical, mut members)| {
+                members.sort();
+                (canonical, members)
+            }).collect();
+            clusters.sort();
+            clusters
+        }
+        fn alignment_partition(alignment: StarcodeAlignment) -> Vec<(Vec<u8>, Vec<Vec<u8>>)> {
+            partition(alignment.cluster_centers.into_iter().zip(alignment.cluster_members))
+        }
+        fn parts(alignment: &StarcodeAlignment) -> impl PartialEq + fmt::Debug + '_ {
+            (&alignment.cluster_centers, &alignment.cluster_count, &alignment.cluster_members, &alignment.cluster_member_ids)
+        }
+
+        // Message passing gives the clusters of starcode, in the same order
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test").join("test_file1.fastq");
+        let records = records::read_fastq_records(io::BufReader::new(File::open(path).unwrap())).unwrap();
+        let reads: Vec<(Vec<u8>, usize)> = records.into_iter().map(|record| (record.sequence, 1)).collect();
+        for (max_distance, cluster_ratio) in [(1, 1.0), (2, 5.0), (-1, 5.0)] {
+            let params = StarcodeParams::builder().max_distance(max_distance).cluster_ratio(cluster_ratio)
+                .show_ids(true).build().unwrap();
+            let alignment = cluster_graph(&params, reads.iter().cloned(), &MessagePassing { cluster_ratio }).unwrap();
+            let expected = cluster(&params, reads.iter().cloned()).unwrap();
+            assert_eq!(parts(&alignment), parts(&expected), "max_distance {}", max_distance);
+        }
+
+        // Spheres and connected components give the same partitions as starcode when the
+        // counts are all different (starcode breaks the ties with 'qsort')
+        let mut rng = rand::thread_rng();
+        for _ in 0..10 {
+            let centers: Vec<Vec<u8>> = (0..rng.gen_range(1..20)).map(|_| generate_random_nucleotide_sequence(12).0).collect();
+            let copies = rng.gen_range(1..300);
+            let mut unique: Vec<Vec<u8>> = mutated_barcodes(&mut rng, &centers, copies, 3).into_iter()
+                .map(|(sequence, _)| sequence)
+                .collect();
+            unique.sort();
+            unique.dedup();
+            let mut counts: Vec<usize> = (1..=unique.len()).collect();
+            counts.shuffle(&mut rng);
+            let sequences: Vec<(Vec<u8>, usize)> = unique.into_iter().zip(counts).collect();
+
+            for tau in [1, 2, 3] {
+                let params = StarcodeParams::builder().max_distance(tau).build().unwrap();
+                let spheres = cluster_spheres(&params, sequences.iter().cloned()).unwrap();
+                let expected = partition(spheres.into_iter().map(|sphere| {
+                    (sphere.center, sphere.members.into_iter().map(|member| member.sequence).collect())
+                }));
+                let alignment = cluster_graph(&params, sequences.iter().cloned(), &Spheres).unwrap();
+                assert_eq!(alignment_partition(alignment), expected, "spheres tau {}", tau);
+
+                let components = cluster_components(&params, sequences.iter().cloned()).unwrap();
+                let expected = partition(components.into_iter().map(|component| {
+                    (component.centroid, component.members.into_iter().map(|member| member.sequence).collect())
+                }));
+                let alignment = cluster_graph(&params, sequences.iter().cloned(), &ConnectedComponents).unwrap();
+                assert_eq!(alignment_partition(alignment), expected, "components tau {}", tau);
+
+                let alignment = cluster_graph(&params, sequences.iter().cloned(), &MessagePassing::default()).unwrap();
+                let expected = cluster(&params, sequences.iter().cloned()).unwrap();
+                assert_eq!(parts(&alignment), parts(&expected), "message passing tau {}", tau);
+            }
+        }
+
+        // Any algorithm can be plugged in, its canonicals are checked
+        struct Singletons;
+        impl ClusteringAlgorithm for Singletons {
+            fn cluster(&self, graph: &MatchGraph, _adjacency: &CsrAdjacency) -> Result<Vec<usize>, StarcodeError> {
+                Ok((0..graph.len()).collect())
+            }
+        }
+        struct Chained;
+        impl ClusteringAlgorithm for Chained {
+            fn cluster(&self, graph: &MatchGraph, _adjacency: &CsrAdjacency) -> Result<Vec<usize>, StarcodeError> {
+                Ok((0..graph.len()).map(|u| u.saturating_sub(1)).collect())
+            }
+        }
+        let params = StarcodeParams::builder().max_distance(1).show_ids(true).build().unwrap();
+        let input = [("ACGTA", 1usize), ("ACGTT", 2), ("ACGTA", 3)];
+        let alignment = cluster_graph(&params, input, &Singletons).unwrap();
+        assert_eq!(alignment.cluster_centers, vec![b"ACGTA".to_vec(), b"ACGTT".to_vec()]);
+        assert_eq!(alignment.cluster_count, vec![4, 2]);
+        assert_eq!(alignment.cluster_member_ids, vec![vec![0, 2], vec![1]]);
+        let alignment = cluster_graph(&params, input, &ConnectedComponents as &dyn ClusteringAlgorithm).unwrap();
+        assert_eq!(alignment.cluster_members, vec![vec![b"ACGTA".to_vec(), b"ACGTT".to_vec()]]);
+        assert_eq!(alignment.cluster_count, vec![6]);
+        let input = [("AAAAA", 1usize), ("CCCCC", 1), ("GGGGG", 1)];
+        assert!(matches!(cluster_graph(&params, input, &Chained), Err(StarcodeError::InvalidParameter(_))));
+        let params = StarcodeParams::builder().output(OutputMode::Tidy).build().unwrap();
+        assert!(matches!(cluster_graph(&params, input, &Singletons), Err(StarcodeError::InvalidParameter(_))));
+    }
+
+    #[test]
+    fn test_hamming_distance() {
+        let hamming = |a: &[u8], b: &[u8]| a.iter().zip(b).filter(|(x, y)| x != y).count();
+
+        // An indel is a Levenshtein match only
+        let input = [("AAAACCCCGGGG", 10usize), ("AAACCCCGGGGT", 1)];
+        let params = StarcodeParams::builder().max_distance(2).build().unwrap();
+        assert_eq!(cluster(&params, input).unwrap().cluster_centers.len(), 1);
+        let params = StarcodeParams::builder().max_distance(2).metric(DistanceMetric::Hamming).build().unwrap();
+        let alignment = cluster(&params, input).unwrap();
+        assert_eq!(alignment.cluster_centers, vec![b"AAAACCCCGGGG".to_vec(), b"AAACCCCGGGGT".to_vec()]);
+        assert_eq!(alignment.cluster_count, vec![10, 1]);
+
+        // Sequences of different lengths never match, tau may exceed the length
+        let graph = match_graph(&params, [("ACGTA", 1usize), ("ACGT", 1), ("ACGTT", 1), ("AC", 1), ("TT", 1), ("AG", 1)]).unwrap();
+        assert_eq!(graph_pairs(&graph), vec![
+            (&b"AC"[..], &b"AG"[..], 1), (&b"AC"[..], &b"TT"[..], 2), (&b"ACGTA"[..], &b"ACGTT"[..], 1),
+            (&b"AG"[..], &b"TT"[..], 2),
+        ]);
+
+        // Spheres and components start with their center, with the members by distance
+        let input = [("AAAAAA", 20usize), ("AAAAAT", 3), ("AAAATT", 2), ("CCCCCC", 1)];
+        let spheres = cluster_spheres(&params, input).unwrap();
+        assert_eq!(spheres.len(), 2);
+        assert_eq!((spheres[0].center.as_slice(), spheres[0].size), (&b"AAAAAA"[..], 25));
+        let members: Vec<(&[u8], usize, usize)> = spheres[0].members.iter()
+            .map(|member| (member.sequence.as_slice(), member.count, member.distance))
+            .collect();
+        assert_eq!(members, vec![(&b"AAAAAA"[..], 20, 0), (&b"AAAAAT"[..], 3, 1), (&b"AAAATT"[..], 2, 2)]);
+        let components = cluster_components(&params, input).unwrap();
+        assert_eq!((components[0].centroid.as_slice(), components[0].count), (&b"AAAAAA"[..], 25));
+        assert_eq!((components[0].members[0].sequence.as_slice(), components[0].members[0].count), (&b"AAAAAA"[..], 20));
+
+        // All the pairs within tau of random barcodes, with any number of threads
+        let mut rng = rand::thread_rng();
+        let centers: Vec<Vec<u8>> = (0..20).map(|_| generate_random_nucleotide_sequence(12).0).collect();
+        let sequences = mutated_barcodes(&mut rng, &centers, 400, 3);
+        let mut unique: Vec<&[u8]> = sequences.iter().map(|(sequence, _)| sequence.as_slice()).collect();
+        unique.sort();
+        unique.dedup();
+        for tau in [0, 1, 2, 3] {
+            let mut expected = Vec::new();
+            for (i, a) in unique.iter().enumerate() {
+                for b in &unique[i + 1..] {
+                    if hamming(a, b) <= tau {
+                        expected.push((*a, *b, hamming(a, b)));
+                    }
+                }
+            }
+            let params = StarcodeParams::builder().max_distance(tau as i32).metric(DistanceMetric::Hamming).build().unwrap();
+            let graph = match_graph(&params, sequences.iter().cloned()).unwrap();
+            assert_eq!(graph_pairs(&graph), expected, "tau {}", tau);
+            let params = StarcodeParams::builder().max_distance(tau as i32).metric(DistanceMetric::Hamming).threads(4).build().unwrap();
+            assert_eq!(match_graph(&params, sequences.iter().cloned()).unwrap(), graph);
+
+            // The clusters are those of message passing over the graph
+            let (alignment, _) = cluster_with_graph(&params, sequences.iter().cloned()).unwrap();
+            let expected = cluster_graph(&params, sequences.iter().cloned(), &MessagePassing::default()).unwrap();
+            assert_eq!(alignment.cluster_centers, expected.cluster_centers);
+            assert_eq!(alignment.cluster_count, expected.cluster_count);
+        }
+
+        // starcode only runs by Levenshtein distance
+        assert!(matches!(assign_reads(&params, &["ACGT", "ACGA"]), Err(StarcodeError::InvalidParameter(_))));
+        let pairs = [(MatePair { first: b"ACGT".to_vec(), second: b"ACGT".to_vec() }, 1usize)];
+        assert!(matches!(cluster_pairs(&params, pairs), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(match_graph(&params, Vec::<(&[u8], usize)>::new()), Err(StarcodeError::EmptyInput)));
+    }
+
+    #[test]
+    fn test_whitelist_correction() {
+        let whitelist = Whitelist::new(
+            ["ACGTACGT", "ACGTACGA", "TTTTGGGG", "acgtacgt", "GGGGAAAA", "GGGGAAAT"], 2).unwrap();
+        let reads = ["ACGTACGT", "ACGTACGC", "ACGTACGT", "ACGTACGA", "TTTTGGGC", "GGGGAAAC",
+                     "ACGTACGT", "AAAAAAAA", "acgtacgt"];
+        let correction = whitelist.correct(&reads, 1).unwrap();
+        assert_eq!(correction.assignments, vec![
+            BarcodeAssignment::Assigned { barcode: 0, distance: 0 },
+            // as near to the first two barcodes, the first has more exact matches
+            BarcodeAssignment::Assigned { barcode: 0, distance: 1 },
+            BarcodeAssignment::Assigned { barcode: 0, distance: 0 },
+            BarcodeAssignment::Assigned { barcode: 1, distance: 0 },
+            BarcodeAssignment::Assigned { barcode: 2, distance: 1 },
+            // as near to the last two barcodes, neither has exact matches
+            BarcodeAssignment::Ambiguous,
+            BarcodeAssignment::Assigned { barcode: 0, distance: 0 },
+            BarcodeAssignment::Unassigned,
+            BarcodeAssignment::Assigned { barcode: 0, distance: 0 },
+        ]);
+        assert_eq!(correction.counts, vec![5, 1, 1, 0, 0, 0]);
+        assert_eq!((correction.exact, correction.corrected, correction.ambiguous, correction.unassigned), (5, 2, 1, 1));
+
+        // Same as the nearest barcodes by brute force
+        let mut rng = rand::thread_rng();
+        let mut barcodes: Vec<Vec<u8>> = (0..50).map(|_| generate_random_nucleotide_sequence(10).0).collect();
+        barcodes.sort();
+        barcodes.dedup();
+        let reads: Vec<Vec<u8>> = mutated_barcodes(&mut rng, &barcodes, 2000, 4).into_iter()
+            .map(|(read, _)| read)
+            .collect();
+        let whitelist = Whitelist::new(&barcodes, 3).unwrap();
+        for tau in [0, 1, 2, 3] {
+            let correction = whitelist.correct(&reads, tau).unwrap();
+            let abundance: Vec<usize> = barcodes.iter()
+                .map(|barcode| reads.iter().filter(|read| *read == barcode).count())
+                .collect();
+            for (read, assignment) in reads.iter().zip(&correction.assignments) {
+                let distances: Vec<usize> = barcodes.iter().map(|barcode| levenshtein(read, barcode)).collect();
+                let nearest = *distances.iter().min().unwrap();
+                let candidates: Vec<usize> = (0..barcodes.len()).filter(|&i| distances[i] == nearest).collect();
+                let best = candidates.iter().map(|&i| abundance[i]).max().unwrap();
+                let winners: Vec<usize> = candidates.into_iter().filter(|&i| abundance[i] == best).collect();
+                let expected = match winners[..] {
+                    _ if nearest > tau => BarcodeAssignment::Unassigned,
+                    [barcode] => BarcodeAssignment::Assigned { barcode, distance: nearest },
+                    _ => BarcodeAssignment::Ambiguous,
+                };
+                assert_eq!(*assignment, expected, "tau {}", tau);
+            }
+            assert_eq!(correction.exact + correction.corrected + correction.ambiguous + correction.unassigned, reads.len());
+            assert_eq!(correction.counts.iter().sum::<usize>(), correction.exact + correction.corrected);
+        }
+
+        assert!(matches!(whitelist.correct(&reads, 4), Err(StarcodeError::InvalidParameter(_))));
+        assert!(matches!(whitelist.correct(&["ACGT", "AC-T"], 1), Err(StarcodeError::InvalidEntry { index: 1, .. })));
+        assert!(matches!(Whitelist::new(Vec::<&[u8]>::new(), 1), Err(StarcodeError::EmptyInput)));
+    }
+
+    #[cfg(feature = "pure-rust")]
+    #[test]
+    fn test_native_cluster() {
+        fn parts(alignment: &StarcodeAlignment) -> impl PartialEq + fmt::Debug + '_ {
+            (&alignment.cluster_centers, &alignment.cluster_count, &alignment.cluster_members, &alignment.cluster_member_ids)
+        }
+        let check = |sequences: &[(Vec<u8>, usize)]| {
+            for (max_distance, cluster_ratio) in [(1, 1.0), (2, 1.0), (2, 5.0), (3, 2.0), (-1, 5.0)] {
+                let params = StarcodeParams::builder().max_distance(max_distance).cluster_ratio(cluster_ratio)
+                    .show_members(true).show_ids(true).build().unwrap();
+                let expected = cluster(&params, sequences.iter().cloned()).unwrap();
+                let native = native::cluster(&params, sequences.iter().cloned()).unwrap();
+                assert_eq!(parts(&native), parts(&expected), "max_distance {} cluster_ratio {}", max_distance, cluster_ratio);
+            }
+        };
+
+        for name in ["test_file1.fastq", "test_file2.fastq", "test_file_spheres.fastq"] {
+            let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("test").join(name);
+            let records = records::read_fastq_records(io::BufReader::new(File::open(path).unwrap())).unwrap();
+            let sequences: Vec<(Vec<u8>, usize)> = records.into_iter().map(|record| (record.sequence, 1)).collect();
+            check(&sequences);
+        }
+
+        // Sequences a few edits away from a few centers, with repeats, small counts (for ties),
+        // then an N or an indel in some of them
+        let mut rng = rand::thread_rng();
+        for _ in 0..20 {
+            let centers: Vec<Vec<u8>> = (0..rng.gen_range(1..30))
+                .map(|_| generate_random_nucleotide_sequence(rng.gen_range(8..=16)).0)
+                .collect();
+            let copies = rng.gen_range(1..400);
+            let mut sequences = mutated_barcodes(&mut rng, &centers, copies, 3);
+            for (sequence, _) in sequences.iter_mut() {
+                let position = rng.gen_range(0..sequence.len());
+                let base = *b"ACGTN".choose(&mut rng).unwrap();
+                match rng.gen_range(0..4) {
+                    0 => sequence[position] = base,
+                    1 => sequence.insert(position, base),
+                    2 if sequence.len() > 1 => { sequence.remove(position); }
+                    _ => {}
+                }
+            }
+            check(&sequences);
+        }
+
+        let params = StarcodeParams::builder().build().unwrap();
+        assert!(matches!(native::cluster(&params, Vec::<(&[u8], usize)>::new()), Err(StarcodeError::EmptyInput)));
+        let spheres = StarcodeParams::builder().algorithm(ClusterAlgorithm::Spheres).build().unwrap();
+        assert!(matches!(native::cluster(&spheres, [("ACGT", 1usize)]), Err(StarcodeError::InvalidParameter(_))));
+    }
+
+    fn mate_pair(first: &str, second: &str) -> MatePair {
+        MatePair { first: first.as_bytes().to_vec(), second: second.as_bytes().to_vec() }
+    }
+
+    #[test]
+    fn test_cluster_pairs() {
+        let mut pairs: FxHashMap<MatePair,usize> = FxHashMap::default();
+        pairs.insert(mate_pair("AAAAAAAAAA", "CCCCCCCCCC"), 3);
+        pairs.insert(mate_pair("AAAAAAAAAT", "CCCCCCCCCC"), 1);
+        pairs.insert(mate_pair("GGGGGGGGGG", "TTTTTTTTTT"), 1);
+        let expected = vec![
+            PairedCluster {
+                canonical: mate_pair("AAAAAAAAAA", "CCCCCCCCCC"),
+                count: 4,
+                members: vec![mate_pair("AAAAAAAAAA", "CCCCCCCCCC"), mate_pair("AAAAAAAAAT", "CCCCCCCCCC")],
+            },
+            PairedCluster {
+                canonical: mate_pair("GGGGGGGGGG", "TTTTTTTTTT"),
+                count: 1,
+                members: vec![mate_pair("GGGGGGGGGG", "TTTTTTTTTT")],
+            },
+        ];
+
+        // The same pairs as fastq files, one record per read
+        let mut reads1 = Vec::new();
+        let mut reads2 = Vec::new();
+        for (i, (pair, count)) in pairs.iter().enumerate() {
+            for j in 0..*count {
+                let header = format!("p{}.{}", i, j);
+                reads1.push(fastq_record(&header, std::str::from_utf8(&pair.first).unwrap()));
+                reads2.push(fastq_record(&header, std::str::from_utf8(&pair.second).unwrap()));
+            }
+        }
+        let (fastq1, fastq2) = (write_fastq(&reads1), write_fastq(&reads2));
+        let gzip1 = gzipped(fastq1.path());
+
+        for algorithm in [ClusterAlgorithm::MessagePassing, ClusterAlgorithm::Spheres, ClusterAlgorithm::ConnectedComponents] {
+            let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).algorithm(algorithm).build().unwrap();
+            assert_eq!(cluster_pairs(&params, &pairs).unwrap(), expected, "{:?}", algorithm);
+            assert_eq!(cluster_pair_files(&params, fastq1.path(), fastq2.path()).unwrap(), expected, "{:?}", algorithm);
+            assert_eq!(cluster_pair_files(&params, gzip1.path(), fastq2.path()).unwrap(), expected, "{:?}", algorithm);
+        }
+
+        let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).show_members(false).build().unwrap();
+        assert!(cluster_pairs(&params, &pairs).unwrap().iter().all(|cluster| cluster.members.is_empty()));
+
+        let params = StarcodeParams::builder().output(OutputMode::Tidy).build().unwrap();
+        assert!(matches!(cluster_pairs(&params, &pairs), Err(StarcodeError::InvalidParameter(_))));
+        let params = StarcodeParams::builder().output(OutputMode::NonRedundant).build().unwrap();
+        assert!(matches!(cluster_pairs(&params, &pairs),
+            Err(StarcodeError::InvalidParameter(message)) if message == "non-redundant output requires fastq input"));
+        let params = StarcodeParams::builder().build().unwrap();
+        assert!(matches!(cluster_pairs(&params, &FxHashMap::<MatePair,usize>::default()), Err(StarcodeError::EmptyInput)));
+        assert!(matches!(cluster_pair_files(&params, fastq1.path(), Path::new("/nonexistent/starcode/input.fastq")), Err(StarcodeError::Io(_))));
+        let mut invalid = pairs.clone();
+        invalid.insert(mate_pair("AAAAAAAAAA", "CC-CCCCCCC"), 1);
+        assert!(matches!(cluster_pairs(&params, &invalid),
+            Err(StarcodeError::InvalidEntry { error: EntryError::InvalidCharacter { position: 2, character: b'-' }, .. })));
+
+
+        // The mates joined by STARCODE_MAX_TAU + 1 dashes fit in MAXBRCDLEN up to 1023
+        let limit = MAXBRCDLEN as usize - STARCODE_MAX_TAU as usize - 1;
+        for (length1, length2) in [(limit / 2, limit - limit / 2), (limit / 2 + 1, limit - limit / 2), (1020, 1020)] {
+            let first = std::str::from_utf8(&generate_random_nucleotide_sequence(length1).0).unwrap().to_string();
+            let second = std::str::from_utf8(&generate_random_nucleotide_sequence(length2).0).unwrap().to_string();
+            let pairs: FxHashMap<MatePair, usize> = vec![(mate_pair(&first, &second), 2)].into_iter().collect();
+            let records1 = vec![fastq_record("p0", &first), fastq_record("p1", &first)];
+            let records2 = vec![fastq_record("p0", &second), fastq_record("p1", &second)];
+            let (fastq1, fastq2) = (write_fastq(&records1), write_fastq(&records2));
+            let clustered = cluster_pairs(&params, &pairs);
+            let from_files = cluster_pair_files(&params, fastq1.path(), fastq2.path());
+            if length1 + length2 <= limit {
+                let expected = vec![PairedCluster {
+                    canonical: mate_pair(&first, &second),
+                    count: 2,
+                    members: vec![mate_pair(&first, &second)],
+                }];
+                assert_eq!(clustered.unwrap(), expected);
+                assert_eq!(from_files.unwrap(), expected);
+            } else {
+                let length = length1 + STARCODE_MAX_TAU as usize + 1 + length2;
+                assert!(matches!(clustered, Err(StarcodeError::InvalidEntry { index: 0, error: EntryError::TooLong { length: l } }) if l == length),
+                    "{} {}", length1, length2);
+                assert!(matches!(from_files, Err(StarcodeError::SequenceTooLong)), "{} {}", length1, length2);
+            }
+        }
+    }
+
+    fn chain_input() -> FxHashMap<Vec<u8>,usize> {
+        // Each sequence is at distance 1 of the next.
+        let mut knowns: FxHashMap<Vec<u8>,usize> = FxHashMap::default();
+        knowns.insert(b"AAAAAAAAAA".to_vec(), 10);
+        knowns.insert(b"AAAAAAAAAC".to_vec(), 5);
+        knowns.insert(b"AAAAAAAACC".to_vec(), 3);
+        knowns.insert(b"AAAAAAACCC".to_vec(), 2);
+        knowns.insert(b"GGGGGGGGGG".to_vec(), 1);
+        knowns
+    }
+
+    #[test]
+    fn test_cluster_spheres() {
+        let params = StarcodeParams::builder().max_distance(1).build().unwrap();
+        let spheres = cluster_spheres(&params, &chain_input()).unwrap();
+
+        let centers: Vec<&[u8]> = spheres.iter().map(|s| s.center.as_slice()).collect();
+        assert_eq!(centers, vec![&b"AAAAAAAAAA"[..], &b"AAAAAAAACC"[..], &b"GGGGGGGGGG"[..]]);
+        let sizes: Vec<usize> = spheres.iter().map(|s| s.size).collect();
+        assert_eq!(sizes, vec![15, 5, 1]);
+        assert_eq!(spheres[0].members, vec![
+            SphereMember { sequence: b"AAAAAAAAAA".to_vec(), count: 10, distance: 0 },
+            SphereMember { sequence: b"AAAAAAAAAC".to_vec(), count: 5, distance: 1 },
+        ]);
+        assert_eq!(spheres[1].members[1], SphereMember { sequence: b"AAAAAAACCC".to_vec(), count: 2, distance: 1 });
+        for sphere in &spheres {
+            assert_eq!(sphere.size, sphere.members.iter().map(|m| m.count).sum::<usize>());
+        }
+
+        // The generic entry point reports the sphere sizes as cluster counts.
+        let params = StarcodeParams::builder().max_distance(1).algorithm(ClusterAlgorithm::Spheres).build().unwrap();
+        assert_eq!(cluster(&params, &chain_input()).unwrap().cluster_count, vec![15, 5, 1]);
+    }
+
+    #[test]
+    fn test_cluster_components() {
+        let params = StarcodeParams::builder().max_distance(1).build().unwrap();
+        let components = cluster_components(&params, &chain_input()).unwrap();
+
+        assert_eq!(components.len(), 2);
+        assert_eq!(components[0].centroid, b"AAAAAAAAAA".to_vec());
+        assert_eq!(components[0].count, 20);
+        assert_eq!(components[0].members[0], ComponentMember { sequence: b"AAAAAAAAAA".to_vec(), count: 10 });
+        let mut members: Vec<(Vec<u8>, usize)> = components[0].members.iter().map(|m| (m.sequence.clone(), m.count)).collect();
+        members.sort();
+        assert_eq!(members, vec![
+            (b"AAAAAAAAAA".to_vec(), 10),
+            (b"AAAAAAAAAC".to_vec(), 5),
+            (b"AAAAAAAACC".to_vec(), 3),
+            (b"AAAAAAACCC".to_vec(), 2),
+        ]);
+        assert_eq!(components[1].members, vec![ComponentMember { sequence: b"GGGGGGGGGG".to_vec(), count: 1 }]);
+
+        let params = StarcodeParams::builder().max_distance(1).algorithm(ClusterAlgorithm::ConnectedComponents).build().unwrap();
+        assert_eq!(cluster(&params, &chain_input()).unwrap().cluster_count, vec![20, 1]);
+    }
+
+    // Barcodes with high counts surrounded by low count variants, from a fixed seed
+    fn barcode_library() -> FxHashMap<Vec<u8>,usize> {
+        let mut state: u64 = 0x5eed;
+        let mut next = move |n: u64| {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            ((state >> 33) % n) as usize
+        };
+        let mut library: FxHashMap<Vec<u8>,usize> = FxHashMap::default();
+        for _ in 0..300 {
+            let barcode: Vec<u8> = (0..16).map(|_| b"ACGT"[next(4)]).collect();
+            *library.entry(barcode.clone()).or_insert(0) += 20 + next(100);
+            for _ in 0..10 {
+                let mut variant = barcode.clone();
+                for _ in 0..1 + next(2) {
+                    let position = next(variant.len() as u64);
+                    variant[position] = b"ACGT"[next(4)];
+                }
+                *library.entry(variant).or_insert(0) += 1 + next(3);
+            }
+        }
+        library
+    }
+
+    // Cluster order and member order may depend on thread timing when counts tie
+    fn normalized(alignment: StarcodeAlignment) -> Vec<(Vec<u8>, usize, Vec<Vec<u8>>)> {
+        let mut clusters: Vec<(Vec<u8>, usize, Vec<Vec<u8>>)> = alignment.cluster_centers.into_iter()
+            .zip(alignment.cluster_count)
+            .zip(alignment.cluster_members)
+            .map(|((center, count), mut members)| {
+                members.sort();
+                (center, count, members)
+            })
+            .collect();
+        clusters.sort();
+        clusters
+    }
+
+    #[test]
+    fn test_multithreaded_matches_single_threaded() {
+        let library = barcode_library();
+        for algorithm in [ClusterAlgorithm::MessagePassing, ClusterAlgorithm::Spheres, ClusterAlgorithm::ConnectedComponents] {
+            let params = StarcodeParams::builder().max_distance(2).algorithm(algorithm).build().unwrap();
+            let single = cluster(&params, &library).unwrap();
+            let expected_total: usize = library.values().sum();
+            if algorithm != ClusterAlgorithm::MessagePassing {
+                assert_eq!(single.cluster_count.iter().sum::<usize>(), expected_total);
+            }
+            let single = normalized(single);
+            for threads in [2, 3, 4, 8] {
+                let params = StarcodeParams::builder().max_distance(2).algorithm(algorithm).threads(threads).build().unwrap();
+                let multi = normalized(cluster(&params, &library).unwrap());
+                assert_eq!(multi, single, "{:?} with {} threads", algorithm, threads);
+            }
+        }
+
+        // More than 127 threads overflowed the 'char' thread counter of the scheduler.
+        let params = StarcodeParams::builder().max_distance(2).build().unwrap();
+        let single = normalized(cluster(&params, &library).unwrap());
+        let params = StarcodeParams::builder().max_distance(2).threads(130).build().unwrap();
+        assert_eq!(normalized(cluster(&params, &library).unwrap()), single);
+    }
+
+    #[test]
+    fn test_concurrent_multithreaded_runs() {
+        let library = std::sync::Arc::new(barcode_library());
+        let params = StarcodeParams::builder().max_distance(2).build().unwrap();
+        let expected = normalized(cluster(&params, &*library).unwrap());
+
+        // Starcode threads are started from several Rust threads at once.
+        let handles: Vec<_> = (0..4).map(|_| {
+            let library = library.clone();
+            thread::spawn(move || {
+                let params = StarcodeParams::builder().max_distance(2).threads(4).build().unwrap();
+                normalized(cluster(&params, &*library).unwrap())
+            })
+        }).collect();
+        for handle in handles {
+            assert_eq!(handle.join().unwrap(), expected);
+        }
+    }
+
+    fn small_input() -> FxHashMap<Vec<u8>,usize> {
+        let mut knowns: FxHashMap<Vec<u8>,usize> = FxHashMap::default();
+        knowns.insert(b"AAAAAAAAAA".to_vec(), 10);
+        knowns.insert(b"AAAAAAAAAC".to_vec(), 1);
+        knowns.insert(b"GGGGGGGGGG".to_vec(), 3);
+        knowns
+    }
+
+    #[test]
+    fn test_input_errors_are_reported() {
+        let mut invalid = small_input();
+        invalid.insert(b"ACGTX".to_vec(), 1);
+        assert!(matches!(StarcodeAlignment::try_align_sequences(&invalid, &2, &2.0),
+            Err(StarcodeError::InvalidEntry { error: EntryError::InvalidCharacter { position: 4, character: b'X' }, .. })));
+
+        let mut too_long = small_input();
+        too_long.insert(vec![b'A'; MAXBRCDLEN as usize + 1], 1);
+        assert!(matches!(StarcodeAlignment::try_align_sequences(&too_long, &2, &2.0),
+            Err(StarcodeError::InvalidEntry { error: EntryError::TooLong { .. }, .. })));
+
+        // The C core checks the sequences too
+        for (sequence, expected) in [
+            (b"ACGTX".to_vec(), starcode_status_t_STARCODE_INVALID_INPUT),
+            (vec![b'A'; MAXBRCDLEN as usize + 1], starcode_status_t_STARCODE_SEQ_TOO_LONG),
+        ] {
+            let c_sequence = CString::new(sequence).unwrap();
+            let mut sequence_ptrs = [c_sequence.as_ptr() as *mut c_char];
+            let counts: [c_long; 1] = [1];
+            let result = unsafe {
+                let outputf1 = libc::tmpfile();
+                let result = starcode_seqs(sequence_ptrs.as_mut_ptr(), counts.as_ptr(), 1,
+                    outputf1 as *mut FILE, std::ptr::null_mut(), 2, 0, 1, 0, 2.0, 0, 0, 0);
+                libc::fclose(outputf1);
+                result
+            };
+            assert_eq!(result, expected as i32);
+        }
+
+        let missing = CString::new("/nonexistent/starcode/input.txt").unwrap();
+        let output = NamedTempFile::new().unwrap();
+        let output_path = path_to_cstring(output.path()).unwrap();
+        let result = unsafe {
+            starcode_helper(missing.as_ptr() as *mut c_char, output_path.as_ptr() as *mut c_char, 2, 0, 1, 0, 2.0, 1, 0, 0)
+        };
+        assert!(matches!(status_to_result(result), Err(StarcodeError::Io(_))));
+
+        // Only exported clusters can do without an output file
+        let sequences = small_input();
+        let c_sequences: Vec<CString> = sequences.keys().map(|s| CString::new(s.as_slice()).unwrap()).collect();
+        let mut sequence_ptrs: Vec<*mut c_char> = c_sequences.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+        let counts: Vec<c_long> = sequences.values().map(|c| *c as c_long).collect();
+        let result = unsafe {
+            starcode_seqs(sequence_ptrs.as_mut_ptr(), counts.as_ptr(), sequence_ptrs.len(),
+                std::ptr::null_mut(), std::ptr::null_mut(), 2, 0, 1, 0, 2.0, 0, 0, output_t_TIDY_OUTPUT as i32)
+        };
+        assert!(matches!(status_to_result(result), Err(StarcodeError::Io(_))));
+
+        let result = unsafe {
+            starcode_seqs(std::ptr::null_mut(), std::ptr::null(), 0, std::ptr::null_mut(), std::ptr::null_mut(), 2, 0, 1, 0, 2.0, 1, 0, 0)
+        };
+        assert!(matches!(status_to_result(result), Err(StarcodeError::EmptyInput)));
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn test_allocation_failures_are_reported() {
+        let sequences = small_input();
+        let expected = StarcodeAlignment::try_align_sequences(&sequences, &2, &2.0).unwrap();
+
+        // Fail each allocation site in turn until the run gets through all of them.
+        let mut n = 1;
+        loop {
+            unsafe { starcode_inject_fault(starcode_status_t_STARCODE_ALLOC_ERROR as i32, n) };
+            let result = StarcodeAlignment::try_align_sequences(&sequences, &2, &2.0);
+            unsafe { starcode_inject_fault(starcode_status_t_STARCODE_OK as i32, 0) };
+            match result {
+                Err(StarcodeError::OutOfMemory) => n += 1,
+                Ok(_) => break,
+                Err(e) => panic!("unexpected error after fault {}: {}", n, e),
+            }
+            assert!(n < 100, "allocation faults never stop");
+        }
+        assert!(n > 1);
+
+        // The failed runs leave nothing behind that changes the next result.
+        let alignment = StarcodeAlignment::try_align_sequences(&sequences, &2, &2.0).unwrap();
+        assert_eq!(alignment.cluster_centers, expected.cluster_centers);
+        assert_eq!(alignment.cluster_count, expected.cluster_count);
+    }
+
+    #[cfg(feature = "fault-injection")]
+    #[test]
+    fn test_thread_and_internal_failures_are_reported() {
+        let sequences = small_input();
+
+        unsafe { starcode_inject_fault(starcode_status_t_STARCODE_THREAD_ERROR as i32, 1) };
+        let result = StarcodeAlignment::try_align_sequences(&sequences, &2, &2.0);
+        unsafe { starcode_inject_fault(starcode_status_t_STARCODE_OK as i32, 0) };
+        assert!(matches!(result, Err(StarcodeError::Thread)));
+
+        let c_sequences: Vec<CString> = sequences.keys().map(|s| CString::new(s.as_slice()).unwrap()).collect();
+        let mut sequence_ptrs: Vec<*mut c_char> = c_sequences.iter().map(|s| s.as_ptr() as *mut c_char).collect();
+        let counts: Vec<c_long> = sequences.values().map(|c| *c as c_long).collect();
+        let result = unsafe {
+            let outputf1 = libc::tmpfile();
+            starcode_inject_fault(starcode_status_t_STARCODE_INTERNAL_ERROR as i32, 1);
+            let result = starcode_seqs(sequence_ptrs.as_mut_ptr(), counts.as_ptr(), sequence_ptrs.len(),
+                outputf1 as *mut FILE, std::ptr::null_mut(), 2, 0, 1, 0, 2.0, 0, 0, output_t_TIDY_OUTPUT as i32);
+            starcode_inject_fault(starcode_status_t_STARCODE_OK as i32, 0);
+            libc::fclose(outputf1);
+            result
+        };
+        assert!(matches!(status_to_result(result), Err(StarcodeError::Internal)));
+    }
+
+    // Heap memory in use by the whole process, starcode and Rust alike
+    #[cfg(all(target_os = "linux", target_env = "gnu"))]
+    fn heap_in_use() -> usize {
+        let info = unsafe { libc::mallinfo2() };
+        info.uordblks + info.hblkhd
+    }
+
+    #[cfg(target_os = "macos")]
+    fn heap_in_use() -> usize {
+        let mut stats: libc::malloc_statistics_t = unsafe { std::mem::zeroed() };
+        unsafe { libc::malloc_zone_statistics(std::ptr::null_mut(), &mut stats) };
+        stats.size_in_use
+    }
+
+    const LEAK_CHECK_VAR: &str = "RUST_STARCODE_LEAK_CHECK";
+
+    type NamedRun<'a> = (&'a str, Box<dyn Fn() + 'a>);
+
+    #[cfg(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos"))]
+    #[test]
+    fn test_repeated_runs_do_not_leak() {
+        // The heap is shared with the tests running in parallel, measure it in a
+        // process that runs nothing else
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["tests::leak_check", "--exact", "--ignored", "--test-threads=1", "--nocapture"])
+            .env(LEAK_CHECK_VAR, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[cfg(any(all(target_os = "linux", target_env = "gnu"), target_os = "macos"))]
+    #[test]
+    #[ignore = "run alone by test_repeated_runs_do_not_leak"]
+    fn leak_check() {
+        if std::env::var_os(LEAK_CHECK_VAR).is_none() {
+            return;
+        }
+        let sequences: FxHashMap<Vec<u8>,usize> = (0..2000)
+            .map(|_| generate_random_nucleotide_sequence(20))
+            .collect();
+        let reads: Vec<&[u8]> = sequences.keys().map(|sequence| &sequence[..]).collect();
+        let pairs: FxHashMap<MatePair,usize> = reads.chunks(2)
+            .filter(|pair| pair.len() == 2)
+            .map(|pair| (MatePair { first: pair[0].to_vec(), second: pair[1].to_vec() }, 1))
+            .collect();
+        let raw = NamedTempFile::new().unwrap();
+        std::fs::write(raw.path(), reads.join(&b'\n')).unwrap();
+        let records: Vec<FastqRecord> = reads.iter().enumerate()
+            .map(|(i, read)| fastq_record(&format!("read{} sample", i), std::str::from_utf8(read).unwrap()))
+            .collect();
+        let fastq = gzipped(write_fastq(&records).path());
+
+        let params = StarcodeParams::builder().max_distance(3).cluster_ratio(2.0)
+            .show_members(true).show_ids(true).build().unwrap();
+        let runs: Vec<NamedRun> = vec![
+            ("message passing", Box::new(|| { cluster(&params, &sequences).unwrap(); })),
+            ("spheres", Box::new(|| { cluster_spheres(&params, &sequences).unwrap(); })),
+            ("components", Box::new(|| { cluster_components(&params, &sequences).unwrap(); })),
+            ("tidy", Box::new(|| { assign_reads(&params, &reads).unwrap(); })),
+            ("pairs", Box::new(|| { cluster_pairs(&params, &pairs).unwrap(); })),
+            ("file", Box::new(|| { cluster_file(&params, raw.path()).unwrap(); })),
+            ("non-redundant", Box::new(|| { representatives(&params, fastq.path()).unwrap(); })),
+            ("trie", Box::new(|| {
+                let mut trie: trie::Trie<Vec<usize>> = trie::Trie::new(20).unwrap();
+                for (i, read) in reads.iter().enumerate() {
+                    trie.insert(read).unwrap().push(i);
+                }
+                for read in &reads[..100] {
+                    trie.search(read, 3).unwrap();
+                }
+            })),
+            ("index", Box::new(|| {
+                let index = BarcodeIndex::new(&reads, 3).unwrap();
+                index.query_batch(&reads[..100], 3).unwrap();
+            })),
+        ];
+        // A failed run frees what it allocated too
+        #[cfg(feature = "fault-injection")]
+        let runs = {
+            let mut runs = runs;
+            runs.push(("failed", Box::new(|| {
+                unsafe { starcode_inject_fault(starcode_status_t_STARCODE_THREAD_ERROR as i32, 3) };
+                let result = cluster(&params, &sequences);
+                unsafe { starcode_inject_fault(starcode_status_t_STARCODE_OK as i32, 0) };
+                assert!(matches!(result, Err(StarcodeError::Thread)));
+            })));
+            runs
+        };
+        for (name, run) in runs {
+            // The first runs fill the caches of the allocator
+            for _ in 0..3 {
+                run();
+            }
+            let before = heap_in_use();
+            for _ in 0..10 {
+                run();
+            }
+            let growth = heap_in_use().saturating_sub(before);
+            assert!(growth < 64 * 1024, "{} runs leaked {} bytes in 10 runs", name, growth);
+        }
+
+        // A context holds the allocations of its last run, and only those, until it is dropped
+        let before = heap_in_use();
+        let mut context = StarcodeContext::new();
+        context.cluster(&params, &sequences).unwrap();
+        let held = heap_in_use().saturating_sub(before);
+        for _ in 0..10 {
+            context.cluster(&params, &sequences).unwrap();
+        }
+        let growth = heap_in_use().saturating_sub(before).saturating_sub(held);
+        assert!(growth < 64 * 1024, "context runs leaked {} bytes in 10 runs", growth);
+        assert!(held > 256 * 1024, "the context holds {} bytes", held);
+        context.clear();
+        assert!(heap_in_use().saturating_sub(before) < 64 * 1024, "the context holds its run after clear");
+        context.cluster(&params, &sequences).unwrap();
+        drop(context);
+        let growth = heap_in_use().saturating_sub(before);
+        assert!(growth < 64 * 1024, "the context leaked {} bytes once dropped", growth);
+    }
+
     fn run_test_with_catch<T>(test: T) -> Result<(), String>
     where
         T: FnOnce() + panic::UnwindSafe
@@ -354,6 +3035,9 @@ mod tests {
         println!("Starting alternating sizes test");
         let result = run_test_with_catch(|| {
             let mut rng = rand::thread_rng();
+            // One context for all the runs, each frees the previous one
+            let mut context = StarcodeContext::new();
+            let params = StarcodeParams::builder().max_distance(2).cluster_ratio(2.0).build().unwrap();
             
             // Start with smaller iterations for debugging
             for i in 0..10 {
@@ -376,9 +3060,7 @@ mod tests {
                 // Create a separate scope to ensure resources are dropped
                 {
                     println!("Starting alignment for iteration {}", i);
-                    // Create a context explicitly to ensure cleanup
-                    let _context = StarcodeContext::new();
-                    let alignment = StarcodeAlignment::align_sequences(&sequences, &2, &2.0);
+                    let alignment = context.cluster(&params, &sequences).unwrap();
                     println!("Alignment complete with {} centers", alignment.cluster_centers.len());
                     
                     // Verify alignment results
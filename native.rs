@@ -0,0 +1,480 @@
+use rustc_hash::FxHashSet;
+
+use crate::cancel::Cancellation;
+use crate::clustering;
+use crate::{
+    ClusterAlgorithm, ClusteringAlgorithm, DistanceMetric, MatchEdge, MatchGraph, MessagePassing,
+    OutputMode, Progress, SequenceCount, SequenceCounts, Stage, StarcodeAlignment, StarcodeError,
+    StarcodeParams, TAU,
+};
+
+// Same as in starcode.c
+const MAX_K_FOR_LOOKUP: i32 = 14;
+const CANCEL_INTERVAL: usize = 1024;
+
+/// Same as `crate::cluster` with the message passing algorithm, in Rust only: the sequences
+/// are sorted, padded, searched in tries and clustered as starcode does, and the clusters
+/// are the same. The search runs on the calling thread, `params.threads` only sets how the
+/// sequences are split between tries (ties are broken as starcode does with one thread).
+pub fn cluster<I, S, C>(params: &StarcodeParams, input: I) -> Result<StarcodeAlignment, StarcodeError>
+where
+    I: IntoIterator<Item = (S, C)>,
+    S: AsRef<[u8]>,
+    C: SequenceCount,
+{
+    if params.output == OutputMode::Tidy {
+        return Err(StarcodeError::InvalidParameter(
+            "tidy output assigns reads, not clusters, use assign_reads instead".to_string()));
+    }
+    if params.algorithm != ClusterAlgorithm::MessagePassing {
+        return Err(StarcodeError::InvalidParameter(
+            "the pure-Rust backend only clusters by message passing".to_string()));
+    }
+    if params.metric != DistanceMetric::Levenshtein {
+        return Err(StarcodeError::InvalidParameter(
+            "the pure-Rust backend only searches by Levenshtein distance".to_string()));
+    }
+    let input = SequenceCounts::new(input, params)?;
+    if input.counts.is_empty() {
+        return Err(StarcodeError::EmptyInput);
+    }
+    let run = Run { cancellation: Cancellation::start(params), params };
+
+    run.report(Progress::Started(Stage::Reading));
+    let useqs: Vec<Useq> = input.sequences.iter().zip(&input.counts).enumerate()
+        .map(|(index, (sequence, &count))| Useq { seq: sequence.to_bytes().to_vec(), count: count as usize, ids: vec![index as u32] })
+        .collect();
+    run.report(Progress::Finished(Stage::Reading));
+
+    run.report(Progress::Started(Stage::Sorting));
+    let total = useqs.len();
+    let useqs = seqsort(useqs);
+    run.report(Progress::UniqueSequences { unique: useqs.len(), total });
+    run.report(Progress::Finished(Stage::Sorting));
+    run.check_cancelled()?;
+
+    run.report(Progress::Started(Stage::Padding));
+    let (height, median) = padding(&useqs);
+    let tau = params.tau(median);
+    run.report(Progress::Finished(Stage::Padding));
+
+    run.report(Progress::Started(Stage::Querying));
+    let edges = run.query(&useqs, tau, height, median)?;
+    run.report(Progress::Finished(Stage::Querying));
+    run.check_cancelled()?;
+
+    run.report(Progress::Started(Stage::Clustering));
+    let mut graph = MatchGraph { sequences: Vec::with_capacity(useqs.len()), counts: Vec::with_capacity(useqs.len()), edges };
+    let mut ids = Vec::with_capacity(useqs.len());
+    for useq in useqs {
+        graph.sequences.push(useq.seq);
+        graph.counts.push(useq.count);
+        ids.push(useq.ids);
+    }
+    let canonicals = MessagePassing { cluster_ratio: params.cluster_ratio }.cluster(&graph, &graph.to_csr())?;
+    run.report(Progress::Finished(Stage::Clustering));
+
+    run.report(Progress::Started(Stage::Output));
+    let alignment = StarcodeAlignment::from_exported_clusters(clustering::export(&graph, &canonicals, ids)?, params);
+    run.report(Progress::Finished(Stage::Output));
+    Ok(alignment)
+}
+
+// The 'useq_t' of starcode, the sequences are referred to by their position once sorted
+struct Useq {
+    seq: Vec<u8>,
+    count: usize,
+    // 0-based positions in the input
+    ids: Vec<u32>,
+}
+
+// Sorts by length then bytes and merges the repeated sequences into the first, as 'seqsort()'
+fn seqsort(mut useqs: Vec<Useq>) -> Vec<Useq> {
+    useqs.sort_by(|a, b| (a.seq.len(), &a.seq).cmp(&(b.seq.len(), &b.seq)));
+    let mut unique: Vec<Useq> = Vec::with_capacity(useqs.len());
+    for useq in useqs {
+        match unique.last_mut() {
+            Some(last) if last.seq == useq.seq => {
+                last.count += useq.count;
+                last.ids.extend(useq.ids);
+            }
+            _ => unique.push(useq),
+        }
+    }
+    unique
+}
+
+// Length of the longest sequence and median length, as 'pad_useq()' computes it
+fn padding(useqs: &[Useq]) -> (usize, usize) {
+    let height = useqs.iter().map(|useq| useq.seq.len()).max().unwrap_or(0);
+    let mut bins = vec![0usize; height + 1];
+    for useq in useqs {
+        bins[useq.seq.len()] += 1;
+    }
+    let mut median = 0;
+    let mut seen = 0;
+    loop {
+        median += 1;
+        seen += bins[median];
+        if seen >= useqs.len() / 2 {
+            return (height, median);
+        }
+    }
+}
+
+// The sequence padded with leading spaces to 'height'
+fn pad(seq: &[u8], height: usize) -> Vec<u8> {
+    let mut padded = vec![b' '; height - seq.len()];
+    padded.extend_from_slice(seq);
+    padded
+}
+
+fn shared_prefix(a: &[u8], b: &[u8]) -> usize {
+    a.iter().zip(b).take_while(|(a, b)| a == b).count()
+}
+
+struct Run<'a> {
+    params: &'a StarcodeParams,
+    cancellation: Cancellation,
+}
+
+impl Run<'_> {
+    fn report(&self, progress: Progress) {
+        if let Some(callback) = &self.params.progress {
+            callback.call(progress);
+        }
+    }
+
+    fn check_cancelled(&self) -> Result<(), StarcodeError> {
+        match self.cancellation.is_cancelled() {
+            true => Err(StarcodeError::Cancelled),
+            false => Ok(()),
+        }
+    }
+
+    // The pairs of sequences within 'tau' of each other, in the order of 'run_plan()' when it
+    // runs on one thread: the tries are built and queried in turn, each from one block
+    fn query(&self, useqs: &[Useq], tau: usize, height: usize, median: usize) -> Result<Vec<MatchEdge>, StarcodeError> {
+        let threads = self.params.threads;
+        let mut ntries = 3 * threads + threads.is_multiple_of(2) as usize;
+        if useqs.len() < ntries {
+            ntries = 1;
+        }
+        let (q, r) = (useqs.len() / ntries, useqs.len() % ntries);
+        let bounds: Vec<usize> = (0..=ntries).map(|i| q * i + i.min(r)).collect();
+        let padded: Vec<Vec<u8>> = useqs.iter().map(|useq| pad(&useq.seq, height)).collect();
+
+        let mut edges = Vec::new();
+        let mut tries: Vec<(Trie, Lookup)> = (0..ntries)
+            .map(|_| (Trie::default(), Lookup::new(median as i32, height as i32, tau as i32)))
+            .collect();
+        let njobs = ntries.div_ceil(2);
+        self.report(Progress::QueryJobs { done: 0, total: ntries * njobs });
+        for job in 0..njobs {
+            for (i, (trie, lookup)) in tries.iter_mut().enumerate() {
+                let block = (i + job) % ntries;
+                let build = job == 0;
+                let mut last_query = None;
+                for (n, query) in (bounds[block]..bounds[block + 1]).enumerate() {
+                    if n % CANCEL_INTERVAL == CANCEL_INTERVAL - 1 {
+                        self.check_cancelled()?;
+                    }
+                    let do_search = lookup.search(&padded[query]) == 1;
+                    let mut hits = Vec::new();
+                    if do_search {
+                        // The depth shared with the next query and with the previous search
+                        let trail = match query + 1 < bounds[block + 1] {
+                            true => shared_prefix(&padded[query], &padded[query + 1]),
+                            false => 0,
+                        };
+                        let start = last_query.map_or(0, |last: usize| shared_prefix(&padded[query], &padded[last]));
+                        hits = trie.search(&padded[query], tau, trail.max(start));
+                        last_query = Some(query);
+                    }
+                    if build {
+                        lookup.insert(&padded[query])?;
+                        trie.insert(&padded[query], query)?;
+                    }
+                    // Skip dist = 0, as this would be self
+                    for (dist, hits) in hits.iter().enumerate().skip(1) {
+                        for &hit in hits {
+                            edges.push(MatchEdge {
+                                query,
+                                target: hit,
+                                distance: dist,
+                                query_count: useqs[query].count,
+                                target_count: useqs[hit].count,
+                            });
+                        }
+                    }
+                }
+                self.report(Progress::QueryJobs { done: job * ntries + i + 1, total: ntries * njobs });
+            }
+        }
+        Ok(edges)
+    }
+}
+
+// A trie of padded sequences, searched with the "poucet" algorithm of trie.c: a dynamic
+// programming in a band of 'tau' around the diagonal of the padded sequences, cut when the
+// center of the band exceeds 'tau'. The hits and their distances are those of starcode,
+// which misses some pairs of sequences of different lengths near the padding
+#[derive(Default)]
+struct Trie {
+    root: Node,
+}
+
+#[derive(Default)]
+struct Node {
+    children: [Option<Box<Node>>; 6],
+    // the last 8 characters to the node, 4 bits each ('node_t.path')
+    path: u32,
+    // position of the sequence ending here (leaves only)
+    useq: Option<usize>,
+}
+
+const PAD: i32 = 5;
+// Width of the caches, 'TAU' in trie.h
+const BAND: usize = TAU as usize;
+// 'cache' of a new 'node_t': the cell 'a' away from the diagonal is 'a'
+const INIT: Cache = [8, 7, 6, 5, 4, 3, 2, 1, 0, 1, 2, 3, 4, 5, 6, 7, 8];
+
+// The band of the dynamic programming at a node, indexed from -BAND to BAND
+type Cache = [i32; 2 * BAND + 1];
+
+fn cell(cache: &Cache, a: i32) -> i32 {
+    cache[(BAND as i32 + a) as usize]
+}
+
+fn set_cell(cache: &mut Cache, a: i32, value: i32) {
+    cache[(BAND as i32 + a) as usize] = value;
+}
+
+// The 'translate' table of trie.c, to insert
+fn translate(c: u8) -> usize {
+    match c {
+        b' ' => PAD as usize,
+        b'A' | b'a' => 1,
+        b'C' | b'c' => 2,
+        b'G' | b'g' => 3,
+        b'T' | b't' => 4,
+        _ => 0,
+    }
+}
+
+// The 'altranslate' table of trie.c, to search: N never matches
+fn altranslate(c: u8) -> i32 {
+    match translate(c) {
+        0 => 6,
+        c => c as i32,
+    }
+}
+
+impl Trie {
+    fn insert(&mut self, padded: &[u8], useq: usize) -> Result<(), StarcodeError> {
+        let mut node = &mut self.root;
+        for &c in padded {
+            let path = node.path;
+            let c = translate(c);
+            node = node.children[c].get_or_insert_with(|| Box::new(Node { path: (path << 4) + c as u32, ..Node::default() }));
+        }
+        // Sequences that only differ by case end on the same leaf, starcode fails on them
+        if node.useq.replace(useq).is_some() {
+            return Err(StarcodeError::Internal);
+        }
+        Ok(())
+    }
+
+    // The sequences within 'tau' of the padded query by distance. Starcode restarts each
+    // search from the nodes the previous one cached ('pebbles') and does not 'dash()' above
+    // them nor above 'seed_depth', so the search only dashes below 'dash_depth', the larger
+    // of the depth shared with the previous query and 'seed_depth'
+    fn search(&self, padded: &[u8], tau: usize, dash_depth: usize) -> Vec<Vec<usize>> {
+        // As in 'search()': the length first, then the query
+        let mut query = Vec::with_capacity(padded.len() + 1);
+        query.push(padded.len() as i32);
+        query.extend(padded.iter().map(|&c| altranslate(c)));
+        let mut search = Search { query: &query, tau: tau as i32, dash_depth, hits: vec![Vec::new(); tau + 1] };
+        search.poucet(&self.root, &INIT, 1);
+        search.hits
+    }
+}
+
+struct Search<'a> {
+    query: &'a [i32],
+    tau: i32,
+    dash_depth: usize,
+    hits: Vec<Vec<usize>>,
+}
+
+impl Search<'_> {
+    fn height(&self) -> usize {
+        self.query.len() - 1
+    }
+
+    // Same as 'poucet()', 'depth' is that of the children of 'node'
+    fn poucet(&mut self, node: &Node, pcache: &Cache, depth: usize) {
+        let query = self.query;
+        let maxa = (depth as i32 - 1).min(self.tau);
+        let path = node.path;
+        let char_at = |a: i32| ((path >> (4 * (a - 1))) & 15) as i32;
+
+        // Upper arm of the L, the same for all the children
+        let mut common = [1, 2, 3, 4, 5, 6, 7, 8, 9];
+        if maxa > 0 {
+            let m = maxa as usize;
+            // "PAD exception": no cost to start after the padding of the query
+            let start = if query[depth - 1] == PAD { 0 } else { cell(pcache, maxa) };
+            let mmatch = start + (char_at(maxa) != query[depth]) as i32;
+            let shift = cell(pcache, maxa - 1).min(common[m]) + 1;
+            common[m - 1] = mmatch.min(shift);
+            for a in (1..maxa).rev() {
+                let mmatch = cell(pcache, a) + (char_at(a) != query[depth]) as i32;
+                let shift = cell(pcache, a - 1).min(common[a as usize]) + 1;
+                common[a as usize - 1] = mmatch.min(shift);
+            }
+        }
+
+        for (i, child) in node.children.iter().enumerate() {
+            let child = match child {
+                Some(child) => child,
+                None => continue,
+            };
+            let i = i as i32;
+            let mut ccache = INIT;
+            ccache[BAND + 1..].copy_from_slice(&common[..BAND]);
+
+            // Horizontal arm of the L
+            if maxa > 0 {
+                // "PAD exception": no cost to start after the padding of the trie
+                let start = if path & 15 == PAD as u32 { 0 } else { cell(pcache, -maxa) };
+                let mmatch = start + (i != query[depth - maxa as usize]) as i32;
+                let shift = cell(pcache, 1 - maxa).min(maxa + 1) + 1;
+                set_cell(&mut ccache, -maxa, mmatch.min(shift));
+                for a in (1..maxa).rev() {
+                    let mmatch = cell(pcache, -a) + (i != query[depth - a as usize]) as i32;
+                    let shift = cell(pcache, 1 - a).min(cell(&ccache, -a - 1)) + 1;
+                    set_cell(&mut ccache, -a, mmatch.min(shift));
+                }
+            }
+            let mmatch = cell(pcache, 0) + (i != query[depth]) as i32;
+            let shift = cell(&ccache, -1).min(cell(&ccache, 1)) + 1;
+            let center = mmatch.min(shift);
+            set_cell(&mut ccache, 0, center);
+
+            if center > self.tau {
+                continue;
+            }
+            if depth == self.height() {
+                if let Some(useq) = child.useq {
+                    self.hits[center as usize].push(useq);
+                }
+                continue;
+            }
+            // No more edits allowed: the rest of the query must be in the trie
+            if depth > self.dash_depth && (-maxa..=maxa).all(|a| cell(&ccache, a) >= self.tau) {
+                self.dash(child, depth + 1);
+                continue;
+            }
+            self.poucet(child, &ccache, depth + 1);
+        }
+    }
+
+    // Same as 'dash()': a hit at 'tau' if the suffix of the query from 'depth' is below 'node'
+    fn dash(&mut self, mut node: &Node, depth: usize) {
+        for &c in &self.query[depth..] {
+            node = match c {
+                1..=4 => match &node.children[c as usize] {
+                    Some(child) => child,
+                    None => return,
+                },
+                _ => return,
+            };
+        }
+        if let Some(useq) = node.useq {
+            self.hits[self.tau as usize].push(useq);
+        }
+    }
+}
+
+// The k-mer filter of starcode ('lookup_t'), sets instead of bitmaps
+struct Lookup {
+    slen: i32,
+    klen: Vec<i32>,
+    kmers: Vec<FxHashSet<i32>>,
+}
+
+impl Lookup {
+    fn new(slen: i32, maxlen: i32, tau: i32) -> Self {
+        let k = slen / (tau + 1);
+        let mut rem = tau - slen % (tau + 1);
+        let klen = (0..=tau).map(|_| {
+            if k > MAX_K_FOR_LOOKUP {
+                return MAX_K_FOR_LOOKUP;
+            }
+            let length = k - (rem > 0) as i32;
+            rem -= 1;
+            length
+        }).collect();
+        Lookup { slen: maxlen, klen, kmers: vec![FxHashSet::default(); tau as usize + 1] }
+    }
+
+    // 1 if the query may be within tau of an inserted sequence, 0 if not, -1 if it is too short
+    fn search(&self, padded: &[u8]) -> i32 {
+        let kmers = self.klen.len() as i32;
+        let mut offset = self.slen;
+        for i in (0..kmers).rev() {
+            let klen = self.klen[i as usize];
+            offset -= klen;
+            let shift = kmers - 1 - i;
+            for j in -shift..=shift {
+                match seq2id(padded, offset + j, klen) {
+                    -2 => return -1,
+                    -1 => continue,
+                    id if self.kmers[i as usize].contains(&id) => return 1,
+                    _ => {}
+                }
+            }
+        }
+        0
+    }
+
+    fn insert(&mut self, padded: &[u8]) -> Result<(), StarcodeError> {
+        let mut offset = self.slen;
+        for i in (0..self.klen.len()).rev() {
+            let klen = self.klen[i];
+            offset -= klen;
+            if offset + klen > padded.len() as i32 {
+                continue;
+            }
+            match seq2id(padded, offset, klen) {
+                -2 => return Err(StarcodeError::Internal),
+                -1 => {}
+                id => { self.kmers[i].insert(id); }
+            }
+        }
+        Ok(())
+    }
+}
+
+// Same as 'seq2id()' in starcode.c: the id of the k-mer of length 'slen' at 'start', -1 if
+// it has an N and -2 if it runs past the end
+fn seq2id(seq: &[u8], start: i32, slen: i32) -> i32 {
+    let mut id: i32 = 0;
+    for i in (slen - 16).max(0)..slen {
+        let position = start + i;
+        let c = if position >= 0 { seq.get(position as usize).copied().unwrap_or(0) } else { 0 };
+        id += match c {
+            b'A' | b'a' | b' ' => 0,
+            b'C' | b'c' => 1,
+            b'G' | b'g' => 2,
+            b'T' | b't' => 3,
+            0 => return -2,
+            _ => return -1,
+        };
+        if i < slen - 1 {
+            id <<= 2;
+        }
+    }
+    id
+}
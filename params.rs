@@ -0,0 +1,252 @@
+use std::time::Duration;
+
+use crate::{
+    cluster_t, cluster_t_COMPONENTS_CLUSTER, cluster_t_MP_CLUSTER, cluster_t_SPHERES_CLUSTER,
+    CancellationToken, Progress, StarcodeError, STARCODE_MAX_TAU, STARCODE_MAX_THREADS,
+};
+use crate::progress::ProgressCallback;
+
+/// Clustering algorithm run after the all-pairs search (the `cluster_t` enum of starcode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClusterAlgorithm {
+    MessagePassing,
+    Spheres,
+    ConnectedComponents,
+}
+
+impl ClusterAlgorithm {
+    pub(crate) fn as_cluster_t(self) -> cluster_t {
+        match self {
+            ClusterAlgorithm::MessagePassing => cluster_t_MP_CLUSTER,
+            ClusterAlgorithm::Spheres => cluster_t_SPHERES_CLUSTER,
+            ClusterAlgorithm::ConnectedComponents => cluster_t_COMPONENTS_CLUSTER,
+        }
+    }
+}
+
+/// Distance between two sequences within which they match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DistanceMetric {
+    // insertions, deletions and substitutions, searched by starcode in tries of padded sequences
+    Levenshtein,
+    // substitutions only, sequences of different lengths never match so that each length is
+    // clustered apart (for fixed-length barcodes)
+    Hamming,
+}
+
+/// What starcode reports for each cluster (the `output_t` enum of starcode)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputMode {
+    // canonical, count and optionally the members of every cluster
+    Clusters,
+    // one representative per cluster
+    NonRedundant,
+    // every input read next to its canonical
+    Tidy,
+}
+
+/// Validated settings for a starcode run, see `StarcodeParams::builder`
+#[derive(Debug, Clone, PartialEq)]
+pub struct StarcodeParams {
+    pub(crate) max_distance: i32,
+    pub(crate) metric: DistanceMetric,
+    pub(crate) threads: usize,
+    pub(crate) algorithm: ClusterAlgorithm,
+    pub(crate) cluster_ratio: f64,
+    pub(crate) show_members: bool,
+    pub(crate) show_ids: bool,
+    pub(crate) output: OutputMode,
+    pub(crate) verbose: bool,
+    pub(crate) normalize: bool,
+    pub(crate) cancellation_token: Option<CancellationToken>,
+    pub(crate) time_limit: Option<Duration>,
+    pub(crate) progress: Option<ProgressCallback>,
+}
+
+impl StarcodeParams {
+    pub fn builder() -> StarcodeParamsBuilder {
+        StarcodeParamsBuilder::default()
+    }
+
+    pub fn max_distance(&self) -> i32 {
+        self.max_distance
+    }
+
+    pub fn metric(&self) -> DistanceMetric {
+        self.metric
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    pub fn algorithm(&self) -> ClusterAlgorithm {
+        self.algorithm
+    }
+
+    pub fn cluster_ratio(&self) -> f64 {
+        self.cluster_ratio
+    }
+
+    pub fn show_members(&self) -> bool {
+        self.show_members
+    }
+
+    pub fn show_ids(&self) -> bool {
+        self.show_ids
+    }
+
+    pub fn output(&self) -> OutputMode {
+        self.output
+    }
+
+    pub fn verbose(&self) -> bool {
+        self.verbose
+    }
+
+    pub fn normalize(&self) -> bool {
+        self.normalize
+    }
+
+    pub fn cancellation_token(&self) -> Option<&CancellationToken> {
+        self.cancellation_token.as_ref()
+    }
+
+    pub fn time_limit(&self) -> Option<Duration> {
+        self.time_limit
+    }
+
+    // The maximum distance, picked from the median sequence length as starcode does when it
+    // is -1
+    pub(crate) fn tau(&self, median: usize) -> usize {
+        match self.max_distance {
+            tau if tau >= 0 => tau as usize,
+            _ if median > 160 => 8,
+            _ => 2 + median / 30,
+        }
+    }
+}
+
+impl Default for StarcodeParams {
+    fn default() -> Self {
+        StarcodeParams {
+            max_distance: -1,
+            metric: DistanceMetric::Levenshtein,
+            threads: 1,
+            algorithm: ClusterAlgorithm::MessagePassing,
+            cluster_ratio: 5.0,
+            show_members: true,
+            show_ids: false,
+            output: OutputMode::Clusters,
+            verbose: false,
+            normalize: false,
+            cancellation_token: None,
+            time_limit: None,
+            progress: None,
+        }
+    }
+}
+
+/// Builder for `StarcodeParams`. The defaults are those of the starcode command line,
+/// except that cluster members are reported.
+#[derive(Debug, Clone, Default)]
+pub struct StarcodeParamsBuilder {
+    params: StarcodeParams,
+}
+
+impl StarcodeParamsBuilder {
+    /// Maximum Levenshtein distance, -1 lets starcode pick it from the median sequence length
+    pub fn max_distance(mut self, max_distance: i32) -> Self {
+        self.params.max_distance = max_distance;
+        self
+    }
+
+    /// Levenshtein by default. Only the sequences held in memory are clustered by Hamming
+    /// distance, the runs on files, read pairs and the tidy output fail with
+    /// `StarcodeError::InvalidParameter`.
+    pub fn metric(mut self, metric: DistanceMetric) -> Self {
+        self.params.metric = metric;
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.params.threads = threads;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: ClusterAlgorithm) -> Self {
+        self.params.algorithm = algorithm;
+        self
+    }
+
+    /// Minimum count ratio for a sequence to absorb another (message passing only)
+    pub fn cluster_ratio(mut self, cluster_ratio: f64) -> Self {
+        self.params.cluster_ratio = cluster_ratio;
+        self
+    }
+
+    pub fn show_members(mut self, show_members: bool) -> Self {
+        self.params.show_members = show_members;
+        self
+    }
+
+    pub fn show_ids(mut self, show_ids: bool) -> Self {
+        self.params.show_ids = show_ids;
+        self
+    }
+
+    pub fn output(mut self, output: OutputMode) -> Self {
+        self.params.output = output;
+        self
+    }
+
+    /// Log the info and debug messages of starcode too (stages, query progress), errors and
+    /// warnings are always logged. The messages go to `tracing`, in the span of their stage.
+    pub fn verbose(mut self, verbose: bool) -> Self {
+        self.params.verbose = verbose;
+        self
+    }
+
+    /// Uppercase the sequences held in memory and replace U with T before they are checked
+    pub fn normalize(mut self, normalize: bool) -> Self {
+        self.params.normalize = normalize;
+        self
+    }
+
+    /// Runs stop with `StarcodeError::Cancelled` once `token` is cancelled
+    pub fn cancellation_token(mut self, token: CancellationToken) -> Self {
+        self.params.cancellation_token = Some(token);
+        self
+    }
+
+    /// Runs stop with `StarcodeError::Cancelled` when they last longer than `time_limit`,
+    /// counted from the start of each run
+    pub fn time_limit(mut self, time_limit: Duration) -> Self {
+        self.params.time_limit = Some(time_limit);
+        self
+    }
+
+    /// Passes the progress of the runs to `callback`, on the thread that started them.
+    /// `callback` must not panic.
+    pub fn progress<F: Fn(Progress) + Send + Sync + 'static>(mut self, callback: F) -> Self {
+        self.params.progress = Some(ProgressCallback::new(callback));
+        self
+    }
+
+    pub fn build(self) -> Result<StarcodeParams, StarcodeError> {
+        let params = self.params;
+        if params.max_distance < -1 || params.max_distance > STARCODE_MAX_TAU as i32 {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "max_distance must be between -1 and {}, got {}", STARCODE_MAX_TAU, params.max_distance)));
+        }
+        if params.threads < 1 || params.threads > STARCODE_MAX_THREADS as usize {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "threads must be between 1 and {}, got {}", STARCODE_MAX_THREADS, params.threads)));
+        }
+        if params.cluster_ratio.is_nan() || params.cluster_ratio < 1.0 {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "cluster_ratio must be at least 1, got {}", params.cluster_ratio)));
+        }
+        Ok(params)
+    }
+}
@@ -0,0 +1,99 @@
+use std::fmt;
+use std::sync::Arc;
+
+use libc::c_long;
+use tracing::Span;
+
+use crate::{
+    progress_t, progress_t_PROGRESS_FINISHED, progress_t_PROGRESS_JOBS, progress_t_PROGRESS_STARTED,
+    progress_t_PROGRESS_UNIQUE, stage_t, stage_t_STAGE_CLUSTERING, stage_t_STAGE_OUTPUT,
+    stage_t_STAGE_PADDING, stage_t_STAGE_QUERYING, stage_t_STAGE_READING, stage_t_STAGE_SORTING,
+};
+
+/// Stage of a starcode run (the `stage_t` enum of starcode), a run goes through them in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Reading,
+    Sorting,
+    Padding,
+    Querying,
+    Clustering,
+    Output,
+}
+
+impl Stage {
+    fn from_stage_t(stage: stage_t) -> Option<Stage> {
+        match stage {
+            stage_t_STAGE_READING => Some(Stage::Reading),
+            stage_t_STAGE_SORTING => Some(Stage::Sorting),
+            stage_t_STAGE_PADDING => Some(Stage::Padding),
+            stage_t_STAGE_QUERYING => Some(Stage::Querying),
+            stage_t_STAGE_CLUSTERING => Some(Stage::Clustering),
+            stage_t_STAGE_OUTPUT => Some(Stage::Output),
+            _ => None,
+        }
+    }
+
+    // Span of the stage, inside the span of the run when called from the thread of the run
+    pub(crate) fn span(self) -> Span {
+        match self {
+            Stage::Reading => tracing::info_span!("read"),
+            Stage::Sorting => tracing::info_span!("sort"),
+            Stage::Padding => tracing::info_span!("pad"),
+            Stage::Querying => tracing::info_span!("query"),
+            Stage::Clustering => tracing::info_span!("cluster"),
+            Stage::Output => tracing::info_span!("output"),
+        }
+    }
+}
+
+/// Progress of a run, passed to the callback set with `StarcodeParamsBuilder::progress`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Progress {
+    Started(Stage),
+    Finished(Stage),
+    // 'unique' different sequences out of the 'total' read, known once they are sorted
+    UniqueSequences { unique: usize, total: usize },
+    // 'done' query jobs out of 'total', the jobs all take about the same time
+    QueryJobs { done: usize, total: usize },
+}
+
+impl Progress {
+    pub(crate) fn from_c(event: progress_t, stage: stage_t, count: c_long, total: c_long) -> Option<Progress> {
+        let (count, total) = (count as usize, total as usize);
+        match event {
+            progress_t_PROGRESS_STARTED => Stage::from_stage_t(stage).map(Progress::Started),
+            progress_t_PROGRESS_FINISHED => Stage::from_stage_t(stage).map(Progress::Finished),
+            progress_t_PROGRESS_UNIQUE => Some(Progress::UniqueSequences { unique: count, total }),
+            progress_t_PROGRESS_JOBS => Some(Progress::QueryJobs { done: count, total }),
+            _ => None,
+        }
+    }
+}
+
+// The progress callback of the parameters, shared by their clones
+#[derive(Clone)]
+pub(crate) struct ProgressCallback(Arc<dyn Fn(Progress) + Send + Sync>);
+
+impl ProgressCallback {
+    pub(crate) fn new<F: Fn(Progress) + Send + Sync + 'static>(callback: F) -> Self {
+        ProgressCallback(Arc::new(callback))
+    }
+
+    pub(crate) fn call(&self, progress: Progress) {
+        (self.0)(progress)
+    }
+}
+
+impl fmt::Debug for ProgressCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressCallback")
+    }
+}
+
+// Clones of a callback are equal, separate callbacks are not
+impl PartialEq for ProgressCallback {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::BufRead;
+
+use crate::StarcodeError;
+
+/// A FASTA record, the header is the line without the leading '>'
+#[derive(Debug, Clone, PartialEq)]
+pub struct FastaRecord {
+    pub header: Vec<u8>,
+    pub sequence: Vec<u8>,
+}
+
+/// A FASTQ record, the header is the line without the leading '@'
+#[derive(Debug, Clone, PartialEq)]
+pub struct FastqRecord {
+    pub header: Vec<u8>,
+    pub sequence: Vec<u8>,
+    pub quality: Vec<u8>,
+}
+
+/// One record per cluster (the non-redundant output), in the format of the input
+#[derive(Debug, Clone, PartialEq)]
+pub enum Representatives {
+    Raw(Vec<Vec<u8>>),
+    Fasta(Vec<FastaRecord>),
+    Fastq(Vec<FastqRecord>),
+    PairedFastq(Vec<(FastqRecord, FastqRecord)>),
+}
+
+impl Representatives {
+    pub fn len(&self) -> usize {
+        match self {
+            Representatives::Raw(records) => records.len(),
+            Representatives::Fasta(records) => records.len(),
+            Representatives::Fastq(records) => records.len(),
+            Representatives::PairedFastq(records) => records.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Reads the non-redundant output of a single file, the format is guessed from the
+// first character as starcode does for its input
+pub(crate) fn read_representatives<R: BufRead>(mut reader: R) -> Result<Representatives, StarcodeError> {
+    let first = reader.fill_buf()?.first().copied();
+    match first {
+        Some(b'>') => Ok(Representatives::Fasta(read_fasta_records(reader)?)),
+        Some(b'@') => Ok(Representatives::Fastq(read_fastq_records(reader)?)),
+        _ => Ok(Representatives::Raw(read_lines(reader)?)),
+    }
+}
+
+fn read_lines<R: BufRead>(reader: R) -> Result<Vec<Vec<u8>>, StarcodeError> {
+    let mut lines = Vec::new();
+    for line in reader.split(b'\n') {
+        lines.push(line?);
+    }
+    Ok(lines)
+}
+
+pub(crate) fn read_fasta_records<R: BufRead>(reader: R) -> Result<Vec<FastaRecord>, StarcodeError> {
+    let lines = read_lines(reader)?;
+    let mut records = Vec::with_capacity(lines.len() / 2);
+    for chunk in lines.chunks(2) {
+        match chunk {
+            [header, sequence] if header.first() == Some(&b'>') => records.push(FastaRecord {
+                header: header[1..].to_vec(),
+                sequence: sequence.clone(),
+            }),
+            _ => return Err(malformed(chunk)),
+        }
+    }
+    Ok(records)
+}
+
+pub(crate) fn read_fastq_records<R: BufRead>(reader: R) -> Result<Vec<FastqRecord>, StarcodeError> {
+    let lines = read_lines(reader)?;
+    let mut records = Vec::with_capacity(lines.len() / 4);
+    for chunk in lines.chunks(4) {
+        match chunk {
+            [header, sequence, plus, quality] if header.first() == Some(&b'@') && plus.first() == Some(&b'+') => {
+                records.push(FastqRecord {
+                    header: header[1..].to_vec(),
+                    sequence: sequence.clone(),
+                    quality: quality.clone(),
+                })
+            }
+            _ => return Err(malformed(chunk)),
+        }
+    }
+    Ok(records)
+}
+
+fn malformed(lines: &[Vec<u8>]) -> StarcodeError {
+    StarcodeError::MalformedOutput(String::from_utf8_lossy(&lines.join(&b'\n')).into_owned())
+}
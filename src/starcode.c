@@ -26,13 +26,14 @@
 #include <ctype.h>
 #include <errno.h>
 #include <pthread.h>
+#include <stdarg.h>
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
 #include "trie.h"
 
-#define alert()                                                    \
-  fprintf(stderr, "error `%s' in %s() (%s:%d)\n", strerror(errno), \
+#define alert(params)                                                    \
+  say(params, LOG_ERROR, "error `%s' in %s() (%s:%d)\n", strerror(errno), \
       __func__, __FILE__, __LINE__)
 
 #define MAX_K_FOR_LOOKUP 14
@@ -47,6 +48,9 @@
 #define STRATEGY_EQUAL 1
 #define STRATEGY_PREFIX 99
 
+// Sequences processed between two calls to the 'cancelled' hook.
+#define CANCEL_INTERVAL 1024
+
 #define str(a) (char*)(a)
 #define min(a, b) (((a) < (b)) ? (a) : (b))
 #define max(a, b) (((a) > (b)) ? (a) : (b))
@@ -76,13 +80,6 @@ static const char capitalize[128] = {0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11,
 struct useq_t;
 struct match_t;
 
-typedef enum {
-  FASTA,
-  FASTQ,
-  RAW,
-  PE_FASTQ,
-  UNSET,
-} format_t;
 typedef struct mtplan_t mtplan_t;
 typedef struct mttrie_t mttrie_t;
 typedef struct mtjob_t mtjob_t;
@@ -92,24 +89,6 @@ typedef struct idstack_t idstack_t;
 
 typedef struct sortargs_t sortargs_t;
 
-// The field 'seqid' is either an id number for
-// the unique sequence or a pointer to a struct
-// containing information about the matches. This
-// creates some confusion in the code at times.
-// See function 'transfer_useq_ids()'.
-struct useq_t {
-  ssize_t count;       // Number of sequences
-  unsigned int nids;   // Number of associated sequence IDs
-  ssize_t sphere_c;    // Centroid: size of the sphere.
-  ssize_t sphere_d;    // Distance to current sphere centroid. / MP:
-                       // Ambiguous flag.
-  char* seq;           // Sequence
-  char* info;          // Multi-function text field
-  gstack_t** matches;  // Matches stratified by distance
-  useq_t* canonical;   // Pointer to canonical sequence
-  int* seqid;          // Unique ID / pointer (see above).
-};
-
 struct lookup_t {
   int slen;
   int kmers;
@@ -124,10 +103,11 @@ struct sortargs_t {
   int64_t b;
   int thread;
   ssize_t repeats;
+  int err;
 };
 
 struct mtplan_t {
-  char active;
+  int active;
   int ntries;
   int jobsdone;
   struct mttrie_t* tries;
@@ -135,6 +115,12 @@ struct mtplan_t {
   pthread_cond_t* monitor;
 };
 
+struct starcode_context_t {
+  starcode_params_t* params;  // Parameters of the last run
+  gstack_t* useqS;            // Sequences of the last run
+  mtplan_t* mtplan;           // Tries and lookups of the last run
+};
+
 struct mttrie_t {
   char flag;
   int currentjob;
@@ -150,8 +136,147 @@ typedef struct starcode_params_t {
     int outputt;
     int clusteralg;
     double cluster_ratio;
+    starcode_hooks_t* hooks;
+    int verbose;
+    int status;
 } starcode_params_t;
 
+// Fault injection for the test suite: the n-th next operation of the
+// calling thread that can fail with 'status' fails with it. Not part of
+// the API, so not declared in starcode.h: the Rust tests declare it.
+// Only test builds define STARCODE_FAULT_INJECTION, elsewhere no
+// operation fails on purpose and 'injected()' is 0.
+// Thread-local so that concurrent runs do not interfere.
+#ifdef STARCODE_FAULT_INJECTION
+static _Thread_local int FAULT_STATUS = STARCODE_OK;
+static _Thread_local int FAULT_COUNTDOWN = 0;
+
+void
+starcode_inject_fault(const int status, const int n) {
+  FAULT_STATUS = status;
+  FAULT_COUNTDOWN = n;
+}
+
+int
+injected(const int status)
+// SYNOPSIS:
+//   Returns 1 if the operation that can fail with 'status' must
+//   fail because of an injected fault, 0 otherwise.
+{
+  if (FAULT_STATUS != status || FAULT_COUNTDOWN < 1)
+    return 0;
+  if (--FAULT_COUNTDOWN > 0)
+    return 0;
+  FAULT_STATUS = STARCODE_OK;
+  return 1;
+}
+#else
+#define injected(status) 0
+#endif
+
+void
+set_status(starcode_params_t* params, const int status)
+// SYNOPSIS:
+//   Records the error of a run. Only the first error is kept, the
+//   others are usually a consequence of it. Query threads call this
+//   concurrently, hence the atomic swap.
+{
+  __sync_bool_compare_and_swap(&params->status, STARCODE_OK, status);
+}
+
+int
+check_cancelled(starcode_params_t* params)
+// SYNOPSIS:
+//   Asks the 'cancelled' hook whether the run must stop and records
+//   the cancellation in the status of the run.
+//
+// RETURN:
+//   1 if the run must stop, 0 otherwise.
+{
+  starcode_hooks_t* hooks = params->hooks;
+  if (hooks == NULL || hooks->cancelled == NULL)
+    return 0;
+  if (!hooks->cancelled(hooks->data))
+    return 0;
+  set_status(params, STARCODE_CANCELLED);
+  return 1;
+}
+
+int
+graph_only(starcode_params_t* params)
+// SYNOPSIS:
+//   Tells whether the caller only wants the pairs passed to the
+//   'edge' hook, so that the run stops after the query.
+//
+// RETURN:
+//   1 if the run stops after the query, 0 otherwise.
+{
+  starcode_hooks_t* hooks = params->hooks;
+  return hooks != NULL && hooks->edge != NULL && hooks->member == NULL;
+}
+
+starcode_context_t*
+owner(starcode_params_t* params)
+// SYNOPSIS:
+//   The context of the hooks, if any, which owns the allocations of
+//   the run instead of the run itself.
+//
+// RETURN:
+//   The context of the run, NULL if the run frees its allocations.
+{
+  starcode_hooks_t* hooks = params->hooks;
+  return hooks == NULL ? NULL : hooks->context;
+}
+
+void
+report(starcode_params_t* params, const progress_t event,
+    const stage_t stage, const long count, const long total)
+// SYNOPSIS:
+//   Passes a progress event to the 'progress' hook, if any.
+{
+  starcode_hooks_t* hooks = params->hooks;
+  if (hooks != NULL && hooks->progress != NULL)
+    hooks->progress(hooks->data, event, stage, count, total);
+}
+
+void
+say(starcode_params_t* params, const log_level_t level,
+    const char* format, ...)
+// SYNOPSIS:
+//   Passes a message to the 'log' hook without its trailing
+//   whitespace. Info and debug messages are dropped unless the run
+//   is verbose. Without a hook, the messages only go to stderr in
+//   verbose runs, so that a library caller never gets them there.
+{
+  starcode_hooks_t* hooks = params->hooks;
+  const int hooked = hooks != NULL && hooks->log != NULL;
+  if (!params->verbose && (level > LOG_WARNING || !hooked))
+    return;
+  va_list args;
+  va_start(args, format);
+  if (!hooked) {
+    vfprintf(stderr, format, args);
+    va_end(args);
+    return;
+  }
+  // Measure the message first, 'args' can only be used once.
+  va_list measure;
+  va_copy(measure, args);
+  int len = vsnprintf(NULL, 0, format, measure);
+  va_end(measure);
+  char* message = len < 0 ? NULL : malloc(len + 1);
+  if (message == NULL) {
+    va_end(args);
+    return;
+  }
+  vsnprintf(message, len + 1, format, args);
+  va_end(args);
+  while (len > 0 && isspace((unsigned char)message[len - 1]))
+    message[--len] = '\0';
+  hooks->log(hooks->data, level, message);
+  free(message);
+}
+
 // Create a function to initialize the parameters
 starcode_params_t* create_starcode_params(
     FILE* outputf1,
@@ -161,6 +286,7 @@ starcode_params_t* create_starcode_params(
     int clusteralg,
     double cluster_ratio
 ) {
+    if (injected(STARCODE_ALLOC_ERROR)) return NULL;
     starcode_params_t* params = malloc(sizeof(starcode_params_t));
     if (params == NULL) return NULL;
     
@@ -170,6 +296,8 @@ starcode_params_t* create_starcode_params(
     params->outputt = outputt;
     params->clusteralg = clusteralg;
     params->cluster_ratio = cluster_ratio;
+    params->hooks = NULL;
+    params->status = STARCODE_OK;
     
     return params;
 }
@@ -197,7 +325,7 @@ struct mtjob_t {
   pthread_cond_t* monitor;
   int* jobsdone;
   char* trieflag;
-  char* active;
+  int* active;
 };
 
 struct thread_wrapped_job {
@@ -221,47 +349,70 @@ struct idstack_t {
 int addmatch(useq_t*, useq_t*, int, int);
 int bisection(int, int, char*, useq_t**, int, int);
 int canonical_order(const void*, const void*);
+int check_cancelled(starcode_params_t*);
+int graph_only(starcode_params_t*);
+starcode_context_t* owner(starcode_params_t*);
+int check_seq(const char*, starcode_params_t*);
+int check_pe_seq(const char*, const char*, starcode_params_t*);
 int cluster_count(const void*, const void*);
-gstack_t* compute_clusters(gstack_t*);
-void connected_components(useq_t*, gstack_t**);
+gstack_t* compute_clusters(gstack_t*, starcode_params_t*);
+int connected_components(useq_t*, gstack_t**);
 long int count_trie_nodes(useq_t**, int, int);
 int sphere_size_order(const void*, const void*);
 int count_order(const void*, const void*);
 int count_order_spheres(const void*, const void*);
 void destroy_useq(useq_t*);
+void destroy_useqs(gstack_t*);
+void export_components(gstack_t*, starcode_hooks_t*);
+void export_mp(gstack_t*, starcode_hooks_t*);
+void export_spheres(gstack_t*, starcode_hooks_t*);
 void destroy_gstack(gstack_t *);
 void destroy_lookup(lookup_t*);
+void destroy_mtplan(mtplan_t*);
 void* do_query(void*);
 void idstack_free(idstack_t*);
 idstack_t* idstack_new(size_t);
-void idstack_push(int*, size_t, idstack_t*);
+int idstack_push(int*, size_t, idstack_t*);
+#ifdef STARCODE_FAULT_INJECTION
+int injected(int);
+void starcode_inject_fault(const int, const int);
+#endif
 int int_ascending(const void*, const void*);
-void krash(void) __attribute__((__noreturn__));
 int lut_insert(lookup_t*, useq_t*);
 int lut_search(lookup_t*, useq_t*);
-void message_passing_clustering(gstack_t*);
-void mp_resolve_ambiguous(useq_t*);
-lookup_t* new_lookup(int, int, int);
+int message_passing_clustering(gstack_t*, starcode_params_t*);
+int mp_resolve_ambiguous(useq_t*);
+lookup_t* new_lookup(int, int, int, starcode_params_t*);
 useq_t* new_useq(int, char*, char*);
 int pad_useq(gstack_t*, int*);
-mtplan_t* plan_mt(int, int, int, int, gstack_t*);
-void print_tidy(long int, const gstack_t*, int);
+mtplan_t* plan_mt(int, int, int, int, gstack_t*, starcode_params_t*);
+int print_tidy(long int, const gstack_t*, int, starcode_params_t*);
+int push_read(int, char*, char*, gstack_t**);
+void report(starcode_params_t*, progress_t, stage_t, long, long);
+void say(starcode_params_t*, log_level_t, const char*, ...);
 void sort_and_print_ids(idstack_t*, starcode_params_t*);
-void run_plan(mtplan_t*, int, int, starcode_params_t*);
+int run_plan(mtplan_t*, int, starcode_params_t*);
 gstack_t* read_rawseq(FILE*, gstack_t*, starcode_params_t*);
 gstack_t* read_fasta(FILE*, gstack_t*, starcode_params_t*);
 gstack_t* read_fastq(FILE*, gstack_t*, starcode_params_t*);
-gstack_t* read_file(FILE*, FILE*, int, starcode_params_t*);
+gstack_t* read_file(FILE*, FILE*, starcode_params_t*);
 gstack_t* read_PE_fastq(FILE*, FILE*, gstack_t*, starcode_params_t*);
+gstack_t* read_pe_seqs(char**, char**, const long*, size_t, gstack_t*,
+    starcode_params_t*);
+gstack_t* read_seqs(char**, const long*, size_t, gstack_t*, starcode_params_t*);
 int seq2id(char*, int);
 gstack_t* seq2useq(gstack_t*, int);
-size_t seqsort(useq_t**, size_t, int);
+ssize_t seqsort(useq_t**, size_t, int);
+void set_status(starcode_params_t*, int);
+int starcode_run(gstack_t*, int, int, int, int, starcode_params_t*);
+int starcode_run_and_destroy(gstack_t*, int, int, int, int,
+    starcode_params_t*);
 int size_order(const void* a, const void* b);
-void sphere_clustering(gstack_t*);
+int sphere_clustering(gstack_t*, starcode_params_t*);
 void transfer_counts_and_update_canonicals(useq_t*);
-void transfer_sorted_useq_ids(useq_t*, useq_t*);
-void transfer_useq_ids(useq_t*, useq_t*);
-void unpad_useq(gstack_t*);
+int transfer_sorted_useq_ids(useq_t*, useq_t*);
+int transfer_useq_ids(useq_t*, useq_t*);
+int unpad_useq(gstack_t*);
 void* nukesort(void*);
 
 
@@ -345,10 +496,12 @@ print_nr_fasta(useq_t* u, starcode_params_t* params) {
 
 void
 print_nr_fastq(useq_t* u, starcode_params_t* params) {
-  char header[M] = {0};
-  char quality[M] = {0};
-  sscanf(u->info, "%s\n%s", header, quality);
-  fprintf(params->outputf1, "%s\n%s\n+\n%s\n", header, u->seq, quality);
+  // The header may contain spaces, split on the newline.
+  char* quality = strchr(u->info, '\n');
+  if (quality == NULL)
+    return;
+  fprintf(params->outputf1, "%.*s\n%s\n+\n%s\n", (int)(quality - u->info),
+      u->info, u->seq, quality + 1);
 }
 
 void
@@ -381,22 +534,28 @@ print_nr_pe_fastq(useq_t* u, starcode_params_t* params) {
   fprintf(params->outputf2, "%s\n%s\n+\n%s\n", head2, seq2, qual2);
 }
 
-void
+int
 print_tidy( // Private
     const long int nseq, // Total number of sequences
     const gstack_t* uSQ,  // Stack of useq after clustering
-    const int is_pe_fastq
+    const int is_pe_fastq,
+    starcode_params_t* params
 )
 // SYNOPSIS:
 //   Print each sequence in initial order next to its
 //   centroid (tab-separated). For paired-end FASTQ file,
 //   print slash-separated pairs next to their slash-
 //   separated pair of centroids.
+//
+// RETURN:
+//   STARCODE_OK upon success, an error code otherwise.
 {
+  if (injected(STARCODE_ALLOC_ERROR))
+    return STARCODE_ALLOC_ERROR;
   useq_t** outputseq = calloc(sizeof(useq_t*), nseq);
   if (outputseq == NULL) {
-    alert();
-    krash();
+    alert(params);
+    return STARCODE_ALLOC_ERROR;
   }
   for (size_t i = 0; i < uSQ->nitems; i++) {
     useq_t* u = (useq_t*)uSQ->items[i];
@@ -413,31 +572,35 @@ print_tidy( // Private
 
   for (long int i = 0; i < nseq; i++) {
     useq_t* u = outputseq[i];
-    if (u == NULL) {
-      alert();
-      krash();
+    if (u == NULL || injected(STARCODE_INTERNAL_ERROR)) {
+      say(params, LOG_ERROR, "no sequence with id %ld\n", i + 1);
+      free(outputseq);
+      return STARCODE_INTERNAL_ERROR;
     }
     if (is_pe_fastq) {
       char * seq2 = strstr(u->seq, sep);
       char * can2 = strstr(u->canonical->seq, sep);
       if (seq2 == NULL || can2 == NULL) {
-        fprintf(stderr, "%s\n", u->seq);
-        fprintf(stderr, "%s\n", u->canonical->seq);
-        alert();
-        krash();
+        say(params, LOG_ERROR, "%s\n%s\npaired-end separator not found\n",
+            u->seq, u->canonical->seq);
+        free(outputseq);
+        return STARCODE_INTERNAL_ERROR;
       }
       // Insert null byte to terminate first read.
       seq2[0] = can2[0] = '\0';
-      fprintf(stdout, "%s/%s\t%s/%s\n", u->seq, seq2 + STARCODE_MAX_TAU + 1,
+      fprintf(params->outputf1, "%s/%s\t%s/%s\n", u->seq, seq2 + STARCODE_MAX_TAU + 1,
           u->canonical->seq, can2 + STARCODE_MAX_TAU + 1);
       // Put it back as it was because the sequence may
       // be present multiple times.
       seq2[0] = can2[0] = '-';
     }
     else {
-      fprintf(stdout, "%s\t%s\n", u->seq, u->canonical->seq);
+      fprintf(params->outputf1, "%s\t%s\n", u->seq, u->canonical->seq);
     }
   }
+
+  free(outputseq);
+  return STARCODE_OK;
 }
 
 int starcode_helper(
@@ -452,24 +615,22 @@ int starcode_helper(
     const int showids,       // Print sequence ID numbers
     const int outputt        // Output type (format)
 ) {
-    // Initialize tower at start
-    //init_new_tower();
-    
     FILE *inputf1 = NULL;
     FILE *outputf1 = NULL;
-    int result = 1; // Default to error
+    int result = STARCODE_IO_ERROR; // Default to error
+    starcode_params_t fallback = {.verbose = verbose};
     
     // Open input file
     inputf1 = fopen(input, "r");
     if (inputf1 == NULL) {
-        fprintf(stderr, "Could not open input file %s\n", input);
+        say(&fallback, LOG_ERROR, "Could not open input file %s\n", input);
         goto cleanup;
     }
     
     // Open output file
     outputf1 = fopen(output, "w");
     if (outputf1 == NULL) {
-        fprintf(stderr, "Could not open output file %s\n", output);
+        say(&fallback, LOG_ERROR, "Could not open output file %s\n", output);
         goto cleanup;
     }
     
@@ -494,9 +655,6 @@ cleanup:
     if (inputf1 != NULL) fclose(inputf1);
     if (outputf1 != NULL) fclose(outputf1);
     
-    // Cleanup tower after use
-    //cleanup_new_tower();
-    
     return result;
 }
 
@@ -518,36 +676,255 @@ starcode(                    // Public
 // SYNOPSIS:
 //   Performs all-pairs sequence clustering.
 {
-  // Initialize thread-local variables for the main thread
-  init_thread_local_tower_top();
+  return starcode_files(inputf1, inputf2, outputf1, outputf2, NULL, tau,
+      verbose, thrmax, clusteralg, parent_to_child, showclusters, showids,
+      outputt);
+}
 
+int
+starcode_files(              // Public
+    FILE* inputf1,           // First input file
+    FILE* inputf2,           // Second input file (fastq)
+    FILE* outputf1,          // First output file
+    FILE* outputf2,          // Second output file (fastq)
+    starcode_hooks_t* hooks, // Library callbacks (may be NULL)
+    int tau,                 // Max Levenshtein distance
+    const int verbose,       // Verbose output (to stderr)
+    int thrmax,              // Max number of threads
+    const int clusteralg,    // Clustring algorithm
+    double parent_to_child,  // Merging threshold
+    const int showclusters,  // Print cluster members
+    const int showids,       // Print sequence ID numbers
+    const int outputt        // Output type (format)
+)
+// SYNOPSIS:
+//   Same as 'starcode()' with library callbacks. If 'hooks' has a
+//   'member' callback, the clusters are exported through it and
+//   'outputf1' is not used for the default output.
+{
   // Replace global variables with struct
   starcode_params_t* params = create_starcode_params(
       outputf1, outputf2, FASTA,outputt, clusteralg, parent_to_child);
   
   if (params == NULL) {
-    fprintf(stderr, "failed to allocate parameters\n");
-    return 1;
-  }
-if (verbose) {
-    fprintf(stderr, "running %s (last revised %s) with %d thread%s\n",
-        VERSION, DATE, thrmax, thrmax > 1 ? "s" : "");
-    fprintf(stderr, "reading input files\n");
-  }
-  gstack_t* uSQ = read_file(inputf1, inputf2, verbose, params);
-  if (uSQ == NULL || uSQ->nitems < 1) {
-    fprintf(stderr, "input file empty\n");
-    return 1;
+    starcode_params_t fallback = {.hooks = hooks};
+    say(&fallback, LOG_ERROR, "failed to allocate parameters\n");
+    return STARCODE_ALLOC_ERROR;
+  }
+  params->hooks = hooks;
+  params->verbose = verbose;
+  say(params, LOG_INFO, "running %s (last revised %s) with %d thread%s\n",
+      VERSION, DATE, thrmax, thrmax > 1 ? "s" : "");
+  report(params, PROGRESS_STARTED, STAGE_READING, 0, 0);
+  say(params, LOG_INFO, "reading input files\n");
+  gstack_t* uSQ = read_file(inputf1, inputf2, params);
+
+  return starcode_run_and_destroy(uSQ, tau, thrmax,
+      showclusters, showids, params);
+}
+
+int
+starcode_seqs(               // Public
+    char** seqs,             // Input sequences
+    const long* counts,      // Input sequence counts
+    const size_t nseq,       // Number of input sequences
+    FILE* outputf1,          // Output file
+    starcode_hooks_t* hooks, // Library callbacks (may be NULL)
+    int tau,                 // Max Levenshtein distance
+    const int verbose,       // Verbose output (to stderr)
+    int thrmax,              // Max number of threads
+    const int clusteralg,    // Clustring algorithm
+    double parent_to_child,  // Merging threshold
+    const int showclusters,  // Print cluster members
+    const int showids,       // Print sequence ID numbers
+    const int outputt        // Output type (format)
+)
+// SYNOPSIS:
+//   Same as 'starcode()' but the input is an array of sequences
+//   with their counts instead of a file, so the caller does not
+//   need to format the input as text. Sequence IDs are the 1-based
+//   positions in the array. If 'hooks' has a 'member' callback, the
+//   clusters are exported through it and 'outputf1' is not used for
+//   the default output.
+{
+  starcode_params_t* params = create_starcode_params(
+      outputf1, NULL, RAW, outputt, clusteralg, parent_to_child);
+
+  if (params == NULL) {
+    starcode_params_t fallback = {.hooks = hooks};
+    say(&fallback, LOG_ERROR, "failed to allocate parameters\n");
+    return STARCODE_ALLOC_ERROR;
+  }
+  params->hooks = hooks;
+  params->verbose = verbose;
+  say(params, LOG_INFO, "running %s (last revised %s) with %d thread%s\n",
+      VERSION, DATE, thrmax, thrmax > 1 ? "s" : "");
+  report(params, PROGRESS_STARTED, STAGE_READING, 0, 0);
+  gstack_t* uSQ = read_seqs(seqs, counts, nseq, new_gstack(), params);
+
+  return starcode_run_and_destroy(uSQ, tau, thrmax,
+      showclusters, showids, params);
+}
+
+int
+starcode_pe_seqs(            // Public
+    char** seqs1,            // First mates
+    char** seqs2,            // Second mates
+    const long* counts,      // Input pair counts
+    const size_t nseq,       // Number of input pairs
+    FILE* outputf1,          // Output file
+    starcode_hooks_t* hooks, // Library callbacks (may be NULL)
+    int tau,                 // Max Levenshtein distance
+    const int verbose,       // Verbose output (to stderr)
+    int thrmax,              // Max number of threads
+    const int clusteralg,    // Clustring algorithm
+    double parent_to_child,  // Merging threshold
+    const int showclusters,  // Print cluster members
+    const int showids,       // Print sequence ID numbers
+    const int outputt        // Output type (format)
+)
+// SYNOPSIS:
+//   Same as 'starcode_seqs()' for paired-end reads. The mates are
+//   joined as in paired-end fastq files. There are no headers and
+//   no qualities, so the non-redundant output is not available.
+{
+  if (outputt == NRED_OUTPUT) {
+    starcode_params_t fallback = {.hooks = hooks};
+    say(&fallback, LOG_ERROR, "non-redundant output requires fastq input\n");
+    return STARCODE_INVALID_INPUT;
   }
 
+  starcode_params_t* params = create_starcode_params(
+      outputf1, NULL, PE_FASTQ, outputt, clusteralg, parent_to_child);
+
+  if (params == NULL) {
+    starcode_params_t fallback = {.hooks = hooks};
+    say(&fallback, LOG_ERROR, "failed to allocate parameters\n");
+    return STARCODE_ALLOC_ERROR;
+  }
+  params->hooks = hooks;
+  params->verbose = verbose;
+  say(params, LOG_INFO, "running %s (last revised %s) with %d thread%s\n",
+      VERSION, DATE, thrmax, thrmax > 1 ? "s" : "");
+  report(params, PROGRESS_STARTED, STAGE_READING, 0, 0);
+  gstack_t* uSQ =
+      read_pe_seqs(seqs1, seqs2, counts, nseq, new_gstack(), params);
+
+  return starcode_run_and_destroy(uSQ, tau, thrmax,
+      showclusters, showids, params);
+}
+
+int
+starcode_run_and_destroy(    // Private
+    gstack_t* uSQ,           // Stack of useq read from the input
+    int tau,                 // Max Levenshtein distance
+    int thrmax,              // Max number of threads
+    const int showclusters,  // Print cluster members
+    const int showids,       // Print sequence ID numbers
+    starcode_params_t* params
+)
+// SYNOPSIS:
+//   Checks that the input was read successfully, runs the clustering
+//   and destroys the parameters and the input, or leaves them to the
+//   context of the hooks.
+//
+// RETURN:
+//   STARCODE_OK upon success, an error code otherwise.
+{
+  // The context gives up the last run and takes this one.
+  starcode_context_t* context = owner(params);
+  if (context != NULL) {
+    starcode_context_clear(context);
+    context->params = params;
+    context->useqS = uSQ;
+  }
+
+  // Readers return NULL or an empty stack for empty input.
+  int status = params->status;
+  if (status == STARCODE_OK && (uSQ == NULL || uSQ->nitems < 1)) {
+    say(params, LOG_ERROR, "input empty\n");
+    status = STARCODE_EMPTY_INPUT;
+  }
+
+  // Only exported clusters and graphs can do without an output file.
+  const int exporting = params->hooks != NULL && params->hooks->member != NULL;
+  if (status == STARCODE_OK && params->outputf1 == NULL &&
+      !(exporting && params->outputt == DEFAULT_OUTPUT) &&
+      !graph_only(params)) {
+    say(params, LOG_ERROR, "no output file\n");
+    status = STARCODE_IO_ERROR;
+  }
+  if (status == STARCODE_OK && params->outputt == NRED_OUTPUT &&
+      params->format == PE_FASTQ && params->outputf2 == NULL) {
+    say(params, LOG_ERROR, "no output file for the second reads\n");
+    status = STARCODE_IO_ERROR;
+  }
+
+  if (status == STARCODE_OK) {
+    report(params, PROGRESS_FINISHED, STAGE_READING, 0, 0);
+    if (params->hooks != NULL && params->hooks->format != NULL)
+      params->hooks->format(params->hooks->data, params->format);
+    // Consumes 'uSQ'.
+    status = starcode_run(uSQ, tau, thrmax,
+        showclusters, showids, params);
+  } else if (context == NULL) {
+    destroy_useqs(uSQ);
+  }
+
+  if (status == STARCODE_INTERNAL_ERROR)
+    say(params, LOG_ERROR,
+        "starcode has crashed, please contact guillaume.filion@gmail.com "
+        "for support with this issue.\n");
+
+  if (context == NULL)
+    destroy_starcode_params(params);
+  return status;
+}
+
+int
+starcode_run(                // Private
+    gstack_t* uSQ,           // Stack of useq read from the input
+    int tau,                 // Max Levenshtein distance
+    int thrmax,              // Max number of threads
+    const int showclusters,  // Print cluster members
+    const int showids,       // Print sequence ID numbers
+    starcode_params_t* params
+)
+// SYNOPSIS:
+//   Back end of 'starcode()' and 'starcode_seqs()'. Sorts, pads and
+//   queries the sequences, then clusters and prints the output. The
+//   stack 'uSQ' and its items are destroyed on return, unless the
+//   context of the hooks owns them (and the tries and lookups).
+//
+// RETURN:
+//   STARCODE_OK upon success, an error code otherwise. In case of
+//   error, the output may be incomplete.
+{
+  int status = STARCODE_OK;
+  gstack_t* clusters = NULL;
+  gstack_t* nrSQ = uSQ;
+  idstack_t* idstack = NULL;
+
   const long int nseq = uSQ->nitems;
 
   // Sort/reduce.
-  if (verbose)
-    fprintf(stderr, "sorting\n");
-  uSQ->nitems = seqsort((useq_t**)uSQ->items, uSQ->nitems, thrmax);
+  report(params, PROGRESS_STARTED, STAGE_SORTING, 0, 0);
+  say(params, LOG_INFO, "sorting\n");
+  ssize_t nuniq = seqsort((useq_t**)uSQ->items, uSQ->nitems, thrmax);
+  if (nuniq < 0) {
+    status = STARCODE_ALLOC_ERROR;
+    goto cleanup;
+  }
+  uSQ->nitems = nuniq;
+  report(params, PROGRESS_UNIQUE, STAGE_SORTING, nuniq, nseq);
+  report(params, PROGRESS_FINISHED, STAGE_SORTING, 0, 0);
+  if (check_cancelled(params)) {
+    status = STARCODE_CANCELLED;
+    goto cleanup;
+  }
 
   // Get number of tries.
+  if (thrmax > STARCODE_MAX_THREADS) thrmax = STARCODE_MAX_THREADS;
   size_t ntries = 3 * thrmax + (thrmax % 2 == 0);
   if (uSQ->nitems < ntries) {
     ntries = 1;
@@ -556,37 +933,53 @@ if (verbose) {
 
   // Pad sequences (and return the median size).
   // Compute 'tau' from it in "auto" mode.
+  report(params, PROGRESS_STARTED, STAGE_PADDING, 0, 0);
   int med = -1;
   int height = pad_useq(uSQ, &med);
+  if (height < 0) {
+    status = STARCODE_ALLOC_ERROR;
+    goto cleanup;
+  }
   if (tau < 0) {
     tau = med > 160 ? 8 : 2 + med / 30;
-    if (verbose) {
-      fprintf(stderr, "setting dist to %d\n", tau);
-    }
+    say(params, LOG_INFO, "setting dist to %d\n", tau);
   }
+  report(params, PROGRESS_FINISHED, STAGE_PADDING, 0, 0);
 
   // Make multithreading plan.
-  mtplan_t* mtplan = plan_mt(tau, height, med, ntries, uSQ);
+  mtplan_t* mtplan = plan_mt(tau, height, med, ntries, uSQ, params);
+  if (mtplan == NULL) {
+    status = STARCODE_ALLOC_ERROR;
+    goto cleanup;
+  }
 
   // Run the query.
-  run_plan(mtplan, verbose, thrmax, params);
-  if (verbose)
-    fprintf(stderr, "progress: 100.00%%\n");
+  report(params, PROGRESS_STARTED, STAGE_QUERYING, 0, 0);
+  status = run_plan(mtplan, thrmax, params);
+  if (owner(params) != NULL)
+    owner(params)->mtplan = mtplan;
+  else
+    destroy_mtplan(mtplan);
+  if (status != STARCODE_OK)
+    goto cleanup;
+  say(params, LOG_DEBUG, "progress: 100.00%%\n");
+  report(params, PROGRESS_FINISHED, STAGE_QUERYING, 0, 0);
 
-  // Free mtplan.
-  free(mtplan->mutex);
-  free(mtplan->monitor);
-  for (int i = 0 ; i < mtplan->ntries ; i++) {
-    free(mtplan->tries[i].jobs->node_pos);
-    free(mtplan->tries[i].jobs->lut);
-    free(mtplan->tries[i].jobs->trie);
-    free(mtplan->tries[i].jobs);
+  // Remove padding characters.
+  if (unpad_useq(uSQ)) {
+    status = STARCODE_ALLOC_ERROR;
+    goto cleanup;
+  }
+  if (check_cancelled(params)) {
+    status = STARCODE_CANCELLED;
+    goto cleanup;
   }
-  free(mtplan->tries);
-  free(mtplan);
 
-  // Remove padding characters.
-  unpad_useq(uSQ);
+  // The edges were passed to the caller during the query.
+  if (graph_only(params)) {
+    report(params, PROGRESS_STARTED, STAGE_OUTPUT, 0, 0);
+    goto cleanup;
+  }
 
   //
   //  MESSAGE PASSING ALGORITHM
@@ -599,32 +992,49 @@ if (verbose) {
       .pe_fastq = PE_FASTQ == params->format,
   };
 
+  // Pass the clusters to the caller instead of printing them.
+  const int exporting = params->hooks != NULL && params->hooks->member != NULL;
+
+  if (showids && params->outputt == DEFAULT_OUTPUT && !exporting) {
+    // Sequence id stack.
+    idstack = idstack_new(64);
+    if (idstack == NULL) {
+      status = STARCODE_ALLOC_ERROR;
+      goto cleanup;
+    }
+  }
+
+  report(params, PROGRESS_STARTED, STAGE_CLUSTERING, 0, 0);
   if (params->clusteralg == MP_CLUSTER) {
-    if (verbose)
-      fprintf(stderr, "message passing clustering\n");
+    say(params, LOG_INFO, "message passing clustering\n");
 
     // Cluster the pairs.
-    message_passing_clustering(uSQ);
+    status = message_passing_clustering(uSQ, params);
+    if (status != STARCODE_OK)
+      goto cleanup;
     // Sort in canonical order.
     qsort(uSQ->items, uSQ->nitems, sizeof(useq_t*), canonical_order);
+    report(params, PROGRESS_FINISHED, STAGE_CLUSTERING, 0, 0);
+    report(params, PROGRESS_STARTED, STAGE_OUTPUT, 0, 0);
 
-    if (params->outputt == DEFAULT_OUTPUT) {
+    if (params->outputt == DEFAULT_OUTPUT && exporting) {
+      export_mp(uSQ, params->hooks);
+    } else if (params->outputt == DEFAULT_OUTPUT) {
       useq_t* first = (useq_t*)uSQ->items[0];
       useq_t* canonical = first->canonical;
 
       // If the first canonical is NULL, then they all are.
       if (first->canonical == NULL)
-        return 0;
+        goto cleanup;
       head_default(first, propt, params);
 
       // Use newline separator.
       memcpy(propt.first, "\n", 1);
 
       // Store sequence ids for the current cluster in a stack.
-      idstack_t* idstack = NULL;
-      if (showids) {
-        idstack = idstack_new(64);
-        idstack_push(first->seqid, first->nids, idstack);
+      if (showids && idstack_push(first->seqid, first->nids, idstack)) {
+        status = STARCODE_ALLOC_ERROR;
+        goto cleanup;
       }
 
       // Run through the clustered items.
@@ -648,20 +1058,22 @@ if (verbose) {
         }
 
         // Update seqid list.
-        if (showids)
-          idstack_push(u->seqid, u->nids, idstack);
+        if (showids && idstack_push(u->seqid, u->nids, idstack)) {
+          status = STARCODE_ALLOC_ERROR;
+          goto cleanup;
+        }
       }
 
       // Print last cluster seqIDs.
-      if (showids) {
+      if (showids)
         sort_and_print_ids(idstack, params);
-        idstack_free(idstack);
-      }
       fprintf(params->outputf1, "\n");
     }
 
     if (params->outputt == TIDY_OUTPUT) {
-      print_tidy(nseq, uSQ, params->format == PE_FASTQ);
+      status = print_tidy(nseq, uSQ, params->format == PE_FASTQ, params);
+      if (status != STARCODE_OK)
+        goto cleanup;
     }
 
     //
@@ -669,19 +1081,20 @@ if (verbose) {
     //
 
   } else if (params->clusteralg == SPHERES_CLUSTER) {
-    if (verbose)
-      fprintf(stderr, "spheres clustering\n");
+    say(params, LOG_INFO, "spheres clustering\n");
     // Cluster the pairs.
-    sphere_clustering(uSQ);
+    status = sphere_clustering(uSQ, params);
+    if (status != STARCODE_OK)
+      goto cleanup;
     // Sort in count order.
     qsort(uSQ->items, uSQ->nitems, sizeof(useq_t*), sphere_size_order);
+    report(params, PROGRESS_FINISHED, STAGE_CLUSTERING, 0, 0);
+    report(params, PROGRESS_STARTED, STAGE_OUTPUT, 0, 0);
 
     // Default output.
-    if (params->outputt == DEFAULT_OUTPUT) {
-      // Sequence id stack.
-      idstack_t* idstack = NULL;
-      if (showids)
-        idstack = idstack_new(64);
+    if (params->outputt == DEFAULT_OUTPUT && exporting) {
+      export_spheres(uSQ, params->hooks);
+    } else if (params->outputt == DEFAULT_OUTPUT) {
       for (size_t i = 0; i < uSQ->nitems; i++) {
         useq_t* u = (useq_t*)uSQ->items[i];
         if (u->canonical != u)
@@ -696,7 +1109,10 @@ if (verbose) {
         // Reset stack and add canonical ids.
         if (showids) {
           idstack->pos = 0;
-          idstack_push(u->seqid, u->nids, idstack);
+          if (idstack_push(u->seqid, u->nids, idstack)) {
+            status = STARCODE_ALLOC_ERROR;
+            goto cleanup;
+          }
         }
 
         // Get sequences and ids from matches.
@@ -709,8 +1125,11 @@ if (verbose) {
                 continue;
               if (showclusters)
                 fprintf(params->outputf1, ",%s", match->seq);
-              if (showids)
-                idstack_push(match->seqid, match->nids, idstack);
+              if (showids &&
+                  idstack_push(match->seqid, match->nids, idstack)) {
+                status = STARCODE_ALLOC_ERROR;
+                goto cleanup;
+              }
             }
           }
         }
@@ -719,12 +1138,12 @@ if (verbose) {
           sort_and_print_ids(idstack, params);
         fprintf(params->outputf1, "\n");
       }
-      if (showids)
-        idstack_free(idstack);
     }
 
     if (params->outputt == TIDY_OUTPUT) {
-      print_tidy(nseq, uSQ, params->format == PE_FASTQ);
+      status = print_tidy(nseq, uSQ, params->format == PE_FASTQ, params);
+      if (status != STARCODE_OK)
+        goto cleanup;
     }
 
     //
@@ -732,20 +1151,26 @@ if (verbose) {
     //
 
   } else if (params->clusteralg == COMPONENTS_CLUSTER) {
-    if (verbose)
-      fprintf(stderr, "connected components clustering\n");
+    say(params, LOG_INFO, "connected components clustering\n");
     // Cluster connected components.
     // Returns a stack containing stacks of clusters, where
     // clusters->item[i]->item[0] is the centroid of the i-th cluster. The
     // output is sorted by cluster count, which is stored in
     // centroid->count.
-    gstack_t* clusters = compute_clusters(uSQ);
+    clusters = compute_clusters(uSQ, params);
+    if (clusters == NULL) {
+      // The status is already set if the run was cancelled.
+      status = params->status == STARCODE_OK ?
+          STARCODE_ALLOC_ERROR : params->status;
+      goto cleanup;
+    }
+    report(params, PROGRESS_FINISHED, STAGE_CLUSTERING, 0, 0);
+    report(params, PROGRESS_STARTED, STAGE_OUTPUT, 0, 0);
 
     // Default output.
-    if (params->outputt == DEFAULT_OUTPUT) {
-      idstack_t* idstack = NULL;
-      if (showids)
-        idstack = idstack_new(64);
+    if (params->outputt == DEFAULT_OUTPUT && exporting) {
+      export_components(clusters, params->hooks);
+    } else if (params->outputt == DEFAULT_OUTPUT) {
       for (size_t i = 0; i < clusters->nitems; i++) {
         gstack_t* cluster = (gstack_t*)clusters->items[i];
         // Get canonical.
@@ -753,30 +1178,43 @@ if (verbose) {
         // Print canonical and cluster count.
         fprintf(params->outputf1, "%s\t%ld", canonical->seq, canonical->count);
         if (showclusters || showids) {
-          fprintf(params->outputf1, "\t%s", canonical->seq);
+          if (showclusters)
+            fprintf(params->outputf1, "\t%s", canonical->seq);
           if (showids) {
             idstack->pos = 0;
-            idstack_push(canonical->seqid, canonical->nids, idstack);
+            if (idstack_push(canonical->seqid, canonical->nids, idstack)) {
+              status = STARCODE_ALLOC_ERROR;
+              goto cleanup;
+            }
           }
           for (size_t k = 1; k < cluster->nitems; k++) {
             useq_t* u = (useq_t*)cluster->items[k];
             if (showclusters)
               fprintf(params->outputf1, ",%s", u->seq);
-            if (showids)
-              idstack_push(u->seqid, u->nids, idstack);
+            if (showids && idstack_push(u->seqid, u->nids, idstack)) {
+              status = STARCODE_ALLOC_ERROR;
+              goto cleanup;
+            }
           }
           if (showids)
             sort_and_print_ids(idstack, params);
         }
         fprintf(params->outputf1, "\n");
       }
-      if (showids)
-        idstack_free(idstack);
     } else if (params->outputt == NRED_OUTPUT) {
-      uSQ->nitems = 0;
-      // Fill uSQ with cluster centroids.
-      for (size_t i = 0; i < clusters->nitems; i++)
-        push(((gstack_t*)clusters->items[i])->items[0], &uSQ);
+      // Fill a separate stack with cluster centroids,
+      // 'uSQ' still owns all the sequences.
+      nrSQ = new_gstack();
+      if (nrSQ == NULL) {
+        status = STARCODE_ALLOC_ERROR;
+        goto cleanup;
+      }
+      for (size_t i = 0; i < clusters->nitems; i++) {
+        if (push(((gstack_t*)clusters->items[i])->items[0], &nrSQ)) {
+          status = STARCODE_ALLOC_ERROR;
+          goto cleanup;
+        }
+      }
     }
   }
 
@@ -785,8 +1223,7 @@ if (verbose) {
   //
 
   if (params->outputt == NRED_OUTPUT) {
-    if (verbose)
-      fprintf(stderr, "non-redundant output\n");
+    say(params, LOG_INFO, "non-redundant output\n");
     // If print non redundant sequences, just print the
     // canonicals with their info.
 
@@ -800,8 +1237,8 @@ if (verbose) {
     else
       print_nr = print_nr_raw;
 
-    for (size_t i = 0; i < uSQ->nitems; i++) {
-      useq_t* u = (useq_t*)uSQ->items[i];
+    for (size_t i = 0; i < nrSQ->nitems; i++) {
+      useq_t* u = (useq_t*)nrSQ->items[i];
       if (u->canonical == NULL)
         break;
       if (u->canonical != u)
@@ -809,18 +1246,86 @@ if (verbose) {
       print_nr(u, params);
     }
   }
+
+cleanup:
+  // Every early exit without an error is in the output stage.
+  if (status == STARCODE_OK)
+    report(params, PROGRESS_FINISHED, STAGE_OUTPUT, 0, 0);
+
   // Clean up.
-  for (int i = 0; i < uSQ->nitems; i++) {
-    destroy_useq(uSQ->items[i]);
+  if (idstack != NULL)
+    idstack_free(idstack);
+  if (nrSQ != uSQ && nrSQ != NULL)
+    destroy_gstack(nrSQ);
+  if (clusters != NULL) {
+    for (size_t i = 0; i < clusters->nitems; i++)
+      destroy_gstack(clusters->items[i]);
+    destroy_gstack(clusters);
+  }
+  // A context keeps the sequences until its next run.
+  if (owner(params) == NULL)
+    destroy_useqs(uSQ);
+
+  return status;
+}
+
+void
+export_mp(gstack_t* uSQ, starcode_hooks_t* hooks)
+// SYNOPSIS:
+//   Passes the message passing clusters to the 'member' hook in the
+//   same order as the default output. 'uSQ' must be sorted in
+//   canonical order.
+{
+  for (size_t i = 0; i < uSQ->nitems; i++) {
+    useq_t* u = (useq_t*)uSQ->items[i];
+    // Unassigned sequences are sorted last.
+    if (u->canonical == NULL)
+      break;
+    hooks->member(hooks->data, u->canonical, u);
   }
-  destroy_gstack(uSQ);
-  destroy_starcode_params(params);
+}
 
-  return 0;
+void
+export_spheres(gstack_t* uSQ, starcode_hooks_t* hooks)
+// SYNOPSIS:
+//   Passes the spheres to the 'member' hook in the same order as the
+//   default output. 'uSQ' must be sorted in sphere size order.
+{
+  for (size_t i = 0; i < uSQ->nitems; i++) {
+    useq_t* u = (useq_t*)uSQ->items[i];
+    if (u->canonical != u)
+      break;
+    hooks->member(hooks->data, u, u);
+    if (u->matches == NULL)
+      continue;
+    gstack_t* hits;
+    for (int j = 0; (hits = u->matches[j]) != TOWER_TOP; j++) {
+      for (size_t k = 0; k < hits->nitems; k++) {
+        useq_t* match = (useq_t*)hits->items[k];
+        if (match->canonical == u)
+          hooks->member(hooks->data, u, match);
+      }
+    }
+  }
 }
 
 void
-run_plan(mtplan_t* mtplan, const int verbose, const int thrmax, starcode_params_t* params) {
+export_components(gstack_t* clusters, starcode_hooks_t* hooks)
+// SYNOPSIS:
+//   Passes the connected components to the 'member' hook in the same
+//   order as the default output. The centroid of each component is
+//   the first item of the component.
+{
+  for (size_t i = 0; i < clusters->nitems; i++) {
+    gstack_t* cluster = (gstack_t*)clusters->items[i];
+    useq_t* canonical = (useq_t*)cluster->items[0];
+    for (size_t k = 0; k < cluster->nitems; k++)
+      hooks->member(hooks->data, canonical, (useq_t*)cluster->items[k]);
+  }
+}
+
+int
+run_plan(mtplan_t* mtplan, const int thrmax, starcode_params_t* params) {
   // Count total number of jobs.
   int njobs = mtplan->ntries * (mtplan->ntries + 1) / 2;
 
@@ -828,12 +1333,23 @@ run_plan(mtplan_t* mtplan, const int verbose, const int thrmax, starcode_params_
   int triedone = 0;
   int idx = -1;
 
+  // Jobs done as last passed to the 'progress' hook. The hook
+  // is called from here so that it runs on the calling thread.
+  int reported = 0;
+  report(params, PROGRESS_JOBS, STAGE_QUERYING, 0, njobs);
+
   while (triedone < mtplan->ntries) {
     // Cycle through the tries in turn.
     idx = (idx + 1) % mtplan->ntries;
     mttrie_t* mttrie = mtplan->tries + idx;
     pthread_mutex_lock(mtplan->mutex);
 
+    // Stop scheduling jobs after the first error.
+    if (params->status != STARCODE_OK) {
+      pthread_mutex_unlock(mtplan->mutex);
+      break;
+    }
+
     // Check whether trie is idle and there are available threads.
     if (mttrie->flag == TRIE_FREE && mtplan->active < thrmax) {
       // No more jobs on this trie.
@@ -842,25 +1358,41 @@ run_plan(mtplan_t* mtplan, const int verbose, const int thrmax, starcode_params_
         triedone++;
       }
 
-      // Some more jobs to do.
+      // Some more jobs to do, unless the run was cancelled.
+      else if (check_cancelled(params)) {
+        pthread_mutex_unlock(mtplan->mutex);
+        break;
+      }
       else {
         mttrie->flag = TRIE_BUSY;
         mtplan->active++;
         mtjob_t* job = mttrie->jobs + mttrie->currentjob++;
         thread_wrapped_job* job_global = malloc(sizeof(thread_wrapped_job));
-        job_global->job = *job;
-        job_global->params = params;
         pthread_t thread;
-        // Start job and detach thread.
-        if (pthread_create(&thread, NULL, do_query, job_global)) {
-          alert();
-          krash();
+        if (job_global == NULL) {
+          set_status(params, STARCODE_ALLOC_ERROR);
+        } else {
+          job_global->job = *job;
+          job_global->params = params;
+          // Start job and detach thread.
+          if (injected(STARCODE_THREAD_ERROR) ||
+              pthread_create(&thread, NULL, do_query, job_global)) {
+            set_status(params, STARCODE_THREAD_ERROR);
+            free(job_global);
+            job_global = NULL;
+          } else {
+            pthread_detach(thread);
+          }
         }
-        pthread_detach(thread);
-        if (verbose) {
-          fprintf(stderr, "progress: %.2f%% \r",
-              100 * (float)(mtplan->jobsdone) / njobs);
+        if (job_global == NULL) {
+          // The job did not start, the trie is idle.
+          mtplan->active--;
+          mttrie->flag = TRIE_FREE;
+          pthread_mutex_unlock(mtplan->mutex);
+          break;
         }
+        say(params, LOG_DEBUG, "progress: %.2f%% \r",
+            100 * (float)(mtplan->jobsdone) / njobs);
       }
     }
 
@@ -869,17 +1401,28 @@ run_plan(mtplan_t* mtplan, const int verbose, const int thrmax, starcode_params_
       pthread_cond_wait(mtplan->monitor, mtplan->mutex);
     }
 
+    int jobsdone = mtplan->jobsdone;
     pthread_mutex_unlock(mtplan->mutex);
+
+    if (jobsdone != reported) {
+      reported = jobsdone;
+      report(params, PROGRESS_JOBS, STAGE_QUERYING, jobsdone, njobs);
+    }
   }
 
-  return;
+  // Wait for the running threads (only after an error or a
+  // cancellation).
+  pthread_mutex_lock(mtplan->mutex);
+  while (mtplan->active > 0) {
+    pthread_cond_wait(mtplan->monitor, mtplan->mutex);
+  }
+  pthread_mutex_unlock(mtplan->mutex);
+
+  return params->status;
 }
 
 void*
 do_query(void* args) {
-  // Initialize thread-local variables
-  init_thread_local_tower_top();
-  
   // Unpack arguments.
   thread_wrapped_job* job_global = (thread_wrapped_job*)args;
   starcode_params_t* params = job_global->params;
@@ -893,8 +1436,8 @@ do_query(void* args) {
   // Create local hit stack.
   gstack_t** hits = new_tower(tau + 1);
   if (hits == NULL) {
-    alert();
-    krash();
+    set_status(params, STARCODE_ALLOC_ERROR);
+    goto done;
   }
 
   // Define a constant to help the compiler recognize
@@ -905,6 +1448,14 @@ do_query(void* args) {
   useq_t* last_query = NULL;
 
   for (int i = job->start; i <= job->end; i++) {
+    // Another thread failed, the result will be discarded.
+    if (params->status != STARCODE_OK)
+      break;
+    // Long jobs check for cancellation as they go.
+    if ((i - job->start) % CANCEL_INTERVAL == CANCEL_INTERVAL - 1 &&
+        check_cancelled(params))
+      break;
+
     useq_t* query = (useq_t*)useqS->items[i];
     int do_search = lut_search(lut, query) == 1;
 
@@ -914,13 +1465,17 @@ do_query(void* args) {
     void** data = NULL;
     if (job->build) {
       if (lut_insert(lut, query)) {
-        alert();
-        krash();
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
       }
       data = insert_string_wo_malloc(trie, query->seq, &node_pos);
-      if (data == NULL || *data != NULL) {
-        alert();
-        krash();
+      if (data == NULL) {
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
+      }
+      if (*data != NULL) {
+        set_status(params, STARCODE_INTERNAL_ERROR);
+        break;
       }
     }
 
@@ -951,41 +1506,37 @@ do_query(void* args) {
       // Search the trie. //
       int err = search(trie, query->seq, tau, hits, start, trail);
       if (err) {
-        alert();
-        krash();
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
       }
 
       for (int j = 0; hits[j] != TOWER_TOP; j++) {
         if (hits[j]->nitems > hits[j]->nslots) {
-          fprintf(stderr, "warning: incomplete search (%s)\n", query->seq);
+          say(params, LOG_WARNING, "warning: incomplete search (%s)\n",
+              query->seq);
           break;
         }
       }
 
       // Link matching pairs for clustering.
       // Skip dist = 0, as this would be self.
-      for (int dist = 1; dist < tau + 1; dist++) {
-        for (size_t j = 0; j < hits[dist]->nitems; j++) {
+      int failed = 0;
+      for (int dist = 1; dist < tau + 1 && !failed; dist++) {
+        for (size_t j = 0; j < hits[dist]->nitems && !failed; j++) {
           useq_t* match = (useq_t*)hits[dist]->items[j];
+          if (params->hooks != NULL && params->hooks->edge != NULL)
+            params->hooks->edge(params->hooks->data, query, match, dist);
           if (bidir_match) {
             // Make a bidirectional match reference.
             // Add reference from query to matched node.
             pthread_mutex_lock(job->mutex + job->queryid);
-            if (addmatch(query, match, dist, tau)) {
-              fprintf(stderr,
-                  "Please contact guillaume.filion@gmail.com "
-                  "for support with this issue.\n");
-              abort();
-            }
+            failed = addmatch(query, match, dist, tau);
             pthread_mutex_unlock(job->mutex + job->queryid);
+            if (failed)
+              break;
             // Add reference from matched node to query.
             pthread_mutex_lock(job->mutex + job->trieid);
-            if (addmatch(match, query, dist, tau)) {
-              fprintf(stderr,
-                  "Please contact guillaume.filion@gmail.com "
-                  "for support with this issue.\n");
-              abort();
-            }
+            failed = addmatch(match, query, dist, tau);
             pthread_mutex_unlock(job->mutex + job->trieid);
           }
 
@@ -1016,17 +1567,17 @@ do_query(void* args) {
             // The child is modified, use the child mutex.
             int mutexid = parent == query ? job->trieid : job->queryid;
             pthread_mutex_lock(job->mutex + mutexid);
-            if (addmatch(child, parent, dist, tau)) {
-              fprintf(stderr,
-                  "Please contact guillaume.filion@gmail.com "
-                  "for support with this issue.\n");
-              abort();
-            }
+            failed = addmatch(child, parent, dist, tau);
             pthread_mutex_unlock(job->mutex + mutexid);
           }
         }
       }
 
+      if (failed) {
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
+      }
+
       last_query = query;
     }
 
@@ -1038,6 +1589,7 @@ do_query(void* args) {
 
   destroy_tower(hits);
 
+done:
   // Flag trie, update thread count and signal scheduler.
   // Use the general mutex. (job->mutex[0])
   pthread_mutex_lock(job->mutex);
@@ -1047,11 +1599,14 @@ do_query(void* args) {
   pthread_cond_signal(job->monitor);
   pthread_mutex_unlock(job->mutex);
 
+  free(job_global);
+
   return NULL;
 }
 
 mtplan_t*
-plan_mt(int tau, int height, int medianlen, int ntries, gstack_t* useqS)
+plan_mt(int tau, int height, int medianlen, int ntries, gstack_t* useqS,
+    starcode_params_t* params)
 // SYNOPSIS:
 //   The scheduler makes the key assumption that the number of tries is
 //   an odd number, which allows to distribute the jobs among as in the
@@ -1073,46 +1628,40 @@ plan_mt(int tau, int height, int medianlen, int ntries, gstack_t* useqS)
 //   time (a query of block i in trie j is the same as a query of block j
 //   in trie i).
 {
-  if (ntries < 1) {
-    alert();
-    krash();
-  }
+  if (ntries < 1 || injected(STARCODE_ALLOC_ERROR))
+    return NULL;
 
   // Initialize plan.
-  mtplan_t* mtplan = malloc(sizeof(mtplan_t));
-  if (mtplan == NULL) {
-    alert();
-    krash();
-  }
+  mtplan_t* mtplan = calloc(1, sizeof(mtplan_t));
+  int* bounds = calloc(ntries + 1, sizeof(int));
+  long* nnodes = calloc(ntries, sizeof(long));
+  if (mtplan == NULL || bounds == NULL || nnodes == NULL)
+    goto fail;
+  mtplan->ntries = ntries;
 
   // Initialize mutex.
-  pthread_mutex_t* mutex = calloc(ntries + 1, sizeof(pthread_mutex_t));
-  pthread_cond_t* monitor = malloc(sizeof(pthread_cond_t));
-  if (mutex == NULL || monitor == NULL) {
-    alert();
-    krash();
-  }
+  mtplan->mutex = calloc(ntries + 1, sizeof(pthread_mutex_t));
+  mtplan->monitor = malloc(sizeof(pthread_cond_t));
+  if (mtplan->mutex == NULL || mtplan->monitor == NULL)
+    goto fail;
   for (int i = 0; i < ntries + 1; i++)
-    pthread_mutex_init(mutex + i, NULL);
-  pthread_cond_init(monitor, NULL);
+    pthread_mutex_init(mtplan->mutex + i, NULL);
+  pthread_cond_init(mtplan->monitor, NULL);
 
   // Initialize 'mttries'.
   mttrie_t* mttries = calloc(ntries, sizeof(mttrie_t));
-  if (mttries == NULL) {
-    alert();
-    krash();
-  }
+  if (mttries == NULL)
+    goto fail;
+  mtplan->tries = mttries;
 
   // Boundaries of the query blocks.
   int Q = useqS->nitems / ntries;
   int R = useqS->nitems % ntries;
-  int* bounds = calloc(ntries + 1, sizeof(int));
   for (int i = 0; i < ntries + 1; i++)
     bounds[i] = Q * i + min(i, R);
 
   // Preallocated tries.
   // Count with maxlen-1
-  long* nnodes = calloc(ntries, sizeof(long));
   for (int i = 0; i < ntries; i++)
     nnodes[i] =
         count_trie_nodes((useq_t**)useqS->items, bounds[i], bounds[i + 1]);
@@ -1121,29 +1670,30 @@ plan_mt(int tau, int height, int medianlen, int ntries, gstack_t* useqS)
   for (int i = 0; i < ntries; i++) {
     // Remember that 'ntries' is odd.
     int njobs = (ntries + 1) / 2;
-       //fprintf(stderr, "Tower build base\n");
 
-    trie_t* local_trie = new_trie(height);
-    node_t* local_nodes = (node_t*)calloc(nnodes[i], sizeof(node_t));
     mtjob_t* jobs = calloc(njobs, sizeof(mtjob_t));
-    if (local_trie == NULL || jobs == NULL) {
-      alert();
-      krash();
-    }
+    if (jobs == NULL)
+      goto fail;
+    mttries[i].jobs = jobs;
 
+    trie_t* local_trie = new_trie(height);
+    node_t* local_nodes = (node_t*)calloc(nnodes[i], sizeof(node_t));
     // Allocate lookup struct.
     // TODO: Try only one lut as well (it will always return 1
     // in the query step though).
-    lookup_t* local_lut = new_lookup(medianlen, height, tau);
-    if (local_lut == NULL) {
-      alert();
-      krash();
-    }
+    lookup_t* local_lut = new_lookup(medianlen, height, tau, params);
+    // Store the pieces in the first job so that 'destroy_mtplan()'
+    // can release them even if some of the allocations failed.
+    jobs[0].trie = local_trie;
+    jobs[0].node_pos = local_nodes;
+    jobs[0].lut = local_lut;
+    if (local_trie == NULL || local_lut == NULL ||
+        (local_nodes == NULL && nnodes[i] > 0))
+      goto fail;
 
     mttries[i].flag = TRIE_FREE;
     mttries[i].currentjob = 0;
     mttries[i].njobs = njobs;
-    mttries[i].jobs = jobs;
 
     for (int j = 0; j < njobs; j++) {
       // Shift boundaries in a way that every trie is built
@@ -1159,8 +1709,8 @@ plan_mt(int tau, int height, int medianlen, int ntries, gstack_t* useqS)
       jobs[j].trie = local_trie;
       jobs[j].node_pos = local_nodes;
       jobs[j].lut = local_lut;
-      jobs[j].mutex = mutex;
-      jobs[j].monitor = monitor;
+      jobs[j].mutex = mtplan->mutex;
+      jobs[j].monitor = mtplan->monitor;
       jobs[j].jobsdone = &(mtplan->jobsdone);
       jobs[j].trieflag = &(mttries[i].flag);
       jobs[j].active = &(mtplan->active);
@@ -1173,14 +1723,77 @@ plan_mt(int tau, int height, int medianlen, int ntries, gstack_t* useqS)
   free(bounds);
   free(nnodes);
 
-  mtplan->active = 0;
-  mtplan->ntries = ntries;
-  mtplan->jobsdone = 0;
-  mtplan->mutex = mutex;
-  mtplan->monitor = monitor;
-  mtplan->tries = mttries;
-
   return mtplan;
+
+fail:
+  free(bounds);
+  free(nnodes);
+  destroy_mtplan(mtplan);
+  return NULL;
+}
+
+void
+destroy_mtplan(mtplan_t* mtplan)
+// SYNOPSIS:
+//   Frees the plan created by 'plan_mt()'. Partially created plans
+//   are accepted.
+{
+  if (mtplan == NULL)
+    return;
+  free(mtplan->mutex);
+  free(mtplan->monitor);
+  if (mtplan->tries != NULL) {
+    for (int i = 0 ; i < mtplan->ntries ; i++) {
+      mtjob_t* jobs = mtplan->tries[i].jobs;
+      if (jobs == NULL)
+        continue;
+      free(jobs->node_pos);
+      destroy_lookup(jobs->lut);
+      // The sequences in the trie belong to 'useqS'.
+      if (jobs->trie != NULL)
+        destroy_trie(jobs->trie, 1, NULL);
+      free(jobs);
+    }
+  }
+  free(mtplan->tries);
+  free(mtplan);
+}
+
+starcode_context_t*
+starcode_context_new(void)
+// SYNOPSIS:
+//   Creates an empty context, see 'starcode_context_clear()'.
+//
+// RETURN:
+//   The context, NULL if it could not be allocated.
+{
+  return calloc(1, sizeof(starcode_context_t));
+}
+
+void
+starcode_context_clear(starcode_context_t* context)
+// SYNOPSIS:
+//   Frees the parameters, the sequences (with their matches) and the
+//   tries and lookups of the last run made with 'context'. The tries
+//   only point to the sequences, they go first.
+{
+  if (context == NULL)
+    return;
+  destroy_mtplan(context->mtplan);
+  destroy_useqs(context->useqS);
+  destroy_starcode_params(context->params);
+  context->mtplan = NULL;
+  context->useqS = NULL;
+  context->params = NULL;
+}
+
+void
+starcode_context_destroy(starcode_context_t* context)
+// SYNOPSIS:
+//   Frees what the context owns, then the context.
+{
+  starcode_context_clear(context);
+  free(context);
 }
 
 long
@@ -1198,30 +1811,37 @@ count_trie_nodes(useq_t** seqs, int start, int end) {
   return count;
 }
 
-void
+int
 connected_components(useq_t* useq, gstack_t** cluster) {
   // Flag claimed.
   useq->canonical = useq;
   // Add myself to cluster.
-  push(useq, cluster);
+  if (push(useq, cluster))
+    return 1;
   // Recursive call on edges.
   if (useq->matches == NULL)
-    return;
+    return 0;
   gstack_t* matches;
   for (int j = 0; (matches = useq->matches[j]) != TOWER_TOP; j++) {
     for (size_t k = 0; k < matches->nitems; k++) {
       useq_t* match = (useq_t*)matches->items[k];
       if (match->canonical != NULL)
         continue;
-      connected_components(match, cluster);
+      if (connected_components(match, cluster))
+        return 1;
     }
   }
+  return 0;
 }
 
 gstack_t*
-compute_clusters(gstack_t* uSQ) {
+compute_clusters(gstack_t* uSQ, starcode_params_t* params) {
   gstack_t* clusters = new_gstack();
+  if (clusters == NULL)
+    return NULL;
   for (size_t i = 0; i < uSQ->nitems; i++) {
+    if (i % CANCEL_INTERVAL == CANCEL_INTERVAL - 1 && check_cancelled(params))
+      goto fail;
     useq_t* useq = (useq_t*)uSQ->items[i];
 
     // Check sequence flag.
@@ -1230,9 +1850,14 @@ compute_clusters(gstack_t* uSQ) {
 
     // Create new cluster.
     gstack_t* cluster = new_gstack();
+    if (cluster == NULL)
+      goto fail;
 
     // Recursively gather connected components.
-    connected_components(useq, &cluster);
+    if (connected_components(useq, &cluster)) {
+      destroy_gstack(cluster);
+      goto fail;
+    }
 
     // Find centroid. (max: #counts THEN #edges).
     // Count useq edges.
@@ -1280,7 +1905,10 @@ compute_clusters(gstack_t* uSQ) {
     }
     useq->count = cluster_count;
     // Store cluster.
-    push(cluster, &clusters);
+    if (push(cluster, &clusters)) {
+      destroy_gstack(cluster);
+      goto fail;
+    }
   }
 
   // Sort clusters by size (counts).
@@ -1288,14 +1916,22 @@ compute_clusters(gstack_t* uSQ) {
       clusters->items, clusters->nitems, sizeof(gstack_t*), cluster_count);
 
   return clusters;
+
+fail:
+  for (size_t i = 0; i < clusters->nitems; i++)
+    destroy_gstack(clusters->items[i]);
+  destroy_gstack(clusters);
+  return NULL;
 }
 
-void
-sphere_clustering(gstack_t* useqS) {
+int
+sphere_clustering(gstack_t* useqS, starcode_params_t* params) {
   // Sort in count order.
   qsort(useqS->items, useqS->nitems, sizeof(useq_t*), count_order_spheres);
 
   for (size_t i = 0; i < useqS->nitems; i++) {
+    if (i % CANCEL_INTERVAL == CANCEL_INTERVAL - 1 && check_cancelled(params))
+      return STARCODE_CANCELLED;
     useq_t* useq = (useq_t*)useqS->items[i];
     if (useq->canonical != NULL)
       continue;
@@ -1330,13 +1966,15 @@ sphere_clustering(gstack_t* useqS) {
     }
   }
 
-  return;
+  return STARCODE_OK;
 }
 
-void
-message_passing_clustering(gstack_t* useqS) {
+int
+message_passing_clustering(gstack_t* useqS, starcode_params_t* params) {
   // Transfer counts to parents recursively.
   for (size_t i = 0; i < useqS->nitems; i++) {
+    if (i % CANCEL_INTERVAL == CANCEL_INTERVAL - 1 && check_cancelled(params))
+      return STARCODE_CANCELLED;
     useq_t* u = (useq_t*)useqS->items[i];
     transfer_counts_and_update_canonicals(u);
   }
@@ -1344,13 +1982,15 @@ message_passing_clustering(gstack_t* useqS) {
   // Resolve ambiguous assignments.
   for (size_t i = 0; i < useqS->nitems; i++) {
     useq_t* u = (useq_t*)useqS->items[i];
-    mp_resolve_ambiguous(u);
+    int status = mp_resolve_ambiguous(u);
+    if (status != STARCODE_OK)
+      return status;
   }
 
-  return;
+  return STARCODE_OK;
 }
 
-size_t
+ssize_t
 seqsort(useq_t** data, size_t numels, int thrmax)
 // SYNOPSIS:
 //   Recursive merge sort for 'useq_t' arrays, tailored for the
@@ -1366,13 +2006,17 @@ seqsort(useq_t** data, size_t numels, int thrmax)
 //   thrmax: number of threads.
 //
 // RETURN:
-//   Number of unique elements.
+//   Number of unique elements, or -1 if memory allocation failed.
 //
 // SIDE EFFECTS:
 //   Pointers to repeated elements are set to NULL.
 {
   // Copy to buffer.
+  if (injected(STARCODE_ALLOC_ERROR))
+    return -1;
   useq_t** buffer = calloc(numels, sizeof(useq_t*));
+  if (buffer == NULL)
+    return -1;
   memcpy(buffer, data, numels * sizeof(useq_t*));
 
   // Prepare args struct.
@@ -1388,6 +2032,7 @@ seqsort(useq_t** data, size_t numels, int thrmax)
   args.b = 0;
   args.thread = 0;
   args.repeats = 0;
+  args.err = 0;
 
   // Allocate a number of threads that is a power of 2.
   while ((thrmax >> (args.thread + 1)) > 0)
@@ -1396,6 +2041,8 @@ seqsort(useq_t** data, size_t numels, int thrmax)
   nukesort(&args);
 
   free(buffer);
+  if (args.err)
+    return -1;
   return numels - args.repeats;
 }
 
@@ -1420,9 +2067,6 @@ nukesort(void* args)
 // SIDE EFFECTS:
 //   Sorts the array of 'useq_t' specified in 'args'.
 {
-  // Initialize thread-local variables
-  init_thread_local_tower_top();
-  
   sortargs_t* sortargs = (sortargs_t*)args;
   if (sortargs->size < 2)
     return NULL;
@@ -1441,14 +2085,18 @@ nukesort(void* args)
     arg1.thread = arg2.thread = arg1.thread - 1;
     // Create threads.
     pthread_t thread1, thread2;
-    if (pthread_create(&thread1, NULL, nukesort, &arg1) ||
-        pthread_create(&thread2, NULL, nukesort, &arg2)) {
-      alert();
-      krash();
-    }
+    int started1 = pthread_create(&thread1, NULL, nukesort, &arg1) == 0;
+    int started2 = pthread_create(&thread2, NULL, nukesort, &arg2) == 0;
+    // Sort in this thread if no thread could be started.
+    if (!started1)
+      nukesort(&arg1);
+    if (!started2)
+      nukesort(&arg2);
     // Wait for threads.
-    pthread_join(thread1, NULL);
-    pthread_join(thread2, NULL);
+    if (started1)
+      pthread_join(thread1, NULL);
+    if (started2)
+      pthread_join(thread2, NULL);
   } else {
     nukesort(&arg1);
     nukesort(&arg2);
@@ -1495,7 +2143,10 @@ nukesort(void* args)
       // Identical sequences, this is the "nuke" part.
       // Add sequence counts.
       ul->count += ur->count;
-      transfer_useq_ids(ul, ur);
+      // On failure, keep merging so that the array remains
+      // consistent for the cleanup.
+      if (transfer_useq_ids(ul, ur))
+        sortargs->err = 1;
       destroy_useq(ur);
       buf[idx++] = l[i++];
       j++;
@@ -1506,8 +2157,9 @@ nukesort(void* args)
       buf[idx++] = r[j++];
   }
 
-  // Accumulate repeats
+  // Accumulate repeats and errors.
   sortargs->repeats = repeats + arg1.repeats + arg2.repeats;
+  sortargs->err |= arg1.err | arg2.err;
 
   // Pad with NULLS.
   ssize_t offset = sortargs->size - sortargs->repeats;
@@ -1516,6 +2168,79 @@ nukesort(void* args)
   return NULL;
 }
 
+int
+check_seq(const char* seq, starcode_params_t* params)
+// SYNOPSIS:
+//   Checks that an input sequence is not too long and contains
+//   only valid DNA characters. Reports the offending sequence.
+//
+// RETURN:
+//   STARCODE_OK, STARCODE_SEQ_TOO_LONG or STARCODE_INVALID_INPUT.
+{
+  size_t seqlen = strlen(seq);
+  if (seqlen > MAXBRCDLEN) {
+    say(params, LOG_ERROR, "max sequence length exceeded (%d)\n"
+        "offending sequence:\n%s\n", MAXBRCDLEN, seq);
+    return STARCODE_SEQ_TOO_LONG;
+  }
+  for (size_t i = 0; i < seqlen; i++) {
+    if (!valid_DNA_char[(uint8_t)seq[i]]) {
+      say(params, LOG_ERROR, "invalid input\noffending sequence:\n%s\n", seq);
+      return STARCODE_INVALID_INPUT;
+    }
+  }
+  return STARCODE_OK;
+}
+
+int
+check_pe_seq(const char* seq1, const char* seq2, starcode_params_t* params)
+// SYNOPSIS:
+//   Checks the mates of a pair as 'check_seq()', and that they are
+//   not too long once joined by the STARCODE_MAX_TAU + 1 dashes
+//   that keep them apart. Reports the offending pair.
+//
+// RETURN:
+//   STARCODE_OK, STARCODE_SEQ_TOO_LONG or STARCODE_INVALID_INPUT.
+{
+  int status = check_seq(seq1, params);
+  if (status == STARCODE_OK)
+    status = check_seq(seq2, params);
+  if (status != STARCODE_OK)
+    return status;
+  if (strlen(seq1) + STARCODE_MAX_TAU + 1 + strlen(seq2) > MAXBRCDLEN) {
+    say(params, LOG_ERROR, "max sequence length exceeded (%d) by joined pair\n"
+        "offending pair:\n%s\n%s\n", MAXBRCDLEN, seq1, seq2);
+    return STARCODE_SEQ_TOO_LONG;
+  }
+  return STARCODE_OK;
+}
+
+int
+push_read(int count, char* seq, char* info, gstack_t** uSQ)
+// SYNOPSIS:
+//   Creates a 'useq_t' for an input read and pushes it on the
+//   stack. The sequence ID is the 1-based position of the read.
+//
+// RETURN:
+//   0 upon success, 1 if memory allocation failed.
+{
+  useq_t* new = new_useq(count, seq, info);
+  if (new == NULL)
+    return 1;
+  new->nids = 1;
+  new->seqid = malloc(sizeof(int));
+  if (new->seqid == NULL) {
+    destroy_useq(new);
+    return 1;
+  }
+  new->seqid[0] = (*uSQ)->nitems + 1;
+  if (push(new, uSQ)) {
+    destroy_useq(new);
+    return 1;
+  }
+  return 0;
+}
+
 gstack_t*
 read_rawseq(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
   ssize_t nread;
@@ -1523,8 +2248,8 @@ read_rawseq(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
   char copy[MAXBRCDLEN];
   char* line = malloc(M);
   if (line == NULL) {
-    alert();
-    krash();
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return uSQ;
   }
 
   char* seq = NULL;
@@ -1532,9 +2257,10 @@ read_rawseq(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
   while ((nread = getline(&line, &nchar, inputf)) != -1) {
     if (nread > MAXBRCDLEN) {
       // Could trigger an overflow in 'copy'.
-      fprintf(stderr, "max sequence length exceeded (%d)\n", MAXBRCDLEN);
-      fprintf(stderr, "offending line:\n%s\n", line);
-      abort();
+      say(params, LOG_ERROR, "max sequence length exceeded (%d)\n"
+          "offending line:\n%s\n", MAXBRCDLEN, line);
+      set_status(params, STARCODE_SEQ_TOO_LONG);
+      break;
     }
     if (line[nread - 1] == '\n')
       line[nread - 1] = '\0';
@@ -1544,27 +2270,15 @@ read_rawseq(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
     } else {
       seq = copy;
     }
-    size_t seqlen = strlen(seq);
-    for (size_t i = 0; i < seqlen; i++) {
-      if (!valid_DNA_char[(int)seq[i]]) {
-        fprintf(stderr, "invalid input\n");
-        fprintf(stderr, "offending sequence:\n%s\n", seq);
-        abort();
-      }
-    }
-    useq_t* new = new_useq(count, seq, NULL);
-    if (new == NULL) {
-      alert();
-      krash();
+    int status = check_seq(seq, params);
+    if (status != STARCODE_OK) {
+      set_status(params, status);
+      break;
     }
-    new->nids = 1;
-    new->seqid = malloc(sizeof(int));
-    if (new->seqid == NULL) {
-      alert();
-      krash();
+    if (push_read(count, seq, NULL, &uSQ)) {
+      set_status(params, STARCODE_ALLOC_ERROR);
+      break;
     }
-    new->seqid[0] = uSQ->nitems + 1;
-    push(new, &uSQ);
   }
 
   free(line);
@@ -1577,8 +2291,8 @@ read_fasta(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
   size_t nchar = M;
   char* line = malloc(M);
   if (line == NULL) {
-    alert();
-    krash();
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return uSQ;
   }
 
   char* header = NULL;
@@ -1592,41 +2306,24 @@ read_fasta(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
       line[nread - 1] = '\0';
 
     if (lineno % 2 == 0) {
-      size_t seqlen = strlen(line);
-      if (seqlen > MAXBRCDLEN) {
-        fprintf(stderr, "max sequence length exceeded (%d)\n", MAXBRCDLEN);
-        fprintf(stderr, "offending sequence:\n%s\n", line);
-        abort();
-      }
-      for (size_t i = 0; i < seqlen; i++) {
-        if (!valid_DNA_char[(int)line[i]]) {
-          fprintf(stderr, "invalid input\n");
-          fprintf(stderr, "offending sequence:\n%s\n", line);
-          abort();
-        }
+      int status = check_seq(line, params);
+      if (status != STARCODE_OK) {
+        set_status(params, status);
+        break;
       }
-      useq_t* new = new_useq(1, line, header);
-      if (new == NULL) {
-        alert();
-        krash();
+      if (push_read(1, line, header, &uSQ)) {
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
       }
       if (header != NULL) {
         free(header);
         header = NULL;
       }
-      new->nids = 1;
-      new->seqid = malloc(sizeof(int));
-      if (new->seqid == NULL) {
-        alert();
-        krash();
-      }
-      new->seqid[0] = uSQ->nitems + 1;
-      push(new, &uSQ);
     } else if (readh) {
       header = strdup(line);
       if (header == NULL) {
-        alert();
-        krash();
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
       }
     }
   }
@@ -1643,8 +2340,8 @@ read_fastq(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
   size_t nchar = M;
   char* line = malloc(M);
   if (line == NULL) {
-    alert();
-    krash();
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return uSQ;
   }
 
   char seq[M + 1] = {0};
@@ -1662,41 +2359,25 @@ read_fastq(FILE* inputf, gstack_t* uSQ, starcode_params_t* params) {
     if (readh && lineno % 4 == 1) {
       strncpy(header, line, M);
     } else if (lineno % 4 == 2) {
-      size_t seqlen = strlen(line);
-      if (seqlen > MAXBRCDLEN) {
-        fprintf(stderr, "max sequence length exceeded (%d)\n", MAXBRCDLEN);
-        fprintf(stderr, "offending sequence:\n%s\n", line);
-        abort();
-      }
-      for (size_t i = 0; i < seqlen; i++) {
-        if (!valid_DNA_char[(int)line[i]]) {
-          fprintf(stderr, "invalid input\n");
-          fprintf(stderr, "offending sequence:\n%s\n", line);
-          abort();
-        }
+      int status = check_seq(line, params);
+      if (status != STARCODE_OK) {
+        set_status(params, status);
+        break;
       }
       strncpy(seq, line, M);
     } else if (lineno % 4 == 0) {
       if (readh) {
         int status = snprintf(info, 2 * M + 2, "%s\n%s", header, line);
         if (status < 0 || status > 2 * M - 1) {
-          alert();
-          krash();
+          say(params, LOG_ERROR, "fastq record too long\n");
+          set_status(params, STARCODE_INVALID_INPUT);
+          break;
         }
       }
-      useq_t* new = new_useq(1, seq, info);
-      if (new == NULL) {
-        alert();
-        krash();
-      }
-      new->nids = 1;
-      new->seqid = malloc(sizeof(int));
-      if (new->seqid == NULL) {
-        alert();
-        krash();
+      if (push_read(1, seq, info, &uSQ)) {
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
       }
-      new->seqid[0] = uSQ->nitems + 1;
-      push(new, &uSQ);
     }
   }
 
@@ -1709,21 +2390,24 @@ read_PE_fastq(FILE* inputf1, FILE* inputf2, gstack_t* uSQ, starcode_params_t* pa
   char c1 = fgetc(inputf1);
   char c2 = fgetc(inputf2);
   if (c1 != '@' || c2 != '@') {
-    fprintf(stderr, "input not a pair of fastq files\n");
-    abort();
+    say(params, LOG_ERROR, "input not a pair of fastq files\n");
+    set_status(params, STARCODE_INVALID_INPUT);
+    return uSQ;
   }
   if (ungetc(c1, inputf1) == EOF || ungetc(c2, inputf2) == EOF) {
-    alert();
-    krash();
+    set_status(params, STARCODE_IO_ERROR);
+    return uSQ;
   }
 
   ssize_t nread;
   size_t nchar = M;
   char* line1 = malloc(M);
   char* line2 = malloc(M);
-  if (line1 == NULL && line2 == NULL) {
-    alert();
-    krash();
+  if (line1 == NULL || line2 == NULL) {
+    free(line1);
+    free(line2);
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return uSQ;
   }
 
   char seq1[M] = {0};
@@ -1746,8 +2430,9 @@ read_PE_fastq(FILE* inputf1, FILE* inputf2, gstack_t* uSQ, starcode_params_t* pa
 
     // Read line from second file and strip newline.
     if ((nread = getline(&line2, &nchar, inputf2)) == -1) {
-      fprintf(stderr, "non conformable paired-end fastq files\n");
-      abort();
+      say(params, LOG_ERROR, "non conformable paired-end fastq files\n");
+      set_status(params, STARCODE_INVALID_INPUT);
+      break;
     }
     if (line2[nread - 1] == '\n')
       line2[nread - 1] = '\0';
@@ -1760,64 +2445,39 @@ read_PE_fastq(FILE* inputf1, FILE* inputf2, gstack_t* uSQ, starcode_params_t* pa
       strncpy(header1, line1, M-1);
       strncpy(header2, line2, M-1);
     } else if (lineno % 4 == 2) {
-      size_t seqlen1 = strlen(line1);
-      size_t seqlen2 = strlen(line2);
-      if (seqlen1 > MAXBRCDLEN || seqlen2 > MAXBRCDLEN) {
-        fprintf(stderr, "max sequence length exceeded (%d)\n", MAXBRCDLEN);
-        fprintf(stderr, "offending sequences:\n%s\n%s\n", line1, line2);
-        abort();
-      }
-      for (size_t i = 0; i < seqlen1; i++) {
-        if (!valid_DNA_char[(int)line1[i]]) {
-          fprintf(stderr, "invalid input\n");
-          fprintf(stderr, "offending sequence:\n%s\n", line1);
-          abort();
-        }
-      }
-      for (size_t i = 0; i < seqlen2; i++) {
-        if (!valid_DNA_char[(int)line2[i]]) {
-          fprintf(stderr, "invalid input\n");
-          fprintf(stderr, "offending sequence:\n%s\n", line2);
-          abort();
-        }
+      int status = check_pe_seq(line1, line2, params);
+      if (status != STARCODE_OK) {
+        set_status(params, status);
+        break;
       }
       strncpy(seq1, line1, M-1);
       strncpy(seq2, line2, M-1);
     } else if (lineno % 4 == 0) {
+      int scheck;
       if (readh) {
-        int scheck = snprintf(
+        scheck = snprintf(
             info, 4 * M, "%s\n%s\n%s\n%s", header1, line1, header2, line2);
-        if (scheck < 0 || scheck > 4 * M - 1) {
-          alert();
-          krash();
-        }
+        scheck = scheck < 0 || scheck > 4 * M - 1;
       } else {
         // No need for the headers, the 'info' member is
         // used to hold a string representation of the pair.
-        int scheck = snprintf(info, 2 * M, "%s/%s", seq1, seq2);
-        if (scheck < 0 || scheck > 2 * M - 1) {
-          alert();
-          krash();
-        }
+        scheck = snprintf(info, 2 * M, "%s/%s", seq1, seq2);
+        scheck = scheck < 0 || scheck > 2 * M - 1;
       }
-      int scheck = snprintf(seq, 2 * M + 8, "%s%s%s", seq1, sep, seq2);
-      if (scheck < 0 || scheck > 2 * M + 7) {
-        alert();
-        krash();
+      if (scheck) {
+        say(params, LOG_ERROR, "fastq record too long\n");
+        set_status(params, STARCODE_INVALID_INPUT);
+        break;
       }
-      useq_t* new = new_useq(1, seq, info);
-      if (new == NULL) {
-        alert();
-        krash();
+      scheck = snprintf(seq, 2 * M + 8, "%s%s%s", seq1, sep, seq2);
+      if (scheck < 0 || scheck > 2 * M + 7) {
+        set_status(params, STARCODE_INTERNAL_ERROR);
+        break;
       }
-      new->nids = 1;
-      new->seqid = malloc(sizeof(int));
-      if (new->seqid == NULL) {
-        alert();
-        krash();
+      if (push_read(1, seq, info, &uSQ)) {
+        set_status(params, STARCODE_ALLOC_ERROR);
+        break;
       }
-      new->seqid[0] = uSQ->nitems + 1;
-      push(new, &uSQ);
     }
   }
 
@@ -1827,7 +2487,80 @@ read_PE_fastq(FILE* inputf1, FILE* inputf2, gstack_t* uSQ, starcode_params_t* pa
 }
 
 gstack_t*
-read_file(FILE* inputf1, FILE* inputf2, const int verbose, starcode_params_t* params) {
+read_seqs(
+    char** seqs,
+    const long* counts,
+    const size_t nseq,
+    gstack_t* uSQ,
+    starcode_params_t* params
+)
+// SYNOPSIS:
+//   Same as 'read_rawseq()' for sequences and counts passed in
+//   memory. Sequence IDs are the 1-based positions in 'seqs'.
+{
+  if (uSQ == NULL) {
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return NULL;
+  }
+
+  for (size_t i = 0; i < nseq; i++) {
+    int status = check_seq(seqs[i], params);
+    if (status != STARCODE_OK) {
+      set_status(params, status);
+      break;
+    }
+    if (push_read(counts[i], seqs[i], NULL, &uSQ)) {
+      set_status(params, STARCODE_ALLOC_ERROR);
+      break;
+    }
+  }
+
+  return uSQ;
+}
+
+gstack_t*
+read_pe_seqs(
+    char** seqs1,
+    char** seqs2,
+    const long* counts,
+    const size_t nseq,
+    gstack_t* uSQ,
+    starcode_params_t* params
+)
+// SYNOPSIS:
+//   Same as 'read_PE_fastq()' for pairs passed in memory. Sequence
+//   IDs are the 1-based positions in 'seqs1' and 'seqs2'.
+{
+  if (uSQ == NULL) {
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return NULL;
+  }
+
+  char seq[2 * M + 8] = {0};
+  char info[2 * M + 2] = {0};
+  char sep[STARCODE_MAX_TAU + 2] = {0};
+  memset(sep, '-', STARCODE_MAX_TAU + 1);
+
+  for (size_t i = 0; i < nseq; i++) {
+    int status = check_pe_seq(seqs1[i], seqs2[i], params);
+    if (status != STARCODE_OK) {
+      set_status(params, status);
+      break;
+    }
+    // The 'info' member holds a string representation of the pair.
+    snprintf(info, 2 * M + 2, "%s/%s", seqs1[i], seqs2[i]);
+    snprintf(seq, 2 * M + 8, "%s%s%s", seqs1[i], sep, seqs2[i]);
+    if (push_read(counts[i], seq, info, &uSQ)) {
+      set_status(params, STARCODE_ALLOC_ERROR);
+      break;
+    }
+  }
+
+  return uSQ;
+}
+
+gstack_t*
+read_file(FILE* inputf1, FILE* inputf2, starcode_params_t* params) {
   if (inputf2 != NULL)
     params->format = PE_FASTQ;
   else {
@@ -1840,30 +2573,27 @@ read_file(FILE* inputf1, FILE* inputf2, const int verbose, starcode_params_t* pa
         return NULL;
       case '>':
         params->format = FASTA;
-        if (verbose)
-          fprintf(stderr, "FASTA format detected\n");
+        say(params, LOG_INFO, "FASTA format detected\n");
         break;
       case '@':
         params->format = FASTQ;
-        if (verbose)
-          fprintf(stderr, "FASTQ format detected\n");
+        say(params, LOG_INFO, "FASTQ format detected\n");
         break;
       default:
         params->format = RAW;
-        if (verbose)
-          fprintf(stderr, "raw format detected\n");
+        say(params, LOG_INFO, "raw format detected\n");
     }
 
     if (ungetc(c, inputf1) == EOF) {
-      alert();
-      krash();
+      set_status(params, STARCODE_IO_ERROR);
+      return NULL;
     }
   }
 
   gstack_t* uSQ = new_gstack();
   if (uSQ == NULL) {
-    alert();
-    krash();
+    set_status(params, STARCODE_ALLOC_ERROR);
+    return NULL;
   }
 
   if (params->format == RAW)
@@ -1875,11 +2605,22 @@ read_file(FILE* inputf1, FILE* inputf2, const int verbose, starcode_params_t* pa
   if (params->format == PE_FASTQ)
     return read_PE_fastq(inputf1, inputf2, uSQ, params);
 
-  return NULL;
+  return uSQ;
 }
 
 int
-pad_useq(gstack_t* useqS, int* median) {
+pad_useq(gstack_t* useqS, int* median)
+// SYNOPSIS:
+//   Pads all sequences with leading spaces to the length of the
+//   longest and computes the median length.
+//
+// RETURN:
+//   The length of the longest sequence, or -1 if memory
+//   allocation failed.
+{
+  if (injected(STARCODE_ALLOC_ERROR))
+    return -1;
+
   // Compute maximum length.
   int maxlen = 0;
   for (size_t i = 0; i < useqS->nitems; i++) {
@@ -1893,8 +2634,9 @@ pad_useq(gstack_t* useqS, int* median) {
   size_t* count = calloc(maxlen + 1, sizeof(size_t));
   char* spaces = malloc(maxlen + 1);
   if (spaces == NULL || count == NULL) {
-    alert();
-    krash();
+    free(count);
+    free(spaces);
+    return -1;
   }
   for (int i = 0; i < maxlen; i++)
     spaces[i] = ' ';
@@ -1910,8 +2652,9 @@ pad_useq(gstack_t* useqS, int* median) {
     // Create a new sequence with padding characters.
     char* padded = malloc(maxlen + 1);
     if (padded == NULL) {
-      alert();
-      krash();
+      free(count);
+      free(spaces);
+      return -1;
     }
     memcpy(padded, spaces, maxlen + 1);
     memcpy(padded + maxlen - len, u->seq, len);
@@ -1932,8 +2675,14 @@ pad_useq(gstack_t* useqS, int* median) {
   return maxlen;
 }
 
-void
-unpad_useq(gstack_t* useqS) {
+int
+unpad_useq(gstack_t* useqS)
+// SYNOPSIS:
+//   Removes the padding characters added by 'pad_useq()'.
+//
+// RETURN:
+//   0 upon success, 1 if memory allocation failed.
+{
   // Take the length of the first sequence (assume all
   // sequences have the same length).
   int len = strlen(((useq_t*)useqS->items[0])->seq);
@@ -1944,50 +2693,48 @@ unpad_useq(gstack_t* useqS) {
       pad++;
     // Create a new sequence without paddings characters.
     char* unpadded = calloc((len - pad + 1), sizeof(char));
-    if (unpadded == NULL) {
-      alert();
-      krash();
-    }
+    if (unpadded == NULL)
+      return 1;
     memcpy(unpadded, u->seq + pad, len - pad + 1);
     free(u->seq);
     u->seq = unpadded;
   }
-  return;
+  return 0;
 }
 
-void
+int
 transfer_useq_ids(useq_t* ud, useq_t* us)
 // Appends the sequence ID list from 'us' to 'ud',
-// the final list is unsorted.
+// the final list is unsorted. Returns 1 if memory
+// allocation failed ('ud' is unchanged), 0 otherwise.
 {
   if (us->nids < 1)
-    return;
+    return 0;
   // Realloc destination buffer.
-  ud->seqid = realloc(ud->seqid, (ud->nids + us->nids) * sizeof(int));
-  if (ud->seqid == NULL) {
-    alert();
-    krash();
-  }
+  int* seqid = realloc(ud->seqid, (ud->nids + us->nids) * sizeof(int));
+  if (seqid == NULL)
+    return 1;
+  ud->seqid = seqid;
   // Copy source list of ids to ud.
   memcpy(ud->seqid + ud->nids, us->seqid, us->nids * sizeof(int));
   // Update id counts in both sequences.
   ud->nids += us->nids;
   us->nids = 0;
+  return 0;
 }
 
-void
+int
 transfer_sorted_useq_ids(useq_t* ud, useq_t* us)
 // Appends the sequence ID list from 'us' to 'ud'
-// and sorts the final list.
+// and sorts the final list. Returns 1 if memory
+// allocation failed ('ud' is unchanged), 0 otherwise.
 {
   if (us->nids < 1)
-    return;
+    return 0;
   // Alloc merge-sort buffer.
   int* buf = calloc(ud->nids + us->nids, sizeof(int));
-  if (buf == NULL) {
-    alert();
-    krash();
-  }
+  if (buf == NULL)
+    return 1;
   int *s, *d;
   d = ud->seqid;
   s = us->seqid;
@@ -2014,6 +2761,7 @@ transfer_sorted_useq_ids(useq_t* ud, useq_t* us)
   ud->seqid = buf;
   ud->nids = k;
   us->nids = 0;
+  return 0;
 }
 
 void
@@ -2092,11 +2840,11 @@ transfer_counts_and_update_canonicals(useq_t* useq)
   return;
 }
 
-void
+int
 mp_resolve_ambiguous(useq_t* useq) {
   // Ambiguous sequences must have NULL canonicals.
   if (useq->canonical != NULL) {
-    return;
+    return STARCODE_OK;
   }
 
   // Get parents.
@@ -2109,8 +2857,11 @@ mp_resolve_ambiguous(useq_t* useq) {
   // Propagate if this is descendant of ambiguous.
   for (size_t i = 0; i < matches->nitems; i++) {
     useq_t* match = (useq_t*)matches->items[i];
-    if (match->canonical == NULL)
-      mp_resolve_ambiguous(match);
+    if (match->canonical == NULL) {
+      int status = mp_resolve_ambiguous(match);
+      if (status != STARCODE_OK)
+        return status;
+    }
   }
 
   // Select canonical. Criteria:
@@ -2155,10 +2906,8 @@ mp_resolve_ambiguous(useq_t* useq) {
 
   // This should not ever happen. It is only a security
   // to avoid dereferencing a null pointer below.
-  if (canonical == NULL) {
-    alert();
-    krash();
-  }
+  if (canonical == NULL)
+    return STARCODE_INTERNAL_ERROR;
 
   // Transfer counts and seq ids to canonical.
   useq->canonical = canonical;
@@ -2168,6 +2917,8 @@ mp_resolve_ambiguous(useq_t* useq) {
   useq->count = 0;
   // Increase canonical sphere size.
   canonical->sphere_c += 1;
+
+  return STARCODE_OK;
 }
 
 int
@@ -2195,15 +2946,17 @@ addmatch(useq_t* to, useq_t* from, int dist, int maxtau)
   // Create stack if not done before.
   if (to->matches == NULL)
     to->matches = new_tower(maxtau + 1);
+  if (to->matches == NULL)
+    return 1;
   return push(from, to->matches + dist);
 }
 
 lookup_t*
-new_lookup(int slen, int maxlen, int tau) {
+new_lookup(int slen, int maxlen, int tau, starcode_params_t* params) {
   lookup_t* lut = (lookup_t*)malloc(
       2 * sizeof(int) + sizeof(int*) + (tau + 1) * sizeof(char*));
   if (lut == NULL) {
-    alert();
+    alert(params);
     return NULL;
   }
 
@@ -2215,6 +2968,11 @@ new_lookup(int slen, int maxlen, int tau) {
   lut->slen = maxlen;
   lut->kmers = tau + 1;
   lut->klen = calloc(lut->kmers, sizeof(int));
+  if (lut->klen == NULL) {
+    free(lut);
+    alert(params);
+    return NULL;
+  }
 
   // Compute k-mer lengths.
   if (k > MAX_K_FOR_LOOKUP)
@@ -2232,8 +2990,9 @@ new_lookup(int slen, int maxlen, int tau) {
       while (--i >= 0) {
         free(lut->lut[i]);
       }
+      free(lut->klen);
       free(lut);
-      alert();
+      alert(params);
       return NULL;
     }
   }
@@ -2243,6 +3002,8 @@ new_lookup(int slen, int maxlen, int tau) {
 
 void
 destroy_lookup(lookup_t* lut) {
+  if (lut == NULL)
+    return;
   for (int i = 0; i < lut->kmers; i++)
     free(lut->lut[i]);
   free(lut->klen);
@@ -2314,6 +3075,38 @@ lut_insert(lookup_t* lut, useq_t* query) {
   return 0;
 }
 
+lookup_t*
+starcode_new_lookup(const int slen, const int maxlen, const int tau)
+// SYNOPSIS:
+//   Public constructor of the k-mer filter of the queries (see
+//   'lut_search()'), for the sequences of median length 'slen'
+//   padded with leading spaces to 'maxlen'.
+//
+// RETURN:
+//   A pointer to the new lookup table, or NULL in case of failure.
+{
+  // No hooks: failures are reported on stderr.
+  starcode_params_t params = {.hooks = NULL};
+  return new_lookup(slen, maxlen, tau, &params);
+}
+
+int
+starcode_lookup_insert(lookup_t* lut, const char* seq) {
+  useq_t useq = {.seq = (char*)seq};
+  return lut_insert(lut, &useq);
+}
+
+int
+starcode_lookup_search(const lookup_t* lut, const char* seq) {
+  useq_t useq = {.seq = (char*)seq};
+  return lut_search((lookup_t*)lut, &useq);
+}
+
+void
+starcode_destroy_lookup(lookup_t* lut) {
+  destroy_lookup(lut);
+}
+
 int
 seq2id(char* seq, int slen) {
   int seqid = 0;
@@ -2342,16 +3135,18 @@ seq2id(char* seq, int slen) {
 useq_t*
 new_useq(int count, char* seq, char* info) {
   // Check input.
-  if (seq == NULL)
+  if (seq == NULL || injected(STARCODE_ALLOC_ERROR))
     return NULL;
 
   useq_t* new = calloc(1, sizeof(useq_t));
-  if (new == NULL) {
-    alert();
-    krash();
-  }
+  if (new == NULL)
+    return NULL;
   size_t slen = strlen(seq);
   new->seq = malloc(slen + 1);
+  if (new->seq == NULL) {
+    free(new);
+    return NULL;
+  }
   for (size_t i = 0; i < slen; i++)
     new->seq[i] = capitalize[(uint8_t)seq[i]];
   new->seq[slen] = 0;
@@ -2363,8 +3158,8 @@ new_useq(int count, char* seq, char* info) {
   if (info != NULL) {
     new->info = strdup(info);
     if (new->info == NULL) {
-      alert();
-      krash();
+      destroy_useq(new);
+      return NULL;
     }
   }
 
@@ -2382,6 +3177,22 @@ destroy_useq(useq_t* useq) {
   free(useq);
 }
 
+void
+destroy_useqs(gstack_t* useqS)
+// SYNOPSIS:
+//   Destroys a stack of sequences and the sequences. Repeated
+//   sequences are destroyed and set to NULL by 'seqsort()', they
+//   are skipped.
+{
+  if (useqS == NULL)
+    return;
+  for (size_t i = 0; i < useqS->nitems; i++) {
+    if (useqS->items[i] != NULL)
+      destroy_useq(useqS->items[i]);
+  }
+  destroy_gstack(useqS);
+}
+
 int
 canonical_order(const void* a, const void* b) {
   useq_t* u1 = *((useq_t**)a);
@@ -2468,15 +3279,15 @@ int_ascending(const void* a, const void* b) {
 
 idstack_t*
 idstack_new(size_t n_elm) {
+  if (injected(STARCODE_ALLOC_ERROR))
+    return NULL;
   idstack_t* stack = malloc(sizeof(idstack_t));
-  if (stack == NULL) {
-    alert();
-    krash();
-  }
+  if (stack == NULL)
+    return NULL;
   stack->elm = calloc(n_elm, sizeof(int));
   if (stack->elm == NULL) {
-    alert();
-    krash();
+    free(stack);
+    return NULL;
   }
   stack->pos = 0;
   stack->max = n_elm;
@@ -2484,24 +3295,24 @@ idstack_new(size_t n_elm) {
   return stack;
 }
 
-void
+int
 idstack_push(int* vals, size_t n_val, idstack_t* stack) {
   size_t newsize = stack->max;
   // Realloc buffer.
   while (stack->pos + n_val > newsize)
     newsize *= 2;
   if (newsize > stack->max) {
-    stack->elm = realloc(stack->elm, newsize * sizeof(int));
-    if (stack->elm == NULL) {
-      alert();
-      krash();
-    }
+    int* elm = realloc(stack->elm, newsize * sizeof(int));
+    if (elm == NULL)
+      return 1;
+    stack->elm = elm;
     stack->max = newsize;
   }
   // Copy values to stack.
   memcpy(stack->elm + stack->pos, vals, n_val * sizeof(int));
   // Update stack top position.
   stack->pos += n_val;
+  return 0;
 }
 
 void
@@ -2510,11 +3321,3 @@ idstack_free(idstack_t* stack) {
   free(stack);
 }
 
-void
-krash(void) {
-  fprintf(stderr,
-      "starcode has crashed, please contact guillaume.filion@gmail.com "
-      "for support with this issue.\n");
-  abort();
-}
-
@@ -30,14 +30,105 @@
 #define VERSION "starcode-v1.4"
 #define DATE "2021-09-22"
 #define STARCODE_MAX_TAU 8
+// The queries are split in 3 * threads (+ 1) tries and the jobs of every
+// pair of tries, ntries * (ntries + 1) / 2, are counted in an int.
+#define STARCODE_MAX_THREADS 15446
 
 struct useq_t;
 struct match_t;
+struct gstack_t;
 typedef struct starcode_params_t starcode_params_t;
+typedef struct starcode_hooks_t starcode_hooks_t;
+typedef struct starcode_context_t starcode_context_t;
 typedef struct useq_t useq_t;
 typedef struct match_t match_t;
 typedef struct lookup_t lookup_t;
 
+// The field 'seqid' is either an id number for
+// the unique sequence or a pointer to a struct
+// containing information about the matches. This
+// creates some confusion in the code at times.
+// See function 'transfer_useq_ids()'.
+struct useq_t {
+  ssize_t count;       // Number of sequences
+  unsigned int nids;   // Number of associated sequence IDs
+  ssize_t sphere_c;    // Centroid: size of the sphere.
+  ssize_t sphere_d;    // Distance to current sphere centroid. / MP:
+                       // Ambiguous flag.
+  char* seq;           // Sequence
+  char* info;          // Multi-function text field
+  struct gstack_t** matches;  // Matches stratified by distance
+  useq_t* canonical;   // Pointer to canonical sequence
+  int* seqid;          // Unique ID / pointer (see above).
+};
+
+typedef enum {
+  FASTA,
+  FASTQ,
+  RAW,
+  PE_FASTQ,
+  UNSET,
+} format_t;
+
+// Stages of a run, in order.
+typedef enum {
+  STAGE_READING,
+  STAGE_SORTING,
+  STAGE_PADDING,
+  STAGE_QUERYING,
+  STAGE_CLUSTERING,
+  STAGE_OUTPUT,
+} stage_t;
+
+// Events passed to the 'progress' hook.
+typedef enum {
+  PROGRESS_STARTED,    // 'stage' started
+  PROGRESS_FINISHED,   // 'stage' finished
+  PROGRESS_UNIQUE,     // 'count' unique sequences out of 'total'
+  PROGRESS_JOBS,       // 'count' query jobs done out of 'total'
+} progress_t;
+
+// Levels of the messages passed to the 'log' hook.
+typedef enum {
+  LOG_ERROR,
+  LOG_WARNING,
+  LOG_INFO,            // Only in verbose mode
+  LOG_DEBUG,           // Only in verbose mode
+} log_level_t;
+
+// Callbacks for library callers. When 'member' is set, the
+// clusters are passed to it instead of being printed: it is
+// called once for every sequence of every cluster, in output
+// order, together with the canonical of the cluster. When
+// 'format' is set, it is called with the format of the input
+// once it has been read. When 'cancelled' is set, it is polled
+// between jobs and the run stops with STARCODE_CANCELLED when
+// it returns non-zero. It is called from the query threads, so
+// it must be thread-safe. When 'progress' is set, it is called
+// from the calling thread as the run goes ('count' and 'total'
+// are 0 unless the event says otherwise). When 'log' is set, the
+// messages of the run are passed to it, one per call and without
+// the trailing newline, instead of being written to stderr. It is
+// called from the query threads as well. When 'edge' is set, it is
+// called once for every pair of sequences within 'tau' of each other
+// as the query finds them, with their distance, whatever the
+// clustering algorithm and the cluster ratio. The sequences are
+// still padded with leading spaces. It is called from the query
+// threads, so it must be thread-safe. If 'member' is not set, the
+// run stops after the query and nothing is clustered or printed.
+struct starcode_hooks_t {
+  void* data;          // Passed back to the callbacks
+  void (*member)(void* data, const useq_t* canonical, const useq_t* member);
+  void (*format)(void* data, format_t format);
+  int (*cancelled)(void* data);
+  void (*progress)(void* data, progress_t event, stage_t stage, long count,
+      long total);
+  void (*log)(void* data, log_level_t level, const char* message);
+  void (*edge)(void* data, const useq_t* query, const useq_t* match,
+      int dist);
+  starcode_context_t* context;  // Owner of the allocations (may be NULL)
+};
+
 
 typedef enum {
    DEFAULT_OUTPUT,
@@ -52,6 +143,20 @@ typedef enum {
    COMPONENTS_CLUSTER
 } cluster_t;
 
+// Return codes of 'starcode()', 'starcode_seqs()' and
+// 'starcode_helper()'.
+typedef enum {
+   STARCODE_OK,
+   STARCODE_EMPTY_INPUT,
+   STARCODE_IO_ERROR,        // Could not open or read a file
+   STARCODE_ALLOC_ERROR,     // Memory allocation failed
+   STARCODE_THREAD_ERROR,    // Could not start a thread
+   STARCODE_INVALID_INPUT,   // Non DNA characters, broken file format
+   STARCODE_SEQ_TOO_LONG,    // Sequence longer than MAXBRCDLEN
+   STARCODE_INTERNAL_ERROR,  // Inconsistent state (this is a bug)
+   STARCODE_CANCELLED        // Stopped by the 'cancelled' hook
+} starcode_status_t;
+
 int starcode_helper(
     char* input,           // First input file
     char* output,          // First output file
@@ -80,6 +185,76 @@ int starcode(
    const int outputt
 );
 
+int starcode_files(
+   FILE *inputf1,
+   FILE *inputf2,
+   FILE *outputf1,
+   FILE *outputf2,
+   starcode_hooks_t *hooks,
+         int tau,
+   const int verbose,
+         int thrmax,
+   const int clusteralg,
+         double parent_to_child,
+   const int showclusters,
+   const int showids,
+   const int outputt
+);
+
+int starcode_seqs(
+   char** seqs,
+   const long* counts,
+   const size_t nseq,
+   FILE *outputf1,
+   starcode_hooks_t *hooks,
+         int tau,
+   const int verbose,
+         int thrmax,
+   const int clusteralg,
+         double parent_to_child,
+   const int showclusters,
+   const int showids,
+   const int outputt
+);
+
+int starcode_pe_seqs(
+   char** seqs1,
+   char** seqs2,
+   const long* counts,
+   const size_t nseq,
+   FILE *outputf1,
+   starcode_hooks_t *hooks,
+         int tau,
+   const int verbose,
+         int thrmax,
+   const int clusteralg,
+         double parent_to_child,
+   const int showclusters,
+   const int showids,
+   const int outputt
+);
+
+// Owner of the allocations of the runs whose hooks point to it: the
+// parameters, the sequences and the tries and lookups of the last run
+// stay in the context until the next run, 'starcode_context_clear()'
+// or 'starcode_context_destroy()'. Without a context, every run frees
+// them before it returns.
+starcode_context_t* starcode_context_new(void);
+void starcode_context_clear(starcode_context_t* context);
+void starcode_context_destroy(starcode_context_t* context);
+
+// K-mer filter of the sequences within 'tau' of a set, used to skip
+// the queries that cannot have a match. The sequences must be padded
+// with leading spaces to 'maxlen', 'slen' is their median length.
+// The search returns 1 if the query may have a match, 0 if not, and
+// -1 if it is shorter than 'maxlen'.
+lookup_t* starcode_new_lookup(const int slen, const int maxlen,
+                              const int tau);
+int starcode_lookup_insert(lookup_t* lut, const char* seq);
+int starcode_lookup_search(const lookup_t* lut, const char* seq);
+void starcode_destroy_lookup(lookup_t* lut);
+
+
 
 
 #endif
@@ -104,15 +104,16 @@ void     poucet (node_t*, int, struct arg_t);
 int      recursive_count_nodes (node_t * node, int, int);
 
 // Globals.
-int ERROR = 0;
-_Thread_local gstack_t * const TOWER_TOP;
-
-// Initialize the thread-local TOWER_TOP variable
-// This should be called when a new thread starts
-void init_thread_local_tower_top(void) {
-  // Cast away const to initialize thread-local copy
-  *((gstack_t **)&TOWER_TOP) = NULL;
-}
+// 'ERROR' is thread-local because the query threads search
+// different tries at the same time.
+_Thread_local int ERROR = 0;
+// Towers are created in the query threads and read in the
+// main thread, so the sentinel must be the same everywhere.
+gstack_t * const TOWER_TOP = NULL;
+
+// Nothing to initialize since 'TOWER_TOP' is shared by all
+// threads. Kept for the code that still calls it.
+void init_thread_local_tower_top(void) {}
 
 int get_height(trie_t *trie) { return trie->info->height; }
 
@@ -177,8 +178,9 @@ search
    }
 
    // Translate the query string. The first 'char' is kept to store
-   // the length of the query, which shifts the array by 1 position.
-   int translated[M];
+   // the length of the query, which shifts the array by 1 position,
+   // and 'EOS' follows the last character.
+   int translated[M+1];
    translated[0] = length;
    translated[length+1] = EOS;
    for (int i = max(0, start_depth-TAU) ; i < length ; i++) {
@@ -494,13 +496,12 @@ new_trienode
 {
 
    node_t *node = calloc(1, sizeof(node_t));
-   memset(node->child, 0, sizeof(node->child));
-
    if (node == NULL) {
       fprintf(stderr, "error: could not create trie node\n");
       ERROR = __LINE__;
       return NULL;
    }
+   memset(node->child, 0, sizeof(node->child));
 
    // Initialize the cache. This is important for the
    // dynamic programming algorithm.
@@ -646,6 +647,7 @@ insert_string_wo_malloc
    for ( ; i < nchar-1 ; i++) {
       int c = translate[(int) string[i]];
       node = insert_wo_malloc(node, c, *from_addr);
+      if (node == NULL) return NULL;
       (*from_addr)++; 
    }
 
@@ -687,6 +689,7 @@ insert_wo_malloc
 
    if (parent->child[position] != NULL) return NULL;
     node_t *newnode = new_trienode();
+   if (newnode == NULL) return NULL;
    // Initialize child data.
    memset(newnode->child, 0, 6 * sizeof(void *));
    newnode->path = (parent->path << 4) + position;
@@ -796,15 +799,9 @@ destroy_from
       }
       //fprintf(stderr, "destroy_from done\n");
 
-      if (free_nodes && node) {
-            //fprintf(stderr, "aaaa %p\n", (void *) &node);
-
-         //free(node);
-                     //fprintf(stderr, "gggg \n");
-
-         //node = NULL;
-
-      }
+      // Nodes above 'maxdepth' belong to the trie, the data
+      // below them (at 'maxdepth') does not.
+      if (free_nodes) free(node);
       //fprintf(stderr, "ss: 100.00%%\n");
 
    }
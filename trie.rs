@@ -0,0 +1,165 @@
+use std::ffi::CString;
+use std::marker::PhantomData;
+
+use libc::{c_int, c_void};
+
+use crate::validation::check_sequence;
+use crate::{
+    check_trie_error_and_reset, destroy_tower, destroy_trie, gstack_t, insert_string, new_tower,
+    new_trie, trie_t, StarcodeError, DESTROY_NODES_YES, MAXBRCDLEN, TAU,
+};
+
+/// A trie of DNA sequences up to `height` long, each with a payload of type `P`, searched
+/// with the "poucet" algorithm of starcode.
+///
+/// Sequences shorter than the height are padded with leading spaces, as starcode does, so
+/// the distances are the Levenshtein distances of the unpadded sequences. N never matches.
+pub struct Trie<P> {
+    trie: *mut trie_t,
+    height: usize,
+    len: usize,
+    // the leaves hold boxed entries, dropped with the trie
+    entries: PhantomData<Box<Entry<P>>>,
+}
+
+// What a leaf of the trie points to
+struct Entry<P> {
+    key: Vec<u8>,
+    payload: P,
+}
+
+/// A sequence of the trie within the searched distance of the query
+#[derive(Debug, PartialEq)]
+pub struct Hit<'a, P> {
+    pub distance: usize,
+    // as first inserted (keys that only differ by case share the entry)
+    pub key: &'a [u8],
+    pub payload: &'a P,
+}
+
+// Nothing in the trie is tied to the thread that built it
+unsafe impl<P: Send> Send for Trie<P> {}
+
+impl<P> Trie<P> {
+    pub fn new(height: usize) -> Result<Self, StarcodeError> {
+        if height == 0 || height > MAXBRCDLEN as usize {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "trie height must be between 1 and {}", MAXBRCDLEN)));
+        }
+        let trie = unsafe { new_trie(height as u32) };
+        if trie.is_null() {
+            unsafe { check_trie_error_and_reset() };
+            return Err(StarcodeError::OutOfMemory);
+        }
+        Ok(Trie { trie, height, len: 0, entries: PhantomData })
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Number of sequences in the trie
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the payload of `key`, inserting `key` with a default payload if needed
+    pub fn insert(&mut self, key: &[u8]) -> Result<&mut P, StarcodeError>
+    where
+        P: Default,
+    {
+        let padded = self.pad(key, "key")?;
+        unsafe {
+            let leaf = insert_string(self.trie, padded.as_ptr());
+            if leaf.is_null() {
+                check_trie_error_and_reset();
+                return Err(StarcodeError::OutOfMemory);
+            }
+            if (*leaf).is_null() {
+                let entry = Box::new(Entry { key: key.to_vec(), payload: P::default() });
+                *leaf = Box::into_raw(entry) as *mut c_void;
+                self.len += 1;
+            }
+            Ok(&mut (*(*leaf as *mut Entry<P>)).payload)
+        }
+    }
+
+    /// Returns the sequences of the trie within `tau` of `query`, by increasing distance
+    pub fn search(&mut self, query: &[u8], tau: usize) -> Result<Vec<Hit<'_, P>>, StarcodeError> {
+        if tau > TAU as usize {
+            return Err(StarcodeError::InvalidParameter(format!("tau must be at most {}", TAU)));
+        }
+        let padded = self.pad(query, "query")?;
+        unsafe {
+            let tower = Tower::new(tau)?;
+            // The search starts from the root. 'dash()' stops at padding, so the search must
+            // seed pebbles (and not dash) until the end of the padding of the query.
+            let padding = self.height - query.len();
+            let error = crate::search(self.trie, padded.as_ptr(), tau as c_int, tower.0, 0, padding as c_int);
+            if error != 0 {
+                check_trie_error_and_reset();
+                return Err(StarcodeError::OutOfMemory);
+            }
+            let mut hits = Vec::new();
+            for distance in 0..=tau {
+                let stack: &gstack_t = &**tower.0.add(distance);
+                // A stack with more items than slots could not take them all
+                if stack.nitems > stack.nslots {
+                    return Err(StarcodeError::OutOfMemory);
+                }
+                for &item in stack.items.as_slice(stack.nitems) {
+                    let entry = &*(item as *const Entry<P>);
+                    hits.push(Hit { distance, key: &entry.key, payload: &entry.payload });
+                }
+            }
+            Ok(hits)
+        }
+    }
+
+    // The sequence padded with leading spaces to the height of the trie
+    fn pad(&self, sequence: &[u8], what: &str) -> Result<CString, StarcodeError> {
+        check_sequence(sequence).map_err(StarcodeError::InvalidSequence)?;
+        if sequence.len() > self.height {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "{} of length {} is longer than the trie height {}", what, sequence.len(), self.height)));
+        }
+        let mut padded = vec![b' '; self.height - sequence.len()];
+        padded.extend_from_slice(sequence);
+        // checked above: DNA only
+        Ok(CString::new(padded).unwrap())
+    }
+}
+
+impl<P> Drop for Trie<P> {
+    fn drop(&mut self) {
+        unsafe { destroy_trie(self.trie, DESTROY_NODES_YES as c_int, Some(drop_entry::<P>)) }
+    }
+}
+
+unsafe extern "C" fn drop_entry<P>(entry: *mut c_void) {
+    drop(Box::from_raw(entry as *mut Entry<P>));
+}
+
+// The hit stacks of a search, one per distance
+struct Tower(*mut *mut gstack_t);
+
+impl Tower {
+    unsafe fn new(tau: usize) -> Result<Tower, StarcodeError> {
+        let tower = new_tower(tau as c_int + 1);
+        if tower.is_null() {
+            check_trie_error_and_reset();
+            return Err(StarcodeError::OutOfMemory);
+        }
+        Ok(Tower(tower))
+    }
+}
+
+impl Drop for Tower {
+    fn drop(&mut self) {
+        unsafe { destroy_tower(self.0) }
+    }
+}
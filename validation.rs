@@ -0,0 +1,79 @@
+use std::fmt;
+
+use crate::{MAXBRCDLEN, STARCODE_MAX_TAU};
+
+/// Why an input entry cannot be passed to starcode
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryError {
+    Empty,
+    // starcode only takes A, C, G, T and N (in either case)
+    InvalidCharacter { position: usize, character: u8 },
+    // longer than MAXBRCDLEN, for pairs once the mates are joined
+    TooLong { length: usize },
+    ZeroCount,
+    CountTooLarge { count: u64 },
+}
+
+impl fmt::Display for EntryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EntryError::Empty => write!(f, "empty sequence"),
+            EntryError::InvalidCharacter { position, character } => write!(
+                f, "invalid character {:?} at position {}", char::from(*character), position),
+            EntryError::TooLong { length } => write!(
+                f, "sequence of length {} is longer than {}", length, MAXBRCDLEN),
+            EntryError::ZeroCount => write!(f, "count is zero"),
+            EntryError::CountTooLarge { count } => write!(f, "count {} is too large", count),
+        }
+    }
+}
+
+// Same as 'valid_DNA_char' in starcode.c
+fn is_valid_dna(character: u8) -> bool {
+    matches!(character, b'A' | b'C' | b'G' | b'T' | b'N' | b'a' | b'c' | b'g' | b't' | b'n')
+}
+
+// Checks that starcode can take 'sequence' as it is
+pub(crate) fn check_sequence(sequence: &[u8]) -> Result<(), EntryError> {
+    if sequence.is_empty() {
+        return Err(EntryError::Empty);
+    }
+    if sequence.len() > MAXBRCDLEN as usize {
+        return Err(EntryError::TooLong { length: sequence.len() });
+    }
+    match sequence.iter().position(|c| !is_valid_dna(*c)) {
+        Some(position) => Err(EntryError::InvalidCharacter { position, character: sequence[position] }),
+        None => Ok(()),
+    }
+}
+
+// Checks that starcode can take the mates of a pair, which it joins with STARCODE_MAX_TAU + 1
+// dashes in between, the joined pair must fit in MAXBRCDLEN as well
+pub(crate) fn check_pair(first: &[u8], second: &[u8]) -> Result<(), EntryError> {
+    check_sequence(first)?;
+    check_sequence(second)?;
+    let length = first.len() + STARCODE_MAX_TAU as usize + 1 + second.len();
+    if length > MAXBRCDLEN as usize {
+        return Err(EntryError::TooLong { length });
+    }
+    Ok(())
+}
+
+// Checks that a count fits the 'count' of starcode (a signed long)
+pub(crate) fn check_count(count: u64) -> Result<(), EntryError> {
+    if count == 0 {
+        return Err(EntryError::ZeroCount);
+    }
+    if count > libc::c_long::MAX as u64 {
+        return Err(EntryError::CountTooLarge { count });
+    }
+    Ok(())
+}
+
+// Uppercases 'sequence' and replaces U with T (RNA input)
+pub(crate) fn normalize(sequence: &[u8]) -> Vec<u8> {
+    sequence.iter().map(|c| match c.to_ascii_uppercase() {
+        b'U' => b'T',
+        upper => upper,
+    }).collect()
+}
@@ -0,0 +1,148 @@
+use rustc_hash::FxHashMap;
+
+use crate::validation::check_sequence;
+use crate::{BarcodeIndex, BarcodeMatch, StarcodeError};
+
+/// A list of valid barcodes that reads are corrected to, such as a single-cell whitelist.
+/// The barcodes are the only centers: every read goes to its nearest barcode within tau, or
+/// to none. Barcodes that only differ by case are the same, the first of them is reported.
+pub struct Whitelist {
+    index: BarcodeIndex,
+}
+
+/// Where `Whitelist::correct` puts a read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarcodeAssignment {
+    // position of the barcode in the whitelist, the distance is 0 for exact matches
+    Assigned { barcode: usize, distance: usize },
+    // several barcodes are nearest and none has more exact matches than the others
+    Ambiguous,
+    // no barcode within tau
+    Unassigned,
+}
+
+/// The reads of `Whitelist::correct`, assigned to the barcodes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WhitelistCorrection {
+    // one per read, in the order of the reads
+    pub assignments: Vec<BarcodeAssignment>,
+    // reads assigned to each barcode, by position in the whitelist
+    pub counts: Vec<usize>,
+    pub exact: usize,
+    pub corrected: usize,
+    pub ambiguous: usize,
+    pub unassigned: usize,
+}
+
+impl Whitelist {
+    /// Indexes `barcodes` to correct reads up to `max_tau` away from them
+    pub fn new<I>(barcodes: I, max_tau: usize) -> Result<Self, StarcodeError>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        Ok(Whitelist { index: BarcodeIndex::new(barcodes, max_tau)? })
+    }
+
+    pub fn max_tau(&self) -> usize {
+        self.index.max_tau()
+    }
+
+    /// Number of barcodes, repeats included
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// The barcode at `index`, as given to `new`
+    pub fn barcode(&self, index: usize) -> &[u8] {
+        self.index.sequence(index)
+    }
+
+    /// Assigns every read to the barcode nearest to it within `tau`. The ties are broken by
+    /// abundance: the barcode that the most reads match exactly gets the read, and the read
+    /// is ambiguous if no barcode has more exact matches than all the others.
+    pub fn correct<S>(&self, reads: &[S], tau: usize) -> Result<WhitelistCorrection, StarcodeError>
+    where
+        S: AsRef<[u8]> + Sync,
+    {
+        if tau > self.max_tau() {
+            return Err(StarcodeError::InvalidParameter(format!(
+                "tau {} is larger than the tau of the whitelist {}", tau, self.max_tau())));
+        }
+        // Each different read is searched once
+        let mut unique: Vec<&[u8]> = Vec::new();
+        let mut read_to_unique: Vec<usize> = Vec::with_capacity(reads.len());
+        let mut positions: FxHashMap<&[u8], usize> = FxHashMap::default();
+        for (index, read) in reads.iter().enumerate() {
+            let read = read.as_ref();
+            check_sequence(read).map_err(|error| StarcodeError::InvalidEntry { index, error })?;
+            let position = *positions.entry(read).or_insert_with(|| {
+                unique.push(read);
+                unique.len() - 1
+            });
+            read_to_unique.push(position);
+        }
+        let nearest: Vec<Vec<BarcodeMatch>> = self.index.query_batch(&unique, tau)?
+            .into_iter()
+            .map(|matches| self.distinct(matches))
+            .collect();
+
+        // The abundance of each barcode is the number of reads that match it exactly
+        let mut abundance = vec![0; self.len()];
+        for &u in &read_to_unique {
+            if let [BarcodeMatch { index, distance: 0 }] = nearest[u][..] {
+                abundance[index] += 1;
+            }
+        }
+        let assignments: Vec<BarcodeAssignment> = nearest.iter().map(|matches| {
+            let best = matches.iter().map(|m| abundance[m.index]).max().unwrap_or(0);
+            let mut best_matches = matches.iter().filter(|m| abundance[m.index] == best);
+            match (best_matches.next(), best_matches.next()) {
+                (None, _) => BarcodeAssignment::Unassigned,
+                (Some(m), None) => BarcodeAssignment::Assigned { barcode: m.index, distance: m.distance },
+                (Some(_), Some(_)) => BarcodeAssignment::Ambiguous,
+            }
+        }).collect();
+
+        let mut correction = WhitelistCorrection {
+            assignments: Vec::with_capacity(reads.len()),
+            counts: vec![0; self.len()],
+            exact: 0,
+            corrected: 0,
+            ambiguous: 0,
+            unassigned: 0,
+        };
+        for u in read_to_unique {
+            let assignment = assignments[u];
+            match assignment {
+                BarcodeAssignment::Assigned { barcode, distance } => {
+                    correction.counts[barcode] += 1;
+                    match distance {
+                        0 => correction.exact += 1,
+                        _ => correction.corrected += 1,
+                    }
+                }
+                BarcodeAssignment::Ambiguous => correction.ambiguous += 1,
+                BarcodeAssignment::Unassigned => correction.unassigned += 1,
+            }
+            correction.assignments.push(assignment);
+        }
+        Ok(correction)
+    }
+
+    // The nearest barcodes without their repeats, the first of each is kept
+    fn distinct(&self, matches: Vec<BarcodeMatch>) -> Vec<BarcodeMatch> {
+        let mut distinct: Vec<BarcodeMatch> = Vec::with_capacity(matches.len());
+        for m in matches {
+            let sequence = self.barcode(m.index);
+            if !distinct.iter().any(|kept| self.barcode(kept.index).eq_ignore_ascii_case(sequence)) {
+                distinct.push(m);
+            }
+        }
+        distinct
+    }
+}